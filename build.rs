@@ -1,15 +1,37 @@
-fn main() {
-    //Operating system check
-    let uname_output = std::process::Command::new("uname")
-        .arg("-s")
+// first line of a command's stdout, or "unknown" if it could not be run
+fn command_output(program: &str, args: &[&str]) -> String {
+    std::process::Command::new(program)
+        .args(args)
         .output()
-        .expect("Failed to execute uname");
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| line.trim().to_string())
+        })
+        .filter(|line| !line.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
 
-    let os_name_lossy = String::from_utf8_lossy(&uname_output.stdout);
-    let os_name = os_name_lossy.trim();
+fn main() {
+    // Build provenance reported by `--version --json`
+    let git_commit = command_output("git", &["rev-parse", "--short=12", "HEAD"]);
+    let build_date = command_output("date", &["-u", "+%Y-%m-%dT%H:%M:%SZ"]);
+    println!("cargo:rustc-env=SSHP4RU_GIT_COMMIT={}", git_commit);
+    println!("cargo:rustc-env=SSHP4RU_BUILD_DATE={}", build_date);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
 
-    // Kqueue if OS is Darwin or FreeBSD
-    if os_name == "Darwin" || os_name == "FreeBSD" {
-        println!("cargo:rustc-cfg=feature=\"use_kqueue\"");
+    // the event interface Fdwatcher is built with, by the target rather than the host so
+    // cross-compiled binaries get the right one; other unix targets use poll(2)
+    println!("cargo:rustc-check-cfg=cfg(epoll, kqueue)");
+    match std::env::var("CARGO_CFG_TARGET_OS").as_deref() {
+        Ok("linux" | "android") => println!("cargo:rustc-cfg=epoll"),
+        Ok("macos" | "ios" | "freebsd" | "netbsd" | "openbsd" | "dragonfly") => {
+            println!("cargo:rustc-cfg=kqueue")
+        }
+        _ => {}
     }
 }
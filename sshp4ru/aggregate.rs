@@ -0,0 +1,154 @@
+use crate::theme::Theme;
+use crate::utils::Colorize;
+use crate::{Host, PROG_NAME};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// how far outside the interquartile range a value has to be to count as an outlier
+const OUTLIER_IQR_FACTOR: f64 = 1.5;
+// with fewer values the quartiles say nothing
+const MIN_VALUES_FOR_OUTLIERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl Aggregate {
+    // <sum|min|max|avg>[,...]
+    pub fn from_spec(spec: &str) -> Result<Vec<Aggregate>, String> {
+        spec.split(',')
+            .map(|name| match name.trim() {
+                "sum" => Ok(Aggregate::Sum),
+                "min" => Ok(Aggregate::Min),
+                "max" => Ok(Aggregate::Max),
+                "avg" => Ok(Aggregate::Avg),
+                other => Err(format!(
+                    "unknown aggregate `{}` (expected sum, min, max or avg)",
+                    other
+                )),
+            })
+            .collect()
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Aggregate::Sum => "sum",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Avg => "avg",
+        }
+    }
+
+    // `values` is sorted and not empty
+    fn compute<'a>(&self, values: &'a [(f64, String)]) -> (f64, Option<&'a str>) {
+        match self {
+            Aggregate::Sum => (values.iter().map(|(value, _)| value).sum(), None),
+            Aggregate::Min => (values[0].0, Some(&values[0].1)),
+            Aggregate::Max => {
+                let (value, host) = &values[values.len() - 1];
+                (*value, Some(host.as_str()))
+            }
+            Aggregate::Avg => {
+                let sum: f64 = values.iter().map(|(value, _)| value).sum();
+                (sum / values.len() as f64, None)
+            }
+        }
+    }
+}
+
+// whole numbers without a fraction, the rest with at most two decimals
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        let formatted = format!("{:.2}", value);
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    }
+}
+
+fn quartile(sorted: &[(f64, String)], q: f64) -> f64 {
+    let position = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+    sorted[lower].0 + (sorted[upper].0 - sorted[lower].0) * (position - lower as f64)
+}
+
+// every host is expected to print a single number, the collected join output is parsed
+pub fn print_aggregates(hosts: &[Rc<RefCell<Host>>], aggregates: &[Aggregate], theme: &Theme) {
+    let (cyan, magenta) = (&theme.hostname, &theme.meta);
+    let mut values: Vec<(f64, String)> = Vec::new();
+    let mut not_numbers: Vec<String> = Vec::new();
+    for host in hosts.iter() {
+        let host = host.borrow();
+        match host.cp.output_buffer.trim().parse::<f64>() {
+            Ok(value) if value.is_finite() => values.push((value, host.name.to_string())),
+            _ => not_numbers.push(host.name.to_string()),
+        }
+    }
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    if values.is_empty() {
+        outln!(
+            "[{}] nothing to aggregate, no host printed a number",
+            PROG_NAME.colorize(cyan)
+        );
+    }
+    for aggregate in aggregates.iter() {
+        if values.is_empty() {
+            break;
+        }
+        let (value, host) = aggregate.compute(&values);
+        out!(
+            "[{}] {} {} over {} host{}",
+            PROG_NAME.colorize(cyan),
+            aggregate.name(),
+            format_number(value).as_str().colorize(magenta),
+            values.len(),
+            if values.len() == 1 { "" } else { "s" }
+        );
+        match host {
+            Some(host) => outln!(" ({})", host.colorize(cyan)),
+            None => outln!(),
+        }
+    }
+
+    if values.len() >= MIN_VALUES_FOR_OUTLIERS {
+        let (q1, q3) = (quartile(&values, 0.25), quartile(&values, 0.75));
+        let margin = (q3 - q1) * OUTLIER_IQR_FACTOR;
+        let outliers: Vec<String> = values
+            .iter()
+            .filter(|(value, _)| *value < q1 - margin || *value > q3 + margin)
+            .map(|(value, host)| {
+                format!(
+                    "{} ({})",
+                    host.as_str().colorize(cyan),
+                    format_number(*value)
+                )
+            })
+            .collect();
+        if !outliers.is_empty() {
+            outln!(
+                "[{}] outliers: {}",
+                PROG_NAME.colorize(cyan),
+                outliers.join(", ")
+            );
+        }
+    }
+    if !not_numbers.is_empty() {
+        outln!(
+            "[{}] not a number: {}",
+            PROG_NAME.colorize(cyan),
+            not_numbers
+                .iter()
+                .map(|host| host.as_str().colorize(cyan))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use crate::redact::Redactor;
+use crate::utils::{command_line, rfc3339_utc};
+use crate::Host;
+use crate::{PROG_NAME, PROG_VERSION};
+use serde_json::json;
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+// fixed seed so that the same plan always yields the same hash across runs and machines
+const AUDIT_HASH_SEED: u64 = 0;
+
+// (hostname, argv) pairs in the order they will be spawned
+pub type Plan = Vec<(String, Vec<String>)>;
+
+pub fn plan_hash(plan: &Plan) -> String {
+    let mut buffer = String::new();
+    for (host, command) in plan.iter() {
+        buffer.push_str(host);
+        buffer.push('\0');
+        buffer.push_str(&command_line(command));
+        buffer.push('\n');
+    }
+
+    format!(
+        "{:016x}",
+        twox_hash::XxHash64::oneshot(AUDIT_HASH_SEED, buffer.as_bytes())
+    )
+}
+
+fn results_digest(hosts: &[Rc<RefCell<Host>>]) -> String {
+    let mut buffer = String::new();
+    for host in hosts.iter() {
+        let host = host.borrow();
+        buffer.push_str(&format!("{}\0{}\n", host.name, host.cp.exit_code));
+    }
+
+    format!(
+        "{:016x}",
+        twox_hash::XxHash64::oneshot(AUDIT_HASH_SEED, buffer.as_bytes())
+    )
+}
+
+fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| format!("uid:{}", nix::unistd::getuid()))
+}
+
+// records are only ever appended (O_APPEND), one JSON object per line
+pub fn append_record(
+    path: &str, plan: &Plan, hosts: &[Rc<RefCell<Host>>], redactor: &Redactor, run_id: &str,
+) -> io::Result<()> {
+    let entries: Vec<serde_json::Value> = plan
+        .iter()
+        .zip(hosts.iter())
+        .map(|((name, command), host)| {
+            json!({
+                "host": name,
+                "argv": command
+                    .iter()
+                    .map(|arg| redactor.redact(arg))
+                    .collect::<Vec<_>>(),
+                "exit_code": host.borrow().cp.exit_code,
+            })
+        })
+        .collect();
+
+    let record = json!({
+        "timestamp": rfc3339_utc(SystemTime::now()),
+        "program": format!("{} {}", PROG_NAME, PROG_VERSION),
+        "user": current_user(),
+        "pid": std::process::id(),
+        "run_id": run_id,
+        "plan_hash": plan_hash(plan),
+        "hosts": entries,
+        "results_digest": results_digest(hosts),
+    });
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", record)?;
+    file.sync_all()
+}
@@ -0,0 +1,120 @@
+use std::borrow::Cow;
+
+// which part of a host's output is kept once it is over the limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Retention {
+    Head,
+    Tail,
+    // half of the limit each, what is in between is dropped
+    HeadTail,
+}
+
+// --per-host-buffer-limit <bytes>[,head|tail|head+tail], bounds what the join and
+// --buffered buffers keep of every host
+#[derive(Debug, Clone, Copy)]
+pub struct BufferLimit {
+    bytes: usize,
+    retention: Retention,
+}
+
+impl std::fmt::Display for BufferLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let retention = match self.retention {
+            Retention::Head => "head",
+            Retention::Tail => "tail",
+            Retention::HeadTail => "head+tail",
+        };
+        write!(f, "{},{}", self.bytes, retention)
+    }
+}
+
+// where and how much was dropped from a buffer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dropped {
+    pub at: usize,
+    pub bytes: u64,
+}
+
+impl BufferLimit {
+    pub fn from_spec(spec: &str) -> Result<BufferLimit, String> {
+        let (bytes, retention) = spec.split_once(',').unwrap_or((spec, "head"));
+        let bytes = match bytes.parse::<usize>() {
+            Ok(bytes) if bytes > 0 => bytes,
+            _ => return Err(format!("`{}` is not a size in bytes > 0", bytes)),
+        };
+        let retention = match retention {
+            "head" => Retention::Head,
+            "tail" => Retention::Tail,
+            "head+tail" => Retention::HeadTail,
+            _ => {
+                return Err(format!(
+                    "unknown policy `{}` (expected head, tail or head+tail)",
+                    retention
+                ))
+            }
+        };
+        Ok(BufferLimit { bytes, retention })
+    }
+
+    // appends `data`, then drops what the retention does not keep; cuts never split a
+    // character
+    pub fn append(&self, buffer: &mut String, data: &str, dropped: &mut Dropped) {
+        if self.retention == Retention::Head {
+            let room = floor_boundary(data, self.bytes.saturating_sub(buffer.len()));
+            buffer.push_str(&data[..room]);
+            if room < data.len() {
+                dropped.at = buffer.len();
+                dropped.bytes += (data.len() - room) as u64;
+            }
+            return;
+        }
+        buffer.push_str(data);
+        if buffer.len() <= self.bytes {
+            return;
+        }
+        let start = match self.retention {
+            Retention::HeadTail => floor_boundary(buffer, self.bytes / 2),
+            _ => 0,
+        };
+        let end = ceil_boundary(buffer, start + buffer.len() - self.bytes);
+        buffer.drain(start..end);
+        dropped.at = start;
+        dropped.bytes += (end - start) as u64;
+    }
+}
+
+// `buffer` as it is printed, a line where output was dropped
+pub fn with_marker(buffer: &str, dropped: Dropped) -> Cow<'_, str> {
+    if dropped.bytes == 0 {
+        return Cow::Borrowed(buffer);
+    }
+    // redaction after the cut may have moved it a little, it only has to be a boundary
+    let (head, tail) = buffer.split_at(floor_boundary(buffer, dropped.at));
+    // the rest of a line cut in its middle, the marker line stands in for it
+    let tail = tail.strip_prefix('\n').unwrap_or(tail);
+    let newline = if head.is_empty() || head.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    Cow::Owned(format!(
+        "{}{}[... {} bytes dropped ...]\n{}",
+        head, newline, dropped.bytes, tail
+    ))
+}
+
+fn floor_boundary(s: &str, mut index: usize) -> usize {
+    index = index.min(s.len());
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_boundary(s: &str, mut index: usize) -> usize {
+    index = index.min(s.len());
+    while !s.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
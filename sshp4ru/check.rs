@@ -0,0 +1,45 @@
+use crate::Host;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+// plugin return codes as defined by the Nagios plugin API (also used by Icinga)
+const CHECK_OK: u8 = 0;
+const CHECK_WARNING: u8 = 1;
+const CHECK_CRITICAL: u8 = 2;
+
+pub fn print_check_status(
+    hosts: &[Rc<RefCell<Host>>], warning: u32, critical: u32, elapsed: Duration,
+) -> u8 {
+    let total = hosts.len();
+    let failed = hosts
+        .iter()
+        .filter(|host| host.borrow().cp.exit_code != 0)
+        .count();
+    let succeeded = total - failed;
+
+    let (status, code) = if failed >= critical as usize {
+        ("CRITICAL", CHECK_CRITICAL)
+    } else if failed >= warning as usize {
+        ("WARNING", CHECK_WARNING)
+    } else {
+        ("OK", CHECK_OK)
+    };
+
+    // perfdata: 'label'=value[UOM];[warn];[crit];[min];[max]
+    outln!(
+        "SSHP4RU {} - {}/{} hosts succeeded | ok={};;;0;{} failed={};{};{};0;{} duration={}ms",
+        status,
+        succeeded,
+        total,
+        succeeded,
+        total,
+        failed,
+        warning,
+        critical,
+        total,
+        elapsed.as_millis()
+    );
+
+    code
+}
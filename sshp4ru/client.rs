@@ -0,0 +1,113 @@
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+// one line of what the daemon sends back, as `sshp4ru serve --socket` writes it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Accepted { job: u64, hosts: usize },
+    // stdout and stderr of the job, host prefixes included
+    Output { job: u64, line: String },
+    Exit { job: u64, code: i32 },
+    // the request was rejected, no job was started
+    Error { message: String },
+}
+
+impl Event {
+    fn from_json(line: &str) -> io::Result<Event> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let event: Value = serde_json::from_str(line).map_err(|e| invalid(e.to_string()))?;
+        let job = event["job"].as_u64().unwrap_or_default();
+        match event["event"].as_str() {
+            Some("accepted") => Ok(Event::Accepted {
+                job,
+                hosts: event["hosts"].as_u64().unwrap_or_default() as usize,
+            }),
+            Some("output") => Ok(Event::Output {
+                job,
+                line: event["line"].as_str().unwrap_or_default().to_string(),
+            }),
+            Some("exit") => Ok(Event::Exit {
+                job,
+                code: event["code"].as_i64().unwrap_or(-1) as i32,
+            }),
+            Some("error") => Ok(Event::Error {
+                message: event["message"].as_str().unwrap_or_default().to_string(),
+            }),
+            _ => Err(invalid(format!("unknown event: {}", line))),
+        }
+    }
+}
+
+// the events read from one connection, until the daemon closes it
+pub struct Events {
+    reader: BufReader<UnixStream>,
+}
+
+impl Events {
+    // drops the output and returns the exit code of the job
+    pub fn wait(self) -> io::Result<i32> {
+        for event in self {
+            match event? {
+                Event::Exit { code, .. } => return Ok(code),
+                Event::Error { message } => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+                }
+                _ => (),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "daemon closed the connection before the job exited",
+        ))
+    }
+}
+
+impl Iterator for Events {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Event::from_json(line.trim_end())),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+// talks to a daemon started with `sshp4ru serve --socket <path>`, one connection per request
+pub struct Client<P: AsRef<Path>> {
+    socket: P,
+}
+
+impl<P: AsRef<Path>> Client<P> {
+    pub fn new(socket: P) -> Client<P> {
+        Client { socket }
+    }
+
+    fn request(&self, request: &Value) -> io::Result<Events> {
+        let mut stream = UnixStream::connect(self.socket.as_ref())?;
+        let mut line = request.to_string();
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+        Ok(Events {
+            reader: BufReader::new(stream),
+        })
+    }
+
+    // the job runs once the ones submitted before it are done; dropping the events
+    // before the exit event stops it
+    pub fn submit<S: AsRef<str>>(&self, hosts: &[S], command: &[S]) -> io::Result<Events> {
+        let strings = |values: &[S]| -> Vec<String> {
+            values.iter().map(|s| s.as_ref().to_string()).collect()
+        };
+        self.request(&json!({ "hosts": strings(hosts), "command": strings(command) }))
+    }
+
+    // the events of every job submitted from now on, by any client
+    pub fn subscribe(&self) -> io::Result<Events> {
+        self.request(&json!({ "subscribe": true }))
+    }
+}
@@ -0,0 +1,145 @@
+use crate::host_source::{read_hosts, HostGroup, HostSource};
+use crate::warnings::Warnings;
+use crate::ParseError;
+use serde_json::Value;
+use std::io;
+use std::process::{Command, Stdio};
+
+// which address of an instance becomes its host name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Address {
+    Public,
+    Private,
+}
+
+impl Address {
+    pub fn parse(value: &str) -> Option<Address> {
+        match value {
+            "public" => Some(Address::Public),
+            "private" => Some(Address::Private),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Address::Public => "public",
+            Address::Private => "private",
+        }
+    }
+}
+
+// --cloud <provider>, the running instances matching every filter. `aws` is asked
+// through the aws CLI, any other provider is a `sshp4ru-cloud-<provider>` program in
+// PATH that prints a hosts file for `--address <kind> <filter>...`.
+#[derive(Debug)]
+pub struct CloudSource {
+    pub provider: String,
+    pub filters: Vec<String>,
+    pub address: Address,
+}
+
+impl HostSource for CloudSource {
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError> {
+        let source = format!("cloud {}", self.provider);
+        let text = match self.provider.as_str() {
+            "aws" => aws_hosts(&self.filters, self.address, warnings)?,
+            provider => {
+                let program = format!("sshp4ru-cloud-{}", provider);
+                let mut command = Command::new(&program);
+                command
+                    .args(["--address", self.address.as_str()])
+                    .args(&self.filters);
+                run(&program, command)?
+            }
+        };
+        let mut hosts = Vec::new();
+        read_hosts(text.as_bytes(), &mut hosts, &source, lenient, warnings)?;
+        Ok(vec![hosts])
+    }
+}
+
+// stdout of a provider query, failing with its stderr when it does
+fn run(program: &str, mut command: Command) -> Result<String, ParseError> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", program, e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ParseError::IoError(io::Error::other(format!(
+            "{} {}: {}",
+            program,
+            output.status,
+            stderr.lines().next().unwrap_or_default()
+        ))));
+    }
+    String::from_utf8(output.stdout).map_err(|e| ParseError::Utf8Error(e.utf8_error()))
+}
+
+// `<name>=<value>` filters as taken by `aws ec2 describe-instances --filters`, only
+// running instances are listed
+fn aws_hosts(
+    filters: &[String], address: Address, warnings: &Warnings,
+) -> Result<String, ParseError> {
+    let mut command = Command::new("aws");
+    command.args(["ec2", "describe-instances", "--output", "json", "--filters"]);
+    command.arg("Name=instance-state-name,Values=running");
+    for filter in filters {
+        // checked when the option was parsed
+        let (name, values) = filter.split_once('=').unwrap_or_default();
+        command.arg(format!("Name={},Values={}", name, values));
+    }
+    let output = run("aws", command)?;
+    let reply: Value = serde_json::from_str(&output).map_err(|e| {
+        ParseError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("aws: {}", e),
+        ))
+    })?;
+
+    // one hosts file line per instance, the instance id, zone and tags as metadata
+    let mut text = String::new();
+    let reservations = reply["Reservations"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice);
+    for instance in reservations
+        .iter()
+        .filter_map(|reservation| reservation["Instances"].as_array())
+        .flatten()
+    {
+        let id = instance["InstanceId"].as_str().unwrap_or_default();
+        let field = match address {
+            Address::Public => "PublicIpAddress",
+            Address::Private => "PrivateIpAddress",
+        };
+        let Some(name) = instance[field].as_str() else {
+            warnings.push(
+                "cloud-no-address",
+                format!("aws instance {} has no {} address", id, address.as_str()),
+            );
+            continue;
+        };
+        text.push_str(name);
+        text.push_str(&format!(" instance_id={}", meta_value(id)));
+        if let Some(zone) = instance["Placement"]["AvailabilityZone"].as_str() {
+            text.push_str(&format!(" az={}", meta_value(zone)));
+        }
+        for tag in instance["Tags"].as_array().map_or(&[][..], Vec::as_slice) {
+            if let (Some(key), Some(value)) = (tag["Key"].as_str(), tag["Value"].as_str()) {
+                let key = meta_value(key).replace('=', "_");
+                text.push_str(&format!(" tag.{}={}", key, meta_value(value)));
+            }
+        }
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+// metadata is split on whitespace, so none is left in keys or values
+fn meta_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_whitespace() { '_' } else { c })
+        .collect()
+}
@@ -0,0 +1,104 @@
+use libc::pid_t;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+// what --collect runs on the host, the remote shell expands the glob
+pub fn remote_command(glob: &str) -> Vec<String> {
+    ["tar", "-cf", "-", "--", glob]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+// where the files of `host` go, a `/` in the name cannot leave the directory
+pub fn host_dir(dir: &str, host: &str) -> PathBuf {
+    Path::new(dir).join(host.replace('/', "_"))
+}
+
+// a tar stream from ssh unpacked by a local tar, started from the queue of follow-up
+// jobs while the run goes on; both ends are waited for on a thread of their own, ssh
+// in a process group of its own like the other follow-up jobs
+pub struct Collector {
+    pub host: String,
+    pid: pid_t,
+    outcome: JoinHandle<Result<(), String>>,
+}
+
+impl Collector {
+    // `env` is the environment of the ssh children
+    pub fn start(
+        host: &str, ssh_command: &[String], dir: &Path, env: &[CString],
+    ) -> io::Result<Collector> {
+        std::fs::create_dir_all(dir)?;
+        let (program, args) = ssh_command
+            .split_first()
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        let mut ssh = Command::new(program)
+            .args(args)
+            .env_clear()
+            .envs(env.iter().filter_map(|pair| {
+                let pair = pair.to_str().ok()?;
+                pair.split_once('=')
+            }))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn()?;
+        // unwrap is safe here, stdout is piped
+        let stream = ssh.stdout.take().unwrap();
+        let tar = match Command::new("tar")
+            .arg("-xf")
+            .arg("-")
+            .arg("-C")
+            .arg(dir)
+            .stdin(stream)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(tar) => tar,
+            Err(e) => {
+                let _ = ssh.kill();
+                let _ = ssh.wait();
+                return Err(e);
+            }
+        };
+        Ok(Collector {
+            host: host.to_string(),
+            pid: ssh.id() as pid_t,
+            outcome: thread::spawn(move || wait(ssh, tar)),
+        })
+    }
+
+    pub fn pid(&self) -> pid_t {
+        self.pid
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.outcome.is_finished()
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        self.outcome
+            .join()
+            .unwrap_or_else(|_| Err("waiting panicked".to_string()))
+    }
+}
+
+// waits for both ends, a failure on either side fails the collection
+fn wait(mut ssh: Child, mut tar: Child) -> Result<(), String> {
+    let ssh = ssh.wait().map_err(|e| e.to_string())?;
+    let tar = tar.wait().map_err(|e| e.to_string())?;
+    if !ssh.success() {
+        return Err(format!("remote tar {}", ssh));
+    }
+    if !tar.success() {
+        return Err(format!("local tar {}", tar));
+    }
+    Ok(())
+}
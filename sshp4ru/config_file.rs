@@ -0,0 +1,108 @@
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+}
+
+// a TOML array of basic strings is also a JSON array, the options and the command are
+// written with JSON escapes so both formats read back through serde_json
+fn toml_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Object(_) => None,
+        Value::Array(items) if items.iter().any(|item| item.is_null() || item.is_object()) => None,
+        value => Some(value.to_string()),
+    }
+}
+
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_string()
+    } else {
+        Value::from(key).to_string()
+    }
+}
+
+// TOML has no null, unset options are left out; objects become tables after the plain keys
+fn write_toml_table(out: &mut String, table: &str, map: &serde_json::Map<String, Value>) {
+    if !table.is_empty() {
+        out.push_str(&format!("\n[{}]\n", table));
+    }
+    for (key, value) in map.iter() {
+        if let Some(value) = toml_value(value) {
+            out.push_str(&format!("{} = {}\n", toml_key(key), value));
+        }
+    }
+    for (key, value) in map.iter() {
+        if let Value::Object(inner) = value {
+            let name = match table {
+                "" => toml_key(key),
+                table => format!("{}.{}", table, toml_key(key)),
+            };
+            write_toml_table(out, &name, inner);
+        }
+    }
+}
+
+pub fn to_toml(value: &Value) -> String {
+    let mut out = String::new();
+    if let Value::Object(map) = value {
+        write_toml_table(&mut out, "", map);
+    }
+    out
+}
+
+fn string_array(value: Option<&Value>, key: &str) -> Result<Vec<String>, String> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    value
+        .as_array()
+        .ok_or_else(|| format!("`{}` is not a list", key))?
+        .iter()
+        .map(|item| {
+            item.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("`{}` holds something other than strings", key))
+        })
+        .collect()
+}
+
+// the `options` and `command` that --print-config wrote, in either format; anything else
+// in the file (the resolved values) is only there to be read
+pub fn read_config_file(path: &str) -> Result<(Vec<String>, Vec<String>), String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let config: Value = if data.trim_start().starts_with('{') {
+        serde_json::from_str(&data).map_err(|e| format!("{}: {}", path, e))?
+    } else {
+        // only the top-level keys before the first table, which is all --print-config
+        // puts outside of [resolved]
+        let mut map = serde_json::Map::new();
+        for (line_no, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                break;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}: line {}: expected key = value", path, line_no + 1))?;
+            let value = serde_json::from_str(value.trim())
+                .map_err(|e| format!("{}: line {}: {}", path, line_no + 1, e))?;
+            map.insert(key.trim().to_string(), value);
+        }
+        Value::Object(map)
+    };
+    Ok((
+        string_array(config.get("options"), "options")?,
+        string_array(config.get("command"), "command")?,
+    ))
+}
@@ -0,0 +1,162 @@
+use crate::signals;
+use crate::utils::generate_seed;
+use crate::PROG_NAME;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs::DirBuilder;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+// masters stay up this long after their last session, retries and follow-up jobs of
+// the same run reuse them; the run stops them when it is done
+const CONTROL_PERSIST: &str = "30s";
+// a master that is not up by then is left to the run, which reports the host
+const PREWARM_CONNECT_TIMEOUT: &str = "ConnectTimeout=10";
+// how often the masters being opened are looked in on
+const PREWARM_POLL: Duration = Duration::from_millis(10);
+
+// under $XDG_RUNTIME_DIR where it is usable, /tmp otherwise; socket paths are limited
+// to about 100 bytes and ssh expands `%` in them, so long or odd runtime dirs are
+// skipped. The pid and a random suffix keep concurrent runs apart.
+pub fn control_dir() -> PathBuf {
+    let base = std::env::var("XDG_RUNTIME_DIR")
+        .ok()
+        .filter(|dir| dir.starts_with('/') && dir.len() <= 40)
+        .filter(|dir| !dir.contains('%'))
+        .unwrap_or_else(|| "/tmp".to_string());
+    Path::new(&base).join(format!(
+        "{}-{}-{:08x}",
+        PROG_NAME,
+        std::process::id(),
+        generate_seed() as u32
+    ))
+}
+
+// %C is a hash of the connection, short enough for any host name
+pub fn ssh_options(dir: &Path) -> Vec<String> {
+    vec![
+        "ControlMaster=auto".to_string(),
+        format!("ControlPath={}/%C", dir.display()),
+        format!("ControlPersist={}", CONTROL_PERSIST),
+    ]
+}
+
+// --mux: opens the masters before the run, each ssh running `true` and ControlPersist
+// keeping the master in the background for the jobs. Like the run, `parallel` go at a
+// time and `per_jump` through the same jump host, the one each command goes with; the
+// next starts as soon as one is done. `env` is the environment of the ssh children. A
+// host that cannot be reached fails in the run, how many masters were opened is returned.
+pub fn prewarm(
+    commands: &[(Vec<String>, Option<String>)], parallel: usize, per_jump: Option<usize>,
+    env: &[CString],
+) -> usize {
+    let mut waiting: VecDeque<&(Vec<String>, Option<String>)> = commands.iter().collect();
+    let mut running: Vec<(Child, Option<&str>)> = Vec::new();
+    let mut ready = 0;
+    while !waiting.is_empty() || !running.is_empty() {
+        while running.len() < parallel.max(1) {
+            let free = |jump: &Option<String>| match (per_jump, jump) {
+                (Some(limit), Some(jump)) => {
+                    running
+                        .iter()
+                        .filter(|(_, busy)| *busy == Some(jump.as_str()))
+                        .count()
+                        < limit
+                }
+                _ => true,
+            };
+            let Some(index) = waiting.iter().position(|(_, jump)| free(jump)) else {
+                break;
+            };
+            let Some((command, jump)) = waiting.remove(index) else {
+                break;
+            };
+            if let Some(child) = spawn_master(command, env) {
+                running.push((child, jump.as_deref()));
+            }
+        }
+
+        let before = running.len();
+        running.retain_mut(|(child, _)| match child.try_wait() {
+            Ok(Some(status)) => {
+                ready += usize::from(status.success());
+                false
+            }
+            Ok(None) => true,
+            Err(_) => false,
+        });
+        if running.len() == before {
+            thread::sleep(PREWARM_POLL);
+        }
+    }
+    ready
+}
+
+// ConnectTimeout keeps an unreachable host from holding a slot for as long as TCP tries,
+// unless the options already set one
+fn spawn_master(command: &[String], env: &[CString]) -> Option<Child> {
+    let (program, args) = command.split_first()?;
+    let timeout_set = args
+        .iter()
+        .any(|arg| arg.to_ascii_lowercase().starts_with("connecttimeout"));
+    Command::new(program)
+        .args(if timeout_set {
+            &[][..]
+        } else {
+            &["-o", PREWARM_CONNECT_TIMEOUT][..]
+        })
+        .args(args)
+        .env_clear()
+        .envs(env.iter().filter_map(|pair| {
+            let pair = pair.to_str().ok()?;
+            pair.split_once('=')
+        }))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()
+}
+
+// the sockets directory of a run, removed with the masters behind it when dropped;
+// SIGINT and SIGTERM remove it from the signal handler instead
+pub struct ControlDir {
+    path: PathBuf,
+    program: String,
+}
+
+impl ControlDir {
+    // only the user gets in, and a directory that already exists is never shared
+    pub fn create(path: &Path, program: &str) -> io::Result<ControlDir> {
+        DirBuilder::new().mode(0o700).create(path)?;
+        signals::track_control_dir(Some(path));
+        Ok(ControlDir {
+            path: path.to_path_buf(),
+            program: program.to_string(),
+        })
+    }
+}
+
+impl Drop for ControlDir {
+    fn drop(&mut self) {
+        signals::track_control_dir(None);
+        if let Ok(entries) = std::fs::read_dir(&self.path) {
+            for entry in entries.flatten() {
+                // the host is ignored, the socket alone picks the master
+                let _ = Command::new(&self.program)
+                    .arg("-o")
+                    .arg(format!("ControlPath={}", entry.path().display()))
+                    .args(["-O", "exit", PROG_NAME])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status();
+            }
+        }
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,570 @@
+use crate::utils::generate_seed;
+use crate::{Config, RuntimeError, PROG_NAME};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, VecDeque};
+use std::fs::{DirBuilder, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+// reports of older jobs are dropped, the daemon may run for a long time
+const KEPT_REPORTS: usize = 100;
+// job requests are small, anything bigger is not one
+const MAX_REQUEST_BODY: usize = 1024 * 1024;
+// events a subscriber may fall behind by before it is dropped, jobs never wait for one
+const SUBSCRIBER_QUEUE: usize = 1024;
+
+// what a client sends, as a single JSON line: {"hosts": [...], "command": [...]};
+// see `client` for the events sent back. `hosts` can be left out when the daemon
+// watches a hosts file
+pub struct Job {
+    pub id: u64,
+    pub hosts: Vec<String>,
+    pub command: Vec<String>,
+}
+
+impl Job {
+    pub fn from_json(id: u64, line: &str) -> Result<Job, String> {
+        let request: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let strings = |key: &str| -> Result<Vec<String>, String> {
+            let values = request[key]
+                .as_array()
+                .ok_or_else(|| format!("`{}` must be an array of strings", key))?;
+            let strings = values
+                .iter()
+                .map(|value| value.as_str().map(str::to_string))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| format!("`{}` must be an array of strings", key))?;
+            if strings.is_empty() {
+                return Err(format!("`{}` is empty", key));
+            }
+            if strings.iter().any(|s| s.is_empty()) {
+                return Err(format!("`{}` entries cannot be empty", key));
+            }
+            // hosts go through the hosts parser, which splits lines on whitespace
+            if key == "hosts" && strings.iter().any(|s| s.contains(char::is_whitespace)) {
+                return Err("`hosts` entries cannot contain whitespace".to_string());
+            }
+            Ok(strings)
+        };
+        Ok(Job {
+            id,
+            hosts: match request.get("hosts") {
+                Some(_) => strings("hosts")?,
+                None => Vec::new(),
+            },
+            command: strings("command")?,
+        })
+    }
+}
+
+struct Daemon {
+    // the daemon's own options, passed on to the sshp4ru running each job
+    args: Vec<String>,
+    // jobs run one at a time in arrival order, so the daemon's -m caps the ssh
+    // processes of every client combined
+    job_lock: Mutex<()>,
+    next_id: AtomicU64,
+    // queues of the clients following the events of all jobs, over HTTP or the socket;
+    // each client's own thread writes them out
+    subscribers: Mutex<Vec<SyncSender<String>>>,
+    reports: Mutex<VecDeque<(u64, Value)>>,
+    // lines of the --watch-hosts file as of its latest change
+    hosts: Option<Mutex<Vec<String>>>,
+    // of --listen-token-file, required on every HTTP request
+    token: Option<String>,
+    // the jobs' reports until they are read back, only the daemon's user gets in
+    report_dir: PathBuf,
+}
+
+impl Daemon {
+    fn next_job(&self, request: &str) -> Result<Job, String> {
+        let mut job = Job::from_json(self.next_id.fetch_add(1, Ordering::Relaxed), request)?;
+        if job.hosts.is_empty() {
+            let hosts = self
+                .hosts
+                .as_ref()
+                .ok_or_else(|| "`hosts` must be an array of strings".to_string())?;
+            job.hosts = hosts.lock().unwrap().clone();
+            if job.hosts.is_empty() {
+                return Err("the watched hosts file lists no hosts".to_string());
+            }
+        }
+        Ok(job)
+    }
+
+    // every job event is also queued for the subscribers, the ones that went away or fell
+    // too far behind are dropped
+    fn broadcast(&self, event: &Value) {
+        let mut line = event.to_string();
+        line.push('\n');
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.try_send(line.clone()).is_ok());
+    }
+
+    // writes the subscriber's queue to it until it goes away or is dropped
+    fn subscribe<W: Write>(&self, mut stream: W) -> io::Result<()> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        self.subscribers.lock().unwrap().push(sender);
+        for line in receiver {
+            stream.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn report(&self, id: u64) -> Option<Value> {
+        let reports = self.reports.lock().unwrap();
+        reports
+            .iter()
+            .find(|(job, _)| *job == id)
+            .map(|(_, report)| report.clone())
+    }
+
+    fn keep_report(&self, id: u64, path: &Path) {
+        let report = std::fs::read(path)
+            .ok()
+            .and_then(|report| serde_json::from_slice(&report).ok());
+        let _ = std::fs::remove_file(path);
+        if let Some(report) = report {
+            let mut reports = self.reports.lock().unwrap();
+            reports.push_back((id, report));
+            if reports.len() > KEPT_REPORTS {
+                reports.pop_front();
+            }
+        }
+    }
+
+    // a job is run by a child sshp4ru with the daemon's options and the hosts on its stdin,
+    // so it behaves exactly like the same command line would
+    fn run_job(
+        &self, job: &Job, emit: &mut dyn FnMut(&Value) -> io::Result<()>,
+    ) -> io::Result<i32> {
+        let _running = self.job_lock.lock().unwrap();
+        let mut emit = |event: Value| -> io::Result<()> {
+            self.broadcast(&event);
+            emit(&event)
+        };
+        emit(json!({ "event": "accepted", "job": job.id, "hosts": job.hosts.len() }))?;
+
+        let report = self.report_dir.join(format!("job-{}.json", job.id));
+        // stdout and stderr share one pipe, so lines arrive in the order they were written
+        let (reader, writer) = nix::unistd::pipe()?;
+        let mut child = Command::new(std::env::current_exe()?)
+            .args(&self.args)
+            .arg("--report")
+            .arg(&report)
+            .arg("-f")
+            .arg("-")
+            // the client's words are the command, never options of the job
+            .arg("--")
+            .args(&job.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::from(writer.try_clone()?))
+            .stderr(Stdio::from(writer))
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            for host in job.hosts.iter() {
+                writeln!(stdin, "{}", host)?;
+            }
+        }
+
+        for line in BufReader::new(File::from(reader)).lines() {
+            let line = line?;
+            // a client that went away stops the job, nobody is left to read its output
+            if let Err(e) = emit(json!({ "event": "output", "job": job.id, "line": line })) {
+                let _ = child.kill();
+                let _ = child.wait();
+                let _ = std::fs::remove_file(&report);
+                return Err(e);
+            }
+        }
+
+        let code = child.wait()?.code().unwrap_or(-1);
+        self.keep_report(job.id, &report);
+        emit(json!({ "event": "exit", "job": job.id, "code": code }))?;
+        Ok(code)
+    }
+}
+
+fn send<W: Write>(stream: &mut W, event: &Value) -> io::Result<()> {
+    let mut line = event.to_string();
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+fn handle_unix_client(daemon: &Daemon, mut stream: UnixStream) -> io::Result<()> {
+    let mut request = String::new();
+    // connected and left without a request, e.g. another daemon checking the socket
+    if BufReader::new(stream.try_clone()?).read_line(&mut request)? == 0 {
+        return Ok(());
+    }
+    // {"subscribe": true} follows the events of all jobs instead of submitting one
+    if serde_json::from_str::<Value>(&request).is_ok_and(|request| request["subscribe"] == true) {
+        return daemon.subscribe(stream);
+    }
+    match daemon.next_job(&request) {
+        Ok(job) => daemon
+            .run_job(&job, &mut |event| send(&mut stream, event))
+            .map(|_| ()),
+        Err(e) => send(&mut stream, &json!({ "event": "error", "message": e })),
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    // the token of an `Authorization: Bearer <token>` header
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+// just enough HTTP/1.1 for curl and dashboards: one request per connection
+fn read_http_request(stream: &TcpStream) -> io::Result<HttpRequest> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut content_length = 0;
+    let mut token = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("bad content-length"))?;
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                token = value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .map(|token| token.trim().to_string());
+            }
+        }
+    }
+    if content_length > MAX_REQUEST_BODY {
+        return Err(invalid("request body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(HttpRequest {
+        method,
+        path,
+        token,
+        body,
+    })
+}
+
+fn write_http_head(stream: &mut TcpStream, status: &str, content_type: &str) -> io::Result<()> {
+    // no content length, the body ends when the connection is closed
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nConnection: close\r\n\r\n",
+        status, content_type
+    )
+}
+
+fn write_http_json(stream: &mut TcpStream, status: &str, body: &Value) -> io::Result<()> {
+    write_http_head(stream, status, "application/json")?;
+    send(stream, body)
+}
+
+// compared in full whatever the input, so the time taken does not tell how much matched
+fn token_matches(given: &str, token: &str) -> bool {
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+    let diff = given
+        .iter()
+        .zip(token.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    diff == 0 && given.len() == token.len()
+}
+
+// POST /jobs streams the job's events back, GET /events follows the events of all jobs,
+// GET /jobs/<id>/report returns the JSON report of a finished job
+fn handle_http_client(daemon: &Daemon, mut stream: TcpStream) -> io::Result<()> {
+    let request = match read_http_request(&stream) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = json!({ "error": e.to_string() });
+            return write_http_json(&mut stream, "400 Bad Request", &error);
+        }
+    };
+    if let Some(token) = &daemon.token {
+        if !request
+            .token
+            .as_deref()
+            .is_some_and(|given| token_matches(given, token))
+        {
+            let error = json!({ "error": "missing or wrong bearer token" });
+            return write_http_json(&mut stream, "401 Unauthorized", &error);
+        }
+    }
+    let report_id = request
+        .path
+        .strip_prefix("/jobs/")
+        .and_then(|rest| rest.strip_suffix("/report"))
+        .and_then(|id| id.parse::<u64>().ok());
+
+    match (request.method.as_str(), request.path.as_str(), report_id) {
+        ("POST", "/jobs", _) => {
+            let body = String::from_utf8_lossy(&request.body);
+            match daemon.next_job(&body) {
+                Ok(job) => {
+                    write_http_head(&mut stream, "200 OK", "application/x-ndjson")?;
+                    daemon
+                        .run_job(&job, &mut |event| send(&mut stream, event))
+                        .map(|_| ())
+                }
+                Err(e) => write_http_json(&mut stream, "400 Bad Request", &json!({ "error": e })),
+            }
+        }
+        ("GET", "/events", _) => {
+            write_http_head(&mut stream, "200 OK", "application/x-ndjson")?;
+            daemon.subscribe(stream)
+        }
+        ("GET", _, Some(id)) => match daemon.report(id) {
+            Some(report) => write_http_json(&mut stream, "200 OK", &report),
+            None => {
+                let error = json!({ "error": format!("no report for job {}", id) });
+                write_http_json(&mut stream, "404 Not Found", &error)
+            }
+        },
+        _ => write_http_json(
+            &mut stream,
+            "404 Not Found",
+            &json!({ "error": "not found" }),
+        ),
+    }
+}
+
+// the lines read_hosts would look at, without blanks and comments
+fn read_watched_hosts(path: &Path) -> io::Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+// only the hosts that came and went are logged, metadata changes are picked up silently
+fn log_hosts_diff(path: &Path, old: &[String], new: &[String]) {
+    let names = |lines: &[String]| -> BTreeSet<String> {
+        lines
+            .iter()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect()
+    };
+    let (old, new) = (names(old), names(new));
+    let changes: Vec<String> = new
+        .difference(&old)
+        .map(|host| format!("+{}", host))
+        .chain(old.difference(&new).map(|host| format!("-{}", host)))
+        .collect();
+    if !changes.is_empty() {
+        eprintln!(
+            "[{}] {} changed, {} hosts: {}",
+            PROG_NAME,
+            path.display(),
+            new.len(),
+            changes.join(" ")
+        );
+    }
+}
+
+// editors replace files by renaming over them, so the directory is watched, not the file
+fn watch_hosts(daemon: &Daemon, path: &Path) -> io::Result<()> {
+    let hosts = match &daemon.hosts {
+        Some(hosts) => hosts,
+        None => return Ok(()),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+    inotify.add_watch(
+        dir,
+        AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+    )?;
+    loop {
+        let events = inotify.read_events()?;
+        if !events
+            .iter()
+            .any(|event| event.name.as_deref() == path.file_name())
+        {
+            continue;
+        }
+        match read_watched_hosts(path) {
+            Ok(new) => {
+                let mut hosts = hosts.lock().unwrap();
+                log_hosts_diff(path, &hosts, &new);
+                *hosts = new;
+            }
+            Err(e) => eprintln!("[{}] {}: {}", PROG_NAME, path.display(), e),
+        }
+    }
+}
+
+// a leftover socket of a daemon that is gone is replaced, a live one is not
+fn bind(path: &str) -> io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another daemon is serving on it",
+        ));
+    }
+    let _ = std::fs::remove_file(path);
+    UnixListener::bind(path)
+}
+
+// the first line of the file, it may not be empty
+fn read_token(path: &str) -> io::Result<String> {
+    let token = std::fs::read_to_string(path)?
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no token in it"));
+    }
+    Ok(token)
+}
+
+// a directory that already exists is never used, it may not be ours
+fn private_dir() -> io::Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!(
+        "{}-{}-{:08x}",
+        PROG_NAME,
+        std::process::id(),
+        generate_seed() as u32
+    ));
+    DirBuilder::new().mode(0o700).create(&path)?;
+    Ok(path)
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.report_dir);
+    }
+}
+
+// every connection gets its own thread, jobs still run one at a time
+pub fn serve(conf: &Config) -> Result<(), RuntimeError> {
+    let token = match &conf.serve_token_file {
+        Some(path) => {
+            Some(read_token(path).map_err(|e| RuntimeError::ListenTokenError(path.clone(), e))?)
+        }
+        None => None,
+    };
+    let daemon = Daemon {
+        args: conf.serve_args.clone(),
+        job_lock: Mutex::new(()),
+        next_id: AtomicU64::new(1),
+        subscribers: Mutex::new(Vec::new()),
+        reports: Mutex::new(VecDeque::new()),
+        hosts: match &conf.serve_hosts {
+            Some(path) => {
+                let hosts = read_watched_hosts(Path::new(path))
+                    .map_err(|e| RuntimeError::WatchHostsError(path.clone(), e))?;
+                eprintln!("[{}] watching {} ({} hosts)", PROG_NAME, path, hosts.len());
+                Some(Mutex::new(hosts))
+            }
+            None => None,
+        },
+        token,
+        report_dir: private_dir()
+            .map_err(|e| RuntimeError::ServeError(std::env::temp_dir().display().to_string(), e))?,
+    };
+
+    let unix_listener = match &conf.serve_socket {
+        Some(path) => {
+            let listener = bind(path).map_err(|e| RuntimeError::ServeError(path.clone(), e))?;
+            eprintln!("[{}] serving on {}", PROG_NAME, path);
+            Some(listener)
+        }
+        None => None,
+    };
+    let tcp_listener = match &conf.serve_listen {
+        Some(addr) => {
+            let listener =
+                TcpListener::bind(addr).map_err(|e| RuntimeError::ServeError(addr.clone(), e))?;
+            // anyone who can reach the port could run commands on every host
+            let loopback = listener
+                .local_addr()
+                .map_err(|e| RuntimeError::ServeError(addr.clone(), e))?
+                .ip()
+                .is_loopback();
+            if !loopback && daemon.token.is_none() {
+                return Err(RuntimeError::ServeError(
+                    addr.clone(),
+                    io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "listening beyond loopback needs --listen-token-file",
+                    ),
+                ));
+            }
+            eprintln!("[{}] serving HTTP on {}", PROG_NAME, addr);
+            Some(listener)
+        }
+        None => None,
+    };
+
+    thread::scope(|scope| {
+        let daemon = &daemon;
+        if let Some(path) = &conf.serve_hosts {
+            scope.spawn(move || {
+                if let Err(e) = watch_hosts(daemon, Path::new(path)) {
+                    eprintln!("[{}] watching {}: {}", PROG_NAME, path, e);
+                }
+            });
+        }
+        if let Some(listener) = unix_listener {
+            scope.spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    scope.spawn(move || {
+                        if let Err(e) = handle_unix_client(daemon, stream) {
+                            eprintln!("[{}] unix client: {}", PROG_NAME, e);
+                        }
+                    });
+                }
+            });
+        }
+        if let Some(listener) = tcp_listener {
+            scope.spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    scope.spawn(move || {
+                        if let Err(e) = handle_http_client(daemon, stream) {
+                            eprintln!("[{}] http client: {}", PROG_NAME, e);
+                        }
+                    });
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// how long `durations` take with `jobs` at a time, each starting in order on the first
+// job slot to free up, the way the run loop hands them out
+pub fn makespan(durations: &[u64], jobs: usize) -> u64 {
+    let mut slots: BinaryHeap<Reverse<u64>> = (0..jobs.max(1)).map(|_| Reverse(0)).collect();
+    let mut end = 0;
+    for duration in durations.iter() {
+        let Reverse(free_at) = slots.pop().unwrap_or(Reverse(0));
+        let finished = free_at + duration;
+        end = end.max(finished);
+        slots.push(Reverse(finished));
+    }
+    end
+}
+
+// the fewest jobs above `jobs`, up to `limit`, estimated to finish within `deadline_ms`
+pub fn jobs_needed(durations: &[u64], deadline_ms: u64, jobs: u8, limit: u8) -> Option<u8> {
+    (jobs.saturating_add(1)..=limit).find(|&jobs| makespan(durations, jobs as usize) <= deadline_ms)
+}
@@ -0,0 +1,48 @@
+// what `--debug` traces, `-d` turns everything on
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DebugCategories {
+    // spawning, retries, locking and per host timings
+    pub scheduler: bool,
+    // epoll wakeups and reads from the children's pipes
+    pub io: bool,
+    // the hosts list and the ssh command line
+    pub ssh: bool,
+    // signal handler setup
+    pub signals: bool,
+}
+
+impl DebugCategories {
+    pub fn all() -> DebugCategories {
+        DebugCategories {
+            scheduler: true,
+            io: true,
+            ssh: true,
+            signals: true,
+        }
+    }
+
+    // <category>[,<category>...]
+    pub fn from_spec(spec: &str) -> Result<DebugCategories, String> {
+        let mut categories = DebugCategories::default();
+        for category in spec.split(',').map(str::trim) {
+            match category {
+                "scheduler" => categories.scheduler = true,
+                "io" => categories.io = true,
+                "ssh" => categories.ssh = true,
+                "signals" => categories.signals = true,
+                "all" => categories = DebugCategories::all(),
+                _ => {
+                    return Err(format!(
+                        "unknown category `{}` (expected scheduler, io, ssh, signals or all)",
+                        category
+                    ))
+                }
+            }
+        }
+        Ok(categories)
+    }
+
+    pub fn any(&self) -> bool {
+        self.scheduler || self.io || self.ssh || self.signals
+    }
+}
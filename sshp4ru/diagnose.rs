@@ -0,0 +1,111 @@
+use crate::redact::Redactor;
+use crate::Host;
+use libc::pid_t;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Output, Stdio};
+use std::rc::Rc;
+use std::thread::{self, JoinHandle};
+
+// diagnostics longer than this are cut, the report keeps the start
+const MAX_DIAGNOSTICS_LENGTH: usize = 64 * 1024;
+
+// --on-remote-failure running on a host whose command failed, started from the queue of
+// follow-up jobs while the run goes on
+pub struct Diagnostic {
+    host: Rc<RefCell<Host>>,
+    child: Background,
+}
+
+impl Diagnostic {
+    // `env` is the environment of the ssh children
+    pub fn start(
+        host: &Rc<RefCell<Host>>, ssh_command: &[String], env: &[CString],
+    ) -> io::Result<Diagnostic> {
+        Ok(Diagnostic {
+            host: Rc::clone(host),
+            child: spawn(ssh_command, env)?,
+        })
+    }
+
+    pub fn pid(&self) -> pid_t {
+        self.child.pid()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.child.is_finished()
+    }
+
+    // stdout then stderr, attached to the host for the report with secrets redacted
+    pub fn finish(self, redactor: &Redactor) {
+        self.host.borrow_mut().diagnostics = Some(self.child.collect(redactor));
+    }
+}
+
+// a child of spawn() read to the end on a thread of its own, so a full pipe never holds
+// it up while the run loop only looks in once in a while
+pub struct Background {
+    pid: pid_t,
+    output: JoinHandle<io::Result<Output>>,
+}
+
+impl Background {
+    fn new(child: Child) -> Background {
+        Background {
+            pid: child.id() as pid_t,
+            output: thread::spawn(move || child.wait_with_output()),
+        }
+    }
+
+    pub fn pid(&self) -> pid_t {
+        self.pid
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.output.is_finished()
+    }
+
+    // the output cut to MAX_DIAGNOSTICS_LENGTH and redacted, waited for if need be
+    pub fn collect(self, redactor: &Redactor) -> Result<(String, i32), String> {
+        self.output
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("reader panicked")))
+            .map(|output| {
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+                if text.len() > MAX_DIAGNOSTICS_LENGTH {
+                    let mut end = MAX_DIAGNOSTICS_LENGTH;
+                    while !text.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    text.truncate(end);
+                }
+                (
+                    redactor.redact(&text).into_owned(),
+                    output.status.code().unwrap_or(-1),
+                )
+            })
+            .map_err(|e| e.to_string())
+    }
+}
+
+// runs `command` on its own with the output piped, also for --map-exit; in a process
+// group of its own so a stopped run can kill what a local command started too
+pub fn spawn(command: &[String], env: &[CString]) -> io::Result<Background> {
+    let (program, args) = command.split_first().ok_or(io::ErrorKind::InvalidInput)?;
+    Command::new(program)
+        .args(args)
+        .env_clear()
+        .envs(env.iter().filter_map(|pair| {
+            let pair = pair.to_str().ok()?;
+            pair.split_once('=')
+        }))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map(Background::new)
+}
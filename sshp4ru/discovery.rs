@@ -0,0 +1,429 @@
+use crate::host_source::{read_hosts, HostGroup, HostSource};
+use crate::http;
+use crate::utils::generate_seed;
+use crate::warnings::Warnings;
+use crate::ParseError;
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+// a name server or the Consul agent slower than this is given up on
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const CONSUL_DEFAULT_ADDR: &str = "127.0.0.1:8500";
+// DNS record type and class of SRV records in the Internet class
+const DNS_TYPE_SRV: u16 = 33;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_MAX_MESSAGE: usize = 4096;
+
+// (priority, weight, port, target) of an SRV record
+type SrvRecord = (u16, u16, u16, String);
+
+// what a name server replied: the records, or that they did not fit in a UDP message
+#[derive(Debug, PartialEq)]
+enum SrvReply {
+    Records(Vec<SrvRecord>),
+    Truncated,
+}
+
+// --dns-srv <name>, one host per SRV record with its port as `port=` metadata; the
+// records are asked of the name servers in /etc/resolv.conf, in order
+#[derive(Debug)]
+pub struct DnsSrvSource {
+    pub name: String,
+}
+
+impl HostSource for DnsSrvSource {
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError> {
+        let mut records = resolve_srv(&self.name)?;
+        // the order SRV asks clients to prefer, which is also the order hosts start in
+        records.sort_by(|a, b| (a.0, b.1, &a.3).cmp(&(b.0, a.1, &b.3)));
+        let mut text = String::new();
+        for (priority, weight, port, target) in records {
+            text.push_str(&format!(
+                "{} port={} srv_priority={} srv_weight={}\n",
+                target, port, priority, weight
+            ));
+        }
+        let mut hosts = Vec::new();
+        let source = format!("dns-srv {}", self.name);
+        read_hosts(text.as_bytes(), &mut hosts, &source, lenient, warnings)?;
+        Ok(vec![hosts])
+    }
+}
+
+// --consul-service <name>, the instances passing their health checks as the Consul agent
+// at $CONSUL_HTTP_ADDR (127.0.0.1:8500 by default) knows them
+#[derive(Debug)]
+pub struct ConsulSource {
+    pub service: String,
+}
+
+impl HostSource for ConsulSource {
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError> {
+        let addr = std::env::var("CONSUL_HTTP_ADDR").unwrap_or_else(|_| CONSUL_DEFAULT_ADDR.into());
+        let token = std::env::var("CONSUL_HTTP_TOKEN").ok();
+        let path = format!(
+            "/v1/health/service/{}?passing=1",
+            percent_encode(&self.service)
+        );
+        let body = http_get(&addr, &path, token.as_deref())
+            .map_err(|e| io::Error::new(e.kind(), format!("consul {}: {}", addr, e)))?;
+        let reply: Value = serde_json::from_str(&body).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("consul {}: {}", addr, e),
+            )
+        })?;
+
+        // the service address where it registered one, the node's otherwise
+        let mut text = String::new();
+        for entry in reply.as_array().map_or(&[][..], Vec::as_slice) {
+            let (node, service) = (&entry["Node"], &entry["Service"]);
+            let address = match service["Address"].as_str() {
+                Some(address) if !address.is_empty() => address,
+                _ => node["Address"].as_str().unwrap_or_default(),
+            };
+            if address.is_empty() {
+                continue;
+            }
+            text.push_str(address);
+            if let Some(port) = service["Port"].as_u64().filter(|port| *port > 0) {
+                text.push_str(&format!(" port={}", port));
+            }
+            if let Some(name) = node["Node"].as_str() {
+                text.push_str(&format!(" node={}", name));
+            }
+            if let Some(datacenter) = node["Datacenter"].as_str() {
+                text.push_str(&format!(" datacenter={}", datacenter));
+            }
+            // metadata is split on whitespace, so none is left in the tags
+            let tags: Vec<String> = service["Tags"]
+                .as_array()
+                .map_or(&[][..], Vec::as_slice)
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|tag| tag.replace(char::is_whitespace, "_"))
+                .collect();
+            if !tags.is_empty() {
+                text.push_str(&format!(" tags={}", tags.join(",")));
+            }
+            text.push('\n');
+        }
+        let mut hosts = Vec::new();
+        let source = format!("consul-service {}", self.service);
+        read_hosts(text.as_bytes(), &mut hosts, &source, lenient, warnings)?;
+        Ok(vec![hosts])
+    }
+}
+
+// everything but the unreserved characters of RFC 3986, so a name stays one path segment
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// plain http only, as a local agent listens: [http://]host:port
+fn http_get(addr: &str, path: &str, token: Option<&str>) -> io::Result<String> {
+    let authority = addr.strip_prefix("http://").unwrap_or(addr);
+    if authority.contains("://") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only http:// addresses are supported",
+        ));
+    }
+    let headers: Vec<(&str, &str)> = token
+        .map(|token| ("X-Consul-Token", token))
+        .into_iter()
+        .collect();
+    http::request(
+        authority.trim_end_matches('/'),
+        "GET",
+        path,
+        &headers,
+        None,
+        DISCOVERY_TIMEOUT,
+    )
+}
+
+// every SRV record of `name`, a target of `.` says the service is not offered there and
+// is left out
+fn resolve_srv(name: &str) -> Result<Vec<SrvRecord>, ParseError> {
+    let servers = name_servers()?;
+    let mut last_error = io::Error::other("no name servers in /etc/resolv.conf");
+    for server in servers {
+        match query_srv(server, name) {
+            Ok(records) => return Ok(records),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(io::Error::new(
+        last_error.kind(),
+        format!("dns-srv {}: {}", name, last_error),
+    )
+    .into())
+}
+
+fn name_servers() -> io::Result<Vec<SocketAddr>> {
+    let conf = std::fs::read_to_string(RESOLV_CONF)?;
+    Ok(conf
+        .lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|server| (server.trim(), 53).to_socket_addrs().ok()?.next())
+        .collect())
+}
+
+// asked over UDP, and again over TCP when the records do not fit in a UDP reply
+fn query_srv(server: SocketAddr, name: &str) -> io::Result<Vec<SrvRecord>> {
+    let id = generate_seed() as u16;
+    let query = srv_query(id, name)?;
+
+    let bind = if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(&query)?;
+    let mut reply = vec![0u8; DNS_MAX_MESSAGE];
+    let len = loop {
+        let len = socket.recv(&mut reply)?;
+        // a late reply to an earlier query is not ours
+        if len >= 12 && reply[..2] == id.to_be_bytes() {
+            break len;
+        }
+    };
+    if let SrvReply::Records(records) = parse_srv_reply(&reply[..len])? {
+        return Ok(records);
+    }
+
+    // over TCP every message goes with its length in front
+    let mut stream = TcpStream::connect_timeout(&server, DISCOVERY_TIMEOUT)?;
+    stream.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+    stream.set_write_timeout(Some(DISCOVERY_TIMEOUT))?;
+    stream.write_all(&(query.len() as u16).to_be_bytes())?;
+    stream.write_all(&query)?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut reply = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut reply)?;
+    if reply.len() < 12 || reply[..2] != id.to_be_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reply to another query",
+        ));
+    }
+    match parse_srv_reply(&reply)? {
+        SrvReply::Records(records) => Ok(records),
+        SrvReply::Truncated => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reply truncated over TCP",
+        )),
+    }
+}
+
+// header: id, recursion desired, one question
+fn srv_query(id: u16, name: &str) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(512);
+    query.extend(id.to_be_bytes());
+    query.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid name"));
+        }
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(DNS_TYPE_SRV.to_be_bytes());
+    query.extend(DNS_CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn parse_srv_reply(reply: &[u8]) -> io::Result<SrvReply> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    if reply.len() < 12 {
+        return Err(invalid("malformed reply"));
+    }
+    let flags = u16::from_be_bytes([reply[2], reply[3]]);
+    match flags & 0x000f {
+        0 => {}
+        3 => return Err(invalid("no such name")),
+        rcode => return Err(io::Error::other(format!("name server error {}", rcode))),
+    }
+    if flags & 0x0200 != 0 {
+        return Ok(SrvReply::Truncated);
+    }
+    let questions = u16::from_be_bytes([reply[4], reply[5]]);
+    let answers = u16::from_be_bytes([reply[6], reply[7]]);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(reply, pos).ok_or_else(|| invalid("malformed reply"))? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(reply, pos).ok_or_else(|| invalid("malformed reply"))?;
+        let field = |at: usize| -> io::Result<u16> {
+            reply
+                .get(at..at + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .ok_or_else(|| invalid("malformed reply"))
+        };
+        let (rtype, rdlength) = (field(pos)?, field(pos + 8)? as usize);
+        let rdata = pos + 10;
+        if rtype == DNS_TYPE_SRV {
+            let target = read_name(reply, rdata + 6).ok_or_else(|| invalid("malformed reply"))?;
+            if !target.is_empty() {
+                records.push((field(rdata)?, field(rdata + 2)?, field(rdata + 4)?, target));
+            }
+        }
+        pos = rdata + rdlength;
+    }
+    Ok(SrvReply::Records(records))
+}
+
+// the offset after a possibly compressed name starting at `pos`
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += len + 1,
+        }
+    }
+}
+
+// the dotted name at `pos`, following compression pointers; the root is empty
+fn read_name(message: &[u8], mut pos: usize) -> Option<String> {
+    let mut labels: Vec<String> = Vec::new();
+    // pointers only ever go back, a loop of them is cut off
+    let mut jumps = 0;
+    loop {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => return Some(labels.join(".")),
+            len if len & 0xc0 == 0xc0 => {
+                jumps += 1;
+                if jumps > 16 {
+                    return None;
+                }
+                pos = ((len & 0x3f) << 8) | *message.get(pos + 1)? as usize;
+            }
+            len => {
+                let label = message.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += len + 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const NAME: &str = "_ssh._tcp.example.com";
+    // where `example` starts in the question of every reply below
+    const EXAMPLE_AT: u8 = 22;
+
+    // a reply to srv_query(id, NAME) with `flags` and one SRV record per target; every
+    // target ends in a compression pointer to `example.com` in the question
+    fn reply(id: u16, flags: u16, targets: &[(&str, u16)]) -> Vec<u8> {
+        let mut message = srv_query(id, NAME).unwrap();
+        message[2..4].copy_from_slice(&flags.to_be_bytes());
+        message[6..8].copy_from_slice(&(targets.len() as u16).to_be_bytes());
+        for (target, port) in targets {
+            let mut rdata = Vec::new();
+            rdata.extend([0, 10, 0, 5]);
+            rdata.extend(port.to_be_bytes());
+            rdata.push(target.len() as u8);
+            rdata.extend(target.as_bytes());
+            rdata.extend([0xc0, EXAMPLE_AT]);
+            // the owner name points at the question
+            message.extend([0xc0, 12]);
+            message.extend(DNS_TYPE_SRV.to_be_bytes());
+            message.extend(DNS_CLASS_IN.to_be_bytes());
+            message.extend(300u32.to_be_bytes());
+            message.extend((rdata.len() as u16).to_be_bytes());
+            message.extend(rdata);
+        }
+        message
+    }
+
+    #[test]
+    fn follows_compression_pointers() {
+        let reply = reply(7, 0x8180, &[("web1", 22), ("web2", 2222)]);
+        assert_eq!(
+            parse_srv_reply(&reply).unwrap(),
+            SrvReply::Records(vec![
+                (10, 5, 22, "web1.example.com".to_string()),
+                (10, 5, 2222, "web2.example.com".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_truncation() {
+        let reply = reply(7, 0x8380, &[]);
+        assert_eq!(parse_srv_reply(&reply).unwrap(), SrvReply::Truncated);
+    }
+
+    #[test]
+    fn refuses_bad_replies() {
+        assert!(parse_srv_reply(&reply(7, 0x8183, &[])).is_err());
+        let mut cut = reply(7, 0x8180, &[("web1", 22)]);
+        cut.truncate(cut.len() - 4);
+        assert!(parse_srv_reply(&cut).is_err());
+        // a pointer to itself never ends
+        assert_eq!(read_name(&[0xc0, 0], 0), None);
+    }
+
+    #[test]
+    fn falls_back_to_tcp() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(server).unwrap();
+        let name_server = thread::spawn(move || {
+            let mut query = [0u8; 512];
+            let (_, client) = udp.recv_from(&mut query).unwrap();
+            let id = u16::from_be_bytes([query[0], query[1]]);
+            udp.send_to(&reply(id, 0x8380, &[]), client).unwrap();
+
+            let (mut stream, _) = tcp.accept().unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut query).unwrap();
+            let id = u16::from_be_bytes([query[0], query[1]]);
+            let reply = reply(id, 0x8180, &[("web1", 22)]);
+            stream
+                .write_all(&(reply.len() as u16).to_be_bytes())
+                .unwrap();
+            stream.write_all(&reply).unwrap();
+        });
+        let records = query_srv(server, NAME).unwrap();
+        name_server.join().unwrap();
+        assert_eq!(records, vec![(10, 5, 22, "web1.example.com".to_string())]);
+    }
+
+    #[test]
+    fn encodes_service_names() {
+        assert_eq!(percent_encode("web-1_a.b~"), "web-1_a.b~");
+        assert_eq!(percent_encode("a/b?c d"), "a%2Fb%3Fc%20d");
+    }
+}
@@ -0,0 +1,247 @@
+use crate::fdwatcher::NATIVE_INTERFACE;
+use crate::utils::{open_files_limit, Color, Colorize};
+use crate::{Config, Fdwatcher, FDS_PER_JOB, FDS_RESERVED, PROG_NAME};
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+
+// keeps the connectivity check from hanging on a black-holed host
+const PROBE_CONNECT_TIMEOUT: u32 = 5;
+
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+    Skip,
+}
+
+struct Finding {
+    status: Status,
+    check: &'static str,
+    detail: String,
+    // what to do about it, only shown for warnings and failures
+    hint: Option<String>,
+}
+
+impl Finding {
+    fn new(status: Status, check: &'static str, detail: String) -> Finding {
+        Finding {
+            status,
+            check,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn hint(mut self, hint: &str) -> Finding {
+        self.hint = Some(hint.to_string());
+        self
+    }
+}
+
+fn check_ssh(program: &str) -> Finding {
+    // ssh -V prints its version on stderr
+    match Command::new(program)
+        .arg("-V")
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => {
+            let version = String::from_utf8_lossy(&output.stderr);
+            Finding::new(
+                Status::Ok,
+                "ssh",
+                version.lines().next().unwrap_or_default().to_string(),
+            )
+        }
+        Err(e) => Finding::new(
+            Status::Fail,
+            "ssh",
+            format!("cannot run {}: {}", program, e),
+        )
+        .hint("install an OpenSSH client, add it to PATH or point --ssh-path at it"),
+    }
+}
+
+fn check_agent() -> Finding {
+    let socket = match std::env::var("SSH_AUTH_SOCK") {
+        Ok(socket) => socket,
+        Err(_) => {
+            return Finding::new(
+                Status::Warn,
+                "agent",
+                "SSH_AUTH_SOCK is not set".to_string(),
+            )
+            .hint("keys with a passphrase prompt once per host, run ssh-agent and ssh-add")
+        }
+    };
+    if let Err(e) = UnixStream::connect(&socket) {
+        return Finding::new(
+            Status::Warn,
+            "agent",
+            format!("cannot connect to {}: {}", socket, e),
+        )
+        .hint("the agent is gone, restart it and update SSH_AUTH_SOCK");
+    }
+    // ssh-add -l exits 1 when the agent holds no identities
+    match Command::new("ssh-add")
+        .arg("-l")
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.code() == Some(1) => {
+            Finding::new(Status::Warn, "agent", format!("{} holds no keys", socket))
+                .hint("add your key with ssh-add")
+        }
+        Ok(output) => Finding::new(
+            Status::Ok,
+            "agent",
+            format!(
+                "{} keys in {}",
+                String::from_utf8_lossy(&output.stdout).lines().count(),
+                socket
+            ),
+        ),
+        Err(_) => Finding::new(Status::Ok, "agent", format!("listening on {}", socket)),
+    }
+}
+
+fn check_fd_limit(max_jobs: u8) -> Finding {
+    let current = match open_files_limit() {
+        Ok(current) => current,
+        Err(e) => {
+            return Finding::new(Status::Warn, "fd limit", format!("getrlimit failed: {}", e))
+        }
+    };
+
+    let needed = max_jobs as u64 * FDS_PER_JOB + FDS_RESERVED;
+    let detail = format!(
+        "{} open files allowed, -m {} needs {}",
+        current, max_jobs, needed
+    );
+    if current < needed {
+        Finding::new(Status::Warn, "fd limit", detail)
+            .hint("jobs are lowered to fit, raise it with `ulimit -n` for full concurrency")
+    } else {
+        Finding::new(Status::Ok, "fd limit", detail)
+    }
+}
+
+fn check_fdwatcher() -> Finding {
+    match Fdwatcher::new() {
+        Ok(watcher) if watcher.interface() == NATIVE_INTERFACE => {
+            Finding::new(Status::Ok, watcher.interface(), "available".to_string())
+        }
+        Ok(watcher) => Finding::new(
+            Status::Warn,
+            watcher.interface(),
+            format!(
+                "{} could not be set up, using {}",
+                NATIVE_INTERFACE,
+                watcher.interface()
+            ),
+        )
+        .hint("poll(2) works, but scales worse with many jobs"),
+        Err(e) => Finding::new(Status::Fail, NATIVE_INTERFACE, e.to_string()),
+    }
+}
+
+// only tried when hosts were given with -f, stdin is not read just for this
+fn check_connectivity(conf: &Config) -> Finding {
+    if conf.host_sources.is_empty() {
+        return Finding::new(
+            Status::Skip,
+            "connect",
+            "no hosts file given with -f".to_string(),
+        );
+    }
+    let hosts = match conf.parse_hosts() {
+        Ok(hosts) => hosts,
+        Err(e) => {
+            return Finding::new(Status::Fail, "connect", e.to_string())
+                .hint("fix the hosts file first")
+        }
+    };
+    let host = match hosts.first() {
+        Some(host) => host.borrow(),
+        None => return Finding::new(Status::Skip, "connect", "hosts file is empty".to_string()),
+    };
+
+    let command = match conf
+        .ssh_options
+        .build_ssh_command(&host, &["true".to_string()])
+    {
+        Ok(command) => command,
+        Err(e) => return Finding::new(Status::Fail, "connect", e.to_string()),
+    };
+    // never wait for a password or host key prompt, that is exactly what hangs runs
+    let output = Command::new(&conf.ssh_options.program)
+        .arg("-o")
+        .arg("BatchMode=yes")
+        .arg("-o")
+        .arg(format!("ConnectTimeout={}", PROBE_CONNECT_TIMEOUT))
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            Finding::new(Status::Ok, "connect", format!("{} reachable", host.name))
+        }
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Finding::new(
+                Status::Fail,
+                "connect",
+                format!(
+                    "{}: {}",
+                    host.name,
+                    stderr.lines().last().unwrap_or("no output")
+                ),
+            )
+            .hint(&format!(
+                "run `ssh -v {}` to see where it stops (host key, auth, network)",
+                host.name
+            ))
+        }
+        Err(e) => Finding::new(Status::Fail, "connect", format!("cannot run ssh: {}", e)),
+    }
+}
+
+// prints one line per check and returns the exit code: 0 when nothing failed, 1 otherwise
+pub fn doctor(conf: &Config) -> u8 {
+    let theme = &conf.theme;
+    let findings = [
+        check_ssh(&conf.ssh_options.program),
+        check_agent(),
+        check_fd_limit(conf.max_jobs),
+        check_fdwatcher(),
+        check_connectivity(conf),
+    ];
+
+    let mut failed = false;
+    for finding in findings.iter() {
+        let (label, color) = match finding.status {
+            Status::Ok => ("ok", &theme.exit_ok),
+            Status::Warn => ("warn", &theme.meta),
+            Status::Fail => ("fail", &theme.exit_fail),
+            Status::Skip => ("skip", &Color::Empty),
+        };
+        failed |= matches!(finding.status, Status::Fail);
+        // padded before coloring, escape codes would count towards the width
+        outln!(
+            "[{}] {} {:<8} {}",
+            PROG_NAME.colorize(&theme.hostname),
+            format!("{:<4}", label).as_str().colorize(color),
+            finding.check,
+            finding.detail
+        );
+        if let Some(hint) = &finding.hint {
+            outln!("{:indent$}{}", "", hint, indent = PROG_NAME.len() + 17);
+        }
+    }
+
+    if failed {
+        1
+    } else {
+        0
+    }
+}
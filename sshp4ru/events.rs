@@ -0,0 +1,81 @@
+use crate::utils::monotonic_time_ms;
+use crate::{Host, PROG_NAME};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::time::Duration;
+
+// a stalled consumer must not hold up the run
+const EVENTS_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// newline delimited JSON, one object per event: run-start, host-start, host-exit, run-end
+pub struct EventSink {
+    path: String,
+    stream: Option<UnixStream>,
+}
+
+impl EventSink {
+    pub fn connect(path: &str) -> io::Result<EventSink> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_write_timeout(Some(EVENTS_WRITE_TIMEOUT))?;
+        Ok(EventSink {
+            path: path.to_string(),
+            stream: Some(stream),
+        })
+    }
+
+    // streaming is best effort: once the consumer goes away the sink is disabled for the run
+    fn emit(&mut self, mut event: Value) {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        event["time_ms"] = json!(monotonic_time_ms() as u64);
+        let mut line = event.to_string();
+        line.push('\n');
+
+        if let Err(e) = stream.write_all(line.as_bytes()) {
+            eprintln!("[{}] events socket {} closed: {}", PROG_NAME, self.path, e);
+            self.stream = None;
+        }
+    }
+
+    pub fn run_started(&mut self, mode: &str, hosts: usize, run_id: &str) {
+        self.emit(json!({
+            "event": "run-start",
+            "run_id": run_id,
+            "mode": mode,
+            "hosts": hosts,
+        }));
+    }
+
+    pub fn host_started(&mut self, host: &Host) {
+        self.emit(json!({ "event": "host-start", "host": &*host.name, "pid": host.cp.pid }));
+    }
+
+    // the pid is passed in as it is cleared once the child has been reaped
+    pub fn host_finished(&mut self, host: &Host, pid: i32) {
+        self.emit(json!({
+            "event": "host-exit",
+            "host": &*host.name,
+            "pid": pid,
+            "exit_code": host.cp.exit_code,
+            "duration_ms": (host.cp.finished_time - host.cp.started_time) as u64,
+        }));
+    }
+
+    pub fn run_finished(&mut self, hosts: &[Rc<RefCell<Host>>]) {
+        let failed = hosts
+            .iter()
+            .filter(|host| host.borrow().cp.exit_code != 0)
+            .count();
+        self.emit(json!({
+            "event": "run-end",
+            "succeeded": hosts.len() - failed,
+            "failed": failed,
+        }));
+    }
+}
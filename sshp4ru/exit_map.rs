@@ -0,0 +1,122 @@
+use crate::diagnose;
+use crate::redact::Redactor;
+use crate::utils::shell_quote;
+use crate::Host;
+use libc::pid_t;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::io;
+use std::rc::Rc;
+
+// --map-exit <code>:[local:|remote:]<cmd>, what to run once a host exits with <code>
+#[derive(Debug, Clone)]
+pub struct ExitRule {
+    pub code: i32,
+    // run here through sh instead of on the host
+    pub local: bool,
+    pub command: String,
+}
+
+impl ExitRule {
+    pub fn from_spec(spec: &str) -> Result<ExitRule, String> {
+        let (code, command) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("`{}` is not <code>:<cmd>", spec))?;
+        let code = match code.parse::<i32>() {
+            Ok(code) if (0..=255).contains(&code) => code,
+            _ => return Err(format!("`{}` is not an exit code", code)),
+        };
+        let (local, command) = match command.split_once(':') {
+            Some(("local", command)) => (true, command),
+            Some(("remote", command)) => (false, command),
+            _ => (false, command),
+        };
+        if command.is_empty() {
+            return Err(format!("`{}` has no command", spec));
+        }
+        Ok(ExitRule {
+            code,
+            local,
+            command: command.to_string(),
+        })
+    }
+
+    // the command of a local rule, {host} and {meta.<key>} filled in quoted so the shell
+    // takes each as one word whatever the inventory holds
+    pub fn local_command(&self, host: &Host) -> Vec<String> {
+        let command = self
+            .command
+            .replace("{host}", &shell_quote(&host.full_name));
+        vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            host.expand_meta_with(&command, shell_quote),
+        ]
+    }
+}
+
+// what a rule did on a host, for the report
+#[derive(Debug)]
+pub struct FollowUpResult {
+    pub code: i32,
+    pub command: String,
+    pub local: bool,
+    pub outcome: Result<(String, i32), String>,
+}
+
+// a rule's command running for a host, queued when the host exits and started like
+// --on-remote-failure
+pub struct FollowUp {
+    host: Rc<RefCell<Host>>,
+    rule: ExitRule,
+    child: diagnose::Background,
+}
+
+impl FollowUp {
+    // `env` is the environment of the ssh children, local commands also learn the host
+    // and its exit code from SSHP4RU_HOST and SSHP4RU_EXIT_CODE
+    pub fn start(
+        host: &Rc<RefCell<Host>>, rule: &ExitRule, command: &[String], env: &[CString],
+    ) -> io::Result<FollowUp> {
+        let mut env = env.to_vec();
+        if rule.local {
+            let name = host.borrow().full_name.to_string();
+            for pair in [
+                format!("SSHP4RU_HOST={}", name),
+                format!("SSHP4RU_EXIT_CODE={}", rule.code),
+            ] {
+                env.push(CString::new(pair).map_err(|_| io::ErrorKind::InvalidInput)?);
+            }
+        }
+        Ok(FollowUp {
+            host: Rc::clone(host),
+            rule: rule.clone(),
+            child: diagnose::spawn(command, &env)?,
+        })
+    }
+
+    pub fn pid(&self) -> pid_t {
+        self.child.pid()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.child.is_finished()
+    }
+
+    pub fn finish(self, redactor: &Redactor) {
+        let outcome = self.child.collect(redactor);
+        self.host
+            .borrow_mut()
+            .follow_ups
+            .push(result(&self.rule, outcome));
+    }
+}
+
+pub fn result(rule: &ExitRule, outcome: Result<(String, i32), String>) -> FollowUpResult {
+    FollowUpResult {
+        code: rule.code,
+        command: rule.command.clone(),
+        local: rule.local,
+        outcome,
+    }
+}
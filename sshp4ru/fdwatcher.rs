@@ -1,16 +1,33 @@
-use crate::utils::{Color, Colorize};
+use crate::buffer_limit::BufferLimit;
+use crate::output_mode::OutputMode;
+use crate::redact::{RedactStream, Redactor};
+use crate::spawn::Spawner;
+use crate::theme::Theme;
+use crate::utils::{monotonic_time_ms, write_stdout, Color, Colorize};
 use crate::RuntimeError;
-use crate::{Host, ProgMode};
+use crate::{CpState, Host};
+#[cfg(epoll)]
 use epoll;
 use nix::unistd::close;
+use regex::bytes;
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::fs::File;
+use std::hash::Hasher;
 use std::io::{self, Write};
 use std::os::fd::RawFd;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
 
-#[cfg(feature = "USE_KQUEUE")]
+#[cfg(kqueue)]
 use nix::sys::event::{EventFilter, EventFlag, FilterFlag, KEvent, Kqueue};
 
+// a line this long without a newline is matched as it is
+const MATCH_LINE_LIMIT: usize = 64 * 1024;
+// enough for the last message of ssh
+const STDERR_TAIL_LENGTH: usize = 512;
+
 #[derive(Debug, Clone)]
 pub enum PipeType {
     StdOut = 0,
@@ -18,6 +35,35 @@ pub enum PipeType {
     StdIO,
 }
 
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    pub silent: bool,
+    pub max_line_length: u16,
+    pub max_output_length: u16,
+    pub anonymous: bool,
+    pub colorize: bool,
+    // `[hostname]` prefixes are padded to this width in line mode (0 disables padding)
+    pub prefix_width: usize,
+    // prefix each line with `O|` or `E|` in line mode so streams survive without color
+    pub mark_streams: bool,
+    // line mode prints at most this many lines per host
+    pub max_lines: Option<u32>,
+    // line mode prints a host's lines in blocks of this many, or up to a matching line
+    pub atomic_lines: Option<u32>,
+    pub atomic_until: Option<bytes::Regex>,
+    // the host is terminated once it prints a matching line
+    pub kill_on_output: Option<bytes::Regex>,
+    // the whole run is stopped once any host prints a matching line
+    pub abort_on_output: Option<bytes::Regex>,
+    // group mode collects the output in the host's buffer instead of printing it
+    pub buffered: bool,
+    // what the join and --buffered buffers keep of a host, unbounded without it
+    pub buffer_limit: Option<BufferLimit>,
+    pub theme: Theme,
+    // cleared once --total-output-limit is crossed, join mode then stops collecting output
+    pub buffer_output: bool,
+}
+
 #[derive(Debug)]
 pub struct FdEvent {
     host: Rc<RefCell<Host>>,
@@ -25,6 +71,17 @@ pub struct FdEvent {
     buffer: String,
     offset: usize,
     event_type: PipeType,
+    // output not yet matched against the --kill-on-output and --abort-on-output patterns,
+    // a partial line
+    match_buffer: Vec<u8>,
+    // rendered lines held back until the --atomic-lines block is complete
+    block: String,
+    block_lines: u32,
+    // --output-dir file the pipe is copied to, whatever the display mode does with it
+    output_file: Option<(PathBuf, File)>,
+    // partial lines held back from redaction, for the display and the --output-dir file
+    redact_stream: RedactStream,
+    file_redact_stream: RedactStream,
 }
 
 impl FdEvent {
@@ -36,6 +93,12 @@ impl FdEvent {
             offset: 0,
             fd: 0,
             event_type: event_type,
+            match_buffer: Vec::new(),
+            block: String::new(),
+            block_lines: 0,
+            output_file: None,
+            redact_stream: RedactStream::default(),
+            file_redact_stream: RedactStream::default(),
         };
         //different type of buffering will be implemented on subsequent layers.
         match ev_type {
@@ -48,13 +111,29 @@ impl FdEvent {
         fdev
     }
 
+    // `<dir>/<host>.out` or `.err`, truncated so a retried host keeps its last attempt;
+    // join mode has one combined pipe and writes it to `.out`
+    pub fn write_to_dir(&mut self, dir: &Path) -> Result<(), RuntimeError> {
+        let extension = match self.event_type {
+            PipeType::StdErr => "err",
+            _ => "out",
+        };
+        let name = self.host.borrow().full_name.replace('/', "_");
+        let path = dir.join(format!("{}.{}", name, extension));
+        let file = File::create(&path)
+            .map_err(|e| RuntimeError::OutputDirError(path.display().to_string(), e))?;
+        self.output_file = Some((path, file));
+        Ok(())
+    }
+
     pub fn read_active_fd(
-        &mut self, watcher: &Fdwatcher, last_host: &mut Option<String>, newline_print: &mut bool,
-        config_params: impl FnOnce() -> (bool, ProgMode, u16, u16, bool, bool),
+        &mut self, watcher: &Fdwatcher, spawner: &dyn Spawner, mode: &mut dyn OutputMode,
+        newline_print: &mut bool, redactor: &Redactor,
+        config_params: impl FnOnce() -> OutputConfig,
     ) -> Result<bool, RuntimeError> {
         let mut buffer = [0u8; 8192];
-        let (silent, mode, max_line_length, max_output_length, anonymous_opt, colorize) =
-            config_params();
+        let config = config_params();
+        mode.read_started();
 
         let mut fd: RawFd = match self.event_type {
             PipeType::StdIO => self.host.borrow_mut().cp.stdio_fd,
@@ -63,7 +142,12 @@ impl FdEvent {
         };
 
         loop {
-            match nix::unistd::read(fd, &mut buffer) {
+            // a pty master (--tty) reads EIO instead of EOF once the child side is closed
+            let read = match nix::unistd::read(fd, &mut buffer) {
+                Err(nix::errno::Errno::EIO) if matches!(self.event_type, PipeType::StdIO) => Ok(0),
+                read => read,
+            };
+            match read {
                 Ok(0) => {
                     watcher.remove(fd)?;
                     if let Err(e) = close(fd) {
@@ -77,45 +161,58 @@ impl FdEvent {
                         PipeType::StdErr => self.host.borrow_mut().cp.stderr_fd = fd,
                     }
 
-                    match mode {
-                        ProgMode::Join => self.output_join_buf(max_output_length),
-                        ProgMode::Group => (),
-                        ProgMode::Line => self.output_line_buf(anonymous_opt, colorize),
+                    // the last line may lack a newline
+                    self.match_output(b"\n", spawner, &config, redactor);
+                    if let Some((path, file)) = self.output_file.as_mut() {
+                        file.write_all(&self.file_redact_stream.finish(redactor))
+                            .map_err(|e| {
+                                RuntimeError::OutputDirError(path.display().to_string(), e)
+                            })?;
                     }
 
+                    mode.pipe_closed(self, &config, redactor, newline_print)?;
+
                     return Ok(true);
                 }
 
                 Ok(bytes_read) => {
-                    if silent {
+                    // accounted even when silent, for receipts and output quotas
+                    {
+                        let mut host = self.host.borrow_mut();
+                        host.cp
+                            .first_output_time
+                            .get_or_insert_with(monotonic_time_ms);
+                        host.cp.output_digest.write(&buffer[..bytes_read]);
+                        host.cp.output_bytes += bytes_read as u64;
+                        if matches!(self.event_type, PipeType::StdErr) {
+                            host.cp.stderr_bytes += bytes_read as u64;
+                        }
+                        if !matches!(self.event_type, PipeType::StdOut) {
+                            let tail = &mut host.cp.stderr_tail;
+                            tail.extend_from_slice(&buffer[..bytes_read]);
+                            tail.drain(..tail.len().saturating_sub(STDERR_TAIL_LENGTH));
+                        }
+                    }
+                    self.match_output(&buffer[..bytes_read], spawner, &config, redactor);
+                    if let Some((path, file)) = self.output_file.as_mut() {
+                        let redacted = self
+                            .file_redact_stream
+                            .push(redactor, &buffer[..bytes_read]);
+                        file.write_all(&redacted).map_err(|e| {
+                            RuntimeError::OutputDirError(path.display().to_string(), e)
+                        })?;
+                    }
+                    if config.silent {
                         continue;
                     }
 
-                    match mode {
-                        ProgMode::Join => self.process_join_buf(
-                            &buffer[..bytes_read],
-                            max_line_length,
-                            max_output_length,
-                        ),
-                        ProgMode::Group => {
-                            if let Err(_) = self.process_group_buf(
-                                &buffer[..bytes_read],
-                                &last_host,
-                                anonymous_opt,
-                                newline_print,
-                                colorize,
-                            ) {
-                                return Err(RuntimeError::WriteStreamError);
-                            }
-                            *last_host = Some(self.host.borrow().name.clone());
-                        }
-                        ProgMode::Line => self.process_line_buf(
-                            &buffer[..bytes_read],
-                            max_line_length,
-                            anonymous_opt,
-                            colorize,
-                        ),
-                    }
+                    mode.output(
+                        self,
+                        &buffer[..bytes_read],
+                        &config,
+                        redactor,
+                        newline_print,
+                    )?;
                 }
 
                 Err(e) => {
@@ -137,18 +234,85 @@ impl FdEvent {
         self.host.clone()
     }
 
-    fn output_join_buf(&mut self, max_output_length: u16) {
-        if self.offset <= max_output_length as usize {
-            if !self.buffer.ends_with("\n") {
-                self.buffer.push('\n');
-                self.offset += 1;
-            }
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    pub fn pipe_type(&self) -> &PipeType {
+        &self.event_type
+    }
+
+    // complete lines of output are matched, the partial last one waits for the next read
+    fn match_output(
+        &mut self, data: &[u8], spawner: &dyn Spawner, config: &OutputConfig, redactor: &Redactor,
+    ) {
+        if config.kill_on_output.is_none() && config.abort_on_output.is_none() {
+            return;
+        }
+        self.match_buffer.extend_from_slice(data);
+        let end = match self.match_buffer.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None if self.match_buffer.len() >= MATCH_LINE_LIMIT => self.match_buffer.len(),
+            None => return,
+        };
+        let lines: Vec<u8> = self.match_buffer.drain(..end).collect();
+        let first_match = |pattern: &Option<bytes::Regex>| {
+            let pattern = pattern.as_ref()?;
+            let line = lines
+                .split(|&b| b == b'\n')
+                .find(|line| pattern.is_match(line))?;
+            let line = String::from_utf8_lossy(line);
+            Some(redactor.redact(line.trim_end()).into_owned())
+        };
+
+        let mut host = self.host.borrow_mut();
+        // the run loop stops everything once it sees this
+        if host.cp.abort_output.is_none() {
+            host.cp.abort_output = first_match(&config.abort_on_output);
+        }
+        if host.cp.matched_output.is_some() {
+            return;
+        }
+        host.cp.matched_output = first_match(&config.kill_on_output);
+        if host.cp.matched_output.is_none() {
+            return;
+        }
+        if let CpState::Running = host.cp.state {
+            let _ = spawner.signal(host.cp.pid, nix::sys::signal::Signal::SIGTERM);
+        }
+    }
+
+    pub fn output_join_buf(&mut self, config: &OutputConfig, redactor: &Redactor) {
+        if (self.offset <= config.max_output_length as usize || config.buffer_limit.is_some())
+            && !self.buffer.ends_with("\n")
+        {
+            self.buffer.push('\n');
+            self.offset += 1;
         }
         // explicitly move buffer to output_buffer of host to avoid unnecessary copying
-        self.host.borrow_mut().cp.output_buffer = std::mem::take(&mut self.buffer);
+        let buffer = std::mem::take(&mut self.buffer);
+        let redacted = match redactor.redact(&buffer) {
+            Cow::Owned(redacted) => Some(redacted),
+            Cow::Borrowed(_) => None,
+        };
+        self.host.borrow_mut().cp.output_buffer = redacted.unwrap_or(buffer);
     }
 
-    fn process_join_buf(&mut self, buffer: &[u8], max_line_length: u16, max_output_length: u16) {
+    pub fn process_join_buf(&mut self, buffer: &[u8], config: &OutputConfig) {
+        let (max_line_length, max_output_length) =
+            (config.max_line_length, config.max_output_length);
+        // the limit replaces --max-output-length, with the part of the output it keeps
+        if let Some(limit) = config.buffer_limit {
+            let data: String = buffer
+                .iter()
+                .map(|ch| if ch.is_ascii() { *ch as char } else { '?' })
+                .collect();
+            let mut host = self.host.borrow_mut();
+            limit.append(&mut self.buffer, &data, &mut host.cp.dropped);
+            host.cp.truncated |= host.cp.dropped.bytes > 0;
+            self.offset = self.buffer.len();
+            return;
+        }
         for ch in buffer.iter() {
             if self.offset < max_output_length as usize {
                 let ch_ascii = if ch.is_ascii() { *ch as char } else { '?' };
@@ -159,32 +323,91 @@ impl FdEvent {
                 self.buffer.push('\n');
                 self.offset += 1;
             } else {
+                self.host.borrow_mut().cp.truncated = true;
                 break;
             }
         }
     }
 
-    fn process_group_buf(
-        &mut self, buffer: &[u8], last_host: &Option<String>, anonymous_opt: bool,
-        newline_print: &mut bool, colorize: bool,
+    pub fn buffer_group_buf(&mut self, buffer: &[u8], config: &OutputConfig, redactor: &Redactor) {
+        let redacted = self.redact_stream.push(redactor, buffer);
+        self.append_group_chunk(&redacted, config);
+    }
+
+    fn append_group_chunk(&mut self, redacted: &[u8], config: &OutputConfig) {
+        if redacted.is_empty() {
+            return;
+        }
+        let mut chunk = String::new();
+        if config.colorize {
+            let color = match self.event_type {
+                PipeType::StdOut => config.theme.stdout.as_str(),
+                PipeType::StdErr => config.theme.stderr.as_str(),
+                _ => Color::Reset.as_str(),
+            };
+            chunk.push_str(&color);
+        }
+        chunk.push_str(&String::from_utf8_lossy(redacted));
+        if config.colorize {
+            chunk.push_str(&Color::Reset.as_str());
+        }
+        let mut host = self.host.borrow_mut();
+        match config.buffer_limit {
+            Some(limit) => {
+                let cp = &mut *host.cp;
+                limit.append(&mut cp.output_buffer, &chunk, &mut cp.dropped);
+                cp.truncated |= cp.dropped.bytes > 0;
+            }
+            None => host.cp.output_buffer.push_str(&chunk),
+        }
+    }
+
+    pub fn process_group_buf(
+        &mut self, buffer: &[u8], last_host: &Option<Arc<str>>, newline_print: &mut bool,
+        config: &OutputConfig, redactor: &Redactor,
+    ) -> io::Result<()> {
+        let redacted = self.redact_stream.push(redactor, buffer);
+        self.print_group_chunk(&redacted, last_host, newline_print, config)
+    }
+
+    // whatever the redaction still holds back once the pipe is closed
+    pub fn flush_group_buf(
+        &mut self, last_host: &Option<Arc<str>>, newline_print: &mut bool, config: &OutputConfig,
+        redactor: &Redactor,
     ) -> io::Result<()> {
-        let cyan = if colorize { Color::Cyan } else { Color::Empty };
+        let redacted = self.redact_stream.finish(redactor);
+        if config.buffered {
+            self.append_group_chunk(&redacted, config);
+            return Ok(());
+        }
+        self.print_group_chunk(&redacted, last_host, newline_print, config)
+    }
+
+    fn print_group_chunk(
+        &mut self, buffer: &[u8], last_host: &Option<Arc<str>>, newline_print: &mut bool,
+        config: &OutputConfig,
+    ) -> io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let (anonymous_opt, colorize, theme) = (config.anonymous, config.colorize, &config.theme);
+        let cyan = &theme.hostname;
         //maybe somewhat ugly but gets rid of potential unsafe mutation on static last_host and newline_print
         if let Some(last_host) = last_host {
-            if last_host.as_str() != self.host.borrow().name.as_str() {
+            if **last_host != *self.host.borrow().name {
                 if !*newline_print {
-                    println!();
+                    outln!();
                 }
                 if !anonymous_opt {
-                    println!("[{}]", self.host.borrow().name.as_str().colorize(&cyan));
+                    outln!("[{}]", self.host.borrow().name.as_ref().colorize(cyan));
                 }
             }
         } else {
             if !*newline_print {
-                println!();
+                outln!();
             }
             if !anonymous_opt {
-                println!("[{}]", self.host.borrow().name.as_str().colorize(&cyan));
+                outln!("[{}]", self.host.borrow().name.as_ref().colorize(cyan));
             }
         }
 
@@ -192,29 +415,26 @@ impl FdEvent {
             Color::Empty.as_str()
         } else {
             match self.event_type {
-                PipeType::StdOut => Color::Green.as_str(),
-                PipeType::StdErr => Color::Red.as_str(),
+                PipeType::StdOut => theme.stdout.as_str(),
+                PipeType::StdErr => theme.stderr.as_str(),
                 _ => Color::Reset.as_str(),
             }
         };
-        let mut writer = io::BufWriter::new(io::stdout().lock());
-        writer.flush()?;
-
-        writer.write(color.as_bytes())?;
-        writer.write(buffer)?;
+        let mut chunk = color.as_bytes().to_vec();
+        chunk.extend_from_slice(buffer);
         if colorize {
-            writer.write(Color::Reset.as_str().as_bytes())?;
+            chunk.extend_from_slice(Color::Reset.as_str().as_bytes());
         }
+        write_stdout(&chunk)?;
 
         *newline_print = buffer[buffer.len() - 1] != b'\n';
 
         Ok(())
     }
 
-    fn process_line_buf(
-        &mut self, buffer: &[u8], max_line_length: u16, anonymous_opt: bool, colorize: bool,
-    ) {
-        // println!("{}", buffer.len());
+    pub fn process_line_buf(&mut self, buffer: &[u8], config: &OutputConfig, redactor: &Redactor) {
+        let max_line_length = config.max_line_length;
+        // outln!("{}", buffer.len());
         for ch in buffer.iter() {
             if self.offset < max_line_length as usize {
                 let ch_ascii = if ch.is_ascii() { *ch as char } else { '?' };
@@ -224,178 +444,328 @@ impl FdEvent {
                 self.buffer.push('\n');
                 self.offset += 1;
             }
+            if self.offset > max_line_length as usize && *ch != b'\n' {
+                self.host.borrow_mut().cp.truncated = true;
+            }
 
             if *ch == b'\n' {
                 assert!(self.offset > 0);
                 assert!(self.offset < max_line_length as usize + 2);
-                self.print_line_buffer(anonymous_opt, colorize);
+                self.print_line_buffer(config, redactor);
                 self.offset = 0;
                 self.buffer.clear();
             }
         }
     }
 
-    fn output_line_buf(&mut self, anonymous_opt: bool, colorize: bool) {
-        if self.offset == 0 {
-            return;
+    pub fn output_line_buf(&mut self, config: &OutputConfig, redactor: &Redactor) {
+        if self.offset != 0 {
+            self.print_line_buffer(config, redactor);
+            self.offset = 0;
         }
+        // an unfinished block is printed as it is once the host closes the pipe
+        out!("{}", std::mem::take(&mut self.block));
+        self.block_lines = 0;
+    }
 
-        self.print_line_buffer(anonymous_opt, colorize);
-        self.offset = 0;
+    // without --atomic-lines/--atomic-until every line is printed right away
+    fn emit_line(&mut self, line: &str, config: &OutputConfig) {
+        if config.atomic_lines.is_none() && config.atomic_until.is_none() {
+            out!("{}", line);
+            return;
+        }
+        self.block.push_str(line);
+        self.block_lines += 1;
+        let until = config
+            .atomic_until
+            .as_ref()
+            .is_some_and(|pattern| pattern.is_match(self.buffer.trim_end_matches('\n').as_bytes()));
+        if until || config.atomic_lines.is_some_and(|n| self.block_lines >= n) {
+            out!("{}", std::mem::take(&mut self.block));
+            self.block_lines = 0;
+        }
     }
 
-    fn print_line_buffer(&self, anonymous_option: bool, colorize: bool) {
-        let (color, cyan) = if !colorize {
+    fn print_line_buffer(&mut self, config: &OutputConfig, redactor: &Redactor) {
+        if let Some(max_lines) = config.max_lines {
+            let mut host = self.host.borrow_mut();
+            host.cp.lines_printed += 1;
+            if host.cp.lines_printed > max_lines {
+                // said once, the rest of the output is read and dropped
+                if host.cp.lines_printed == max_lines + 1 {
+                    host.cp.truncated = true;
+                    let (cyan, magenta) = if config.colorize {
+                        (config.theme.hostname.clone(), config.theme.meta.clone())
+                    } else {
+                        (Color::Empty, Color::Empty)
+                    };
+                    let notice = format!("(--max-lines {} reached, omitting the rest)", max_lines);
+                    let notice = if config.anonymous {
+                        format!("{}\n", notice.as_str().colorize(&magenta))
+                    } else {
+                        format!(
+                            "[{}] {}\n",
+                            host.name.as_ref().colorize(&cyan),
+                            notice.as_str().colorize(&magenta)
+                        )
+                    };
+                    drop(host);
+                    self.emit_line(&notice, config);
+                }
+                return;
+            }
+        }
+
+        let (color, cyan) = if !config.colorize {
             (Color::Empty, Color::Empty)
         } else {
             (
                 match self.event_type {
-                    PipeType::StdOut => Color::Green,
-                    PipeType::StdErr => Color::Red,
+                    PipeType::StdOut => config.theme.stdout.clone(),
+                    PipeType::StdErr => config.theme.stderr.clone(),
                     _ => Color::Reset,
                 },
-                Color::Cyan,
+                config.theme.hostname.clone(),
             )
         };
 
-        if !anonymous_option {
-            print!("[{}] ", self.host.borrow().name.as_str().colorize(&cyan));
+        let mut rendered = String::new();
+        if !config.anonymous {
+            let host = self.host.borrow();
+            let padding = config
+                .prefix_width
+                .saturating_sub(host.name.chars().count());
+            rendered.push_str(&format!(
+                "[{}]{:padding$} ",
+                host.name.as_ref().colorize(&cyan),
+                "",
+                padding = padding
+            ));
         }
 
-        if let Some(last_char) = self.buffer.chars().rev().next() {
+        if config.mark_streams {
+            match self.event_type {
+                PipeType::StdOut => rendered.push_str("O| "),
+                PipeType::StdErr => rendered.push_str("E| "),
+                PipeType::StdIO => (),
+            }
+        }
+
+        let line = redactor.redact(&self.buffer);
+        if let Some(last_char) = line.chars().next_back() {
+            rendered.push_str(&line.as_ref().colorize(&color));
             if last_char != '\n' {
-                println!("{}", self.buffer.as_str().colorize(&color));
-            } else {
-                print!("{}", self.buffer.as_str().colorize(&color));
+                rendered.push('\n');
             }
+            self.emit_line(&rendered, config);
         }
     }
 }
 
+// the interface the platform is built for, Fdwatcher::new falls back to poll(2) when it
+// cannot be set up at runtime; only doctor asks
+#[cfg(all(feature = "cli", epoll))]
+pub const NATIVE_INTERFACE: &str = "epoll";
+#[cfg(all(feature = "cli", kqueue))]
+pub const NATIVE_INTERFACE: &str = "kqueue";
+#[cfg(all(feature = "cli", not(any(epoll, kqueue))))]
+pub const NATIVE_INTERFACE: &str = "poll";
+
+#[derive(Debug)]
+enum Backend {
+    #[cfg(epoll)]
+    Epoll(i32),
+    #[cfg(kqueue)]
+    Kqueue(Kqueue),
+    // the watched fds, handed to poll(2) on every wait
+    Poll(RefCell<Vec<RawFd>>),
+}
+
 #[derive(Debug)]
 pub struct Fdwatcher {
-    #[cfg(feature = "USE_KQUEUE")]
-    kq: kqueue::Watcher,
-    #[cfg(not(feature = "USE_KQUEUE"))]
-    epoll: i32,
+    backend: Backend,
 }
 
 impl Fdwatcher {
-    #[cfg(feature = "USE_KQUEUE")]
-    pub fn new() -> Self {
-        Self::new_kqueue()
-    }
-
-    #[cfg(not(feature = "USE_KQUEUE"))]
     pub fn new() -> io::Result<Self> {
-        Ok(Self::new_epoll()?)
+        let backend = Self::new_native().unwrap_or_else(|_| Backend::Poll(RefCell::default()));
+        Ok(Self { backend })
     }
 
-    #[cfg(not(feature = "USE_KQUEUE"))]
-    fn new_epoll() -> io::Result<Self> {
-        let epoll_fd = epoll::create(true)?;
-        Ok(Self { epoll: epoll_fd })
+    #[cfg(epoll)]
+    fn new_native() -> io::Result<Backend> {
+        Ok(Backend::Epoll(epoll::create(true)?))
     }
 
-    #[cfg(feature = "USE_KQUEUE")]
-    fn new_kqueue() -> io::Result<Self> {
-        let kq = Kqueue::new()?;
-
-        Ok(Self { kq: kq })
+    #[cfg(kqueue)]
+    fn new_native() -> io::Result<Backend> {
+        Ok(Backend::Kqueue(Kqueue::new()?))
     }
 
-    #[cfg(feature = "USE_KQUEUE")]
-    pub fn add(&mut self, monitor_fd: i32) -> io::Result<()> {
-        //not implemented yet. Probably doesn't work as expected
-        let event = KEvent::new(
-            monitor_fd as u64,
-            EventFilter::EVFILT_READ,
-            EventFlag::EV_ADD,
-            FilterFlag::empty(),
-            0,
-            0,
-        );
-        self.kq.kevent(&[event], &[], None)?;
-
-        Ok(())
+    #[cfg(not(any(epoll, kqueue)))]
+    fn new_native() -> io::Result<Backend> {
+        Ok(Backend::Poll(RefCell::default()))
     }
 
-    #[cfg(not(feature = "USE_KQUEUE"))]
-    pub fn add(&self, monitor_fd: i32) -> io::Result<()> {
-        let event = epoll::Event::new(epoll::Events::EPOLLIN, monitor_fd as u64);
-        if let Err(e) = epoll::ctl(
-            self.epoll,
-            epoll::ControlOptions::EPOLL_CTL_ADD,
-            monitor_fd,
-            event,
-        ) {
-            Err(e)
-        } else {
-            Ok(())
+    // what the watcher actually runs on
+    pub fn interface(&self) -> &'static str {
+        match self.backend {
+            #[cfg(epoll)]
+            Backend::Epoll(_) => "epoll",
+            #[cfg(kqueue)]
+            Backend::Kqueue(_) => "kqueue",
+            Backend::Poll(_) => "poll",
         }
     }
 
-    #[cfg(feature = "USE_KQUEUE")]
-    pub fn wait(
-        &self, completed_events: &mut [RawFd], num_events: usize, timeout: i32,
-    ) -> io::Result<()> {
-        //not implemented yet. Probably doesn't work as expected
-
-        let mut num_completed_events: usize = 0;
-
-        while num_completed_events < num_events {
-            if let Some(event) = self.kq.poll_forever(None) {
-                match event {
-                    kqueue::Ident::Fd(fd) => {
-                        completed_events[num_completed_events] = fd;
-                    }
-                    _ => return Err(io::Error::new(io::ErrorKind::Other, "Invalid event type")),
-                }
+    pub fn add(&self, monitor_fd: i32) -> io::Result<()> {
+        match &self.backend {
+            #[cfg(epoll)]
+            Backend::Epoll(epoll_fd) => {
+                let event = epoll::Event::new(epoll::Events::EPOLLIN, monitor_fd as u64);
+                epoll::ctl(
+                    *epoll_fd,
+                    epoll::ControlOptions::EPOLL_CTL_ADD,
+                    monitor_fd,
+                    event,
+                )
+            }
+            #[cfg(kqueue)]
+            Backend::Kqueue(kq) => {
+                let event = KEvent::new(
+                    monitor_fd as usize,
+                    EventFilter::EVFILT_READ,
+                    EventFlag::EV_ADD,
+                    FilterFlag::empty(),
+                    0,
+                    0,
+                );
+                kq.kevent(&[event], &mut [], None)?;
+                Ok(())
+            }
+            Backend::Poll(fds) => {
+                fds.borrow_mut().push(monitor_fd);
+                Ok(())
             }
-            num_completed_events += 1;
         }
-        Ok(num_completed_events)
     }
 
-    #[cfg(not(feature = "USE_KQUEUE"))]
+    // a signal (SIGUSR1 prints the status) ends the wait early with no events, SA_RESTART
+    // does not apply to any of these
     pub fn wait(
         &self, completed_events: &mut [RawFd], num_events: usize, timeout: i32,
     ) -> Result<usize, RuntimeError> {
-        let mut epoll_events = vec![epoll::Event::new(epoll::Events::empty(), 0); num_events];
-        // // epoll::wait, unlike epoll_wait() (libc) does not take a max events argument,
-        // // it calculates it internally from the size of the given slice (here epoll_events)
-        let num_completed_events = match epoll::wait(self.epoll, timeout, &mut epoll_events) {
-            Ok(n) => n,
-            Err(e) => return Err(RuntimeError::EpollWaitError(e)),
+        let num_events = num_events.min(completed_events.len());
+        let num_completed_events = match &self.backend {
+            #[cfg(epoll)]
+            Backend::Epoll(epoll_fd) => {
+                let mut epoll_events =
+                    vec![epoll::Event::new(epoll::Events::empty(), 0); num_events];
+                // epoll::wait, unlike epoll_wait() (libc) does not take a max events argument,
+                // it calculates it internally from the size of the given slice (here epoll_events)
+                let num = match epoll::wait(*epoll_fd, timeout, &mut epoll_events) {
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+                    Err(e) => return Err(RuntimeError::EpollWaitError(e)),
+                };
+                for (i, event) in epoll_events[0..num].iter().enumerate() {
+                    completed_events[i] = event.data as i32;
+                }
+                num
+            }
+            #[cfg(kqueue)]
+            Backend::Kqueue(kq) => {
+                let empty = KEvent::new(
+                    0,
+                    EventFilter::EVFILT_READ,
+                    EventFlag::empty(),
+                    FilterFlag::empty(),
+                    0,
+                    0,
+                );
+                let mut events = vec![empty; num_events];
+                let timeout = (timeout >= 0).then(|| libc::timespec {
+                    tv_sec: (timeout / 1000) as libc::time_t,
+                    tv_nsec: ((timeout % 1000) * 1_000_000) as libc::c_long,
+                });
+                let num = match kq.kevent(&[], &mut events, timeout) {
+                    Ok(n) => n,
+                    Err(nix::errno::Errno::EINTR) => 0,
+                    Err(e) => return Err(RuntimeError::EpollWaitError(e.into())),
+                };
+                for (i, event) in events[0..num].iter().enumerate() {
+                    completed_events[i] = event.ident() as RawFd;
+                }
+                num
+            }
+            Backend::Poll(fds) => {
+                let mut poll_fds: Vec<libc::pollfd> = fds
+                    .borrow()
+                    .iter()
+                    .map(|&fd| libc::pollfd {
+                        fd,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    })
+                    .collect();
+                let ready = unsafe {
+                    libc::poll(
+                        poll_fds.as_mut_ptr(),
+                        poll_fds.len() as libc::nfds_t,
+                        timeout,
+                    )
+                };
+                if ready < 0 {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        return Ok(0);
+                    }
+                    return Err(RuntimeError::EpollWaitError(e));
+                }
+                // a closed write end shows up as POLLHUP, reading it then returns EOF
+                let ready = poll_fds.iter().filter(|poll_fd| poll_fd.revents != 0);
+                let mut num = 0;
+                for (slot, poll_fd) in completed_events[..num_events].iter_mut().zip(ready) {
+                    *slot = poll_fd.fd;
+                    num += 1;
+                }
+                num
+            }
         };
-
-        for (i, event) in epoll_events[0..num_completed_events].iter().enumerate() {
-            completed_events[i] = event.data as i32;
-        }
-
         Ok(num_completed_events)
     }
 
-    #[cfg(feature = "USE_KQUEUE")]
-    fn remove(&self, monitor_fd: i32) -> io::Result<()> {
-        //not implemented yet. Probably doesn't work as expected
-        self.kq
-            .remove_fd(monitor_fd, kqueue::EventFilter::EVFILT_READ)?;
-        Ok(())
-    }
-
-    #[cfg(not(feature = "USE_KQUEUE"))]
     fn remove(&self, monitor_fd: i32) -> Result<(), RuntimeError> {
-        let event = epoll::Event::new(epoll::Events::EPOLLIN, monitor_fd as u64);
-        if let Err(_) = epoll::ctl(
-            self.epoll,
-            epoll::ControlOptions::EPOLL_CTL_DEL,
-            monitor_fd,
-            event,
-        ) {
-            Err(RuntimeError::MonitorFdError("EPOLL_CTL_DEL".to_string()))
-        } else {
-            Ok(())
+        match &self.backend {
+            #[cfg(epoll)]
+            Backend::Epoll(epoll_fd) => {
+                let event = epoll::Event::new(epoll::Events::EPOLLIN, monitor_fd as u64);
+                epoll::ctl(
+                    *epoll_fd,
+                    epoll::ControlOptions::EPOLL_CTL_DEL,
+                    monitor_fd,
+                    event,
+                )
+                .map_err(|_| RuntimeError::MonitorFdError("EPOLL_CTL_DEL".to_string()))
+            }
+            #[cfg(kqueue)]
+            Backend::Kqueue(kq) => {
+                let event = KEvent::new(
+                    monitor_fd as usize,
+                    EventFilter::EVFILT_READ,
+                    EventFlag::EV_DELETE,
+                    FilterFlag::empty(),
+                    0,
+                    0,
+                );
+                kq.kevent(&[event], &mut [], None)
+                    .map(|_| ())
+                    .map_err(|_| RuntimeError::MonitorFdError("EV_DELETE".to_string()))
+            }
+            Backend::Poll(fds) => {
+                fds.borrow_mut().retain(|&fd| fd != monitor_fd);
+                Ok(())
+            }
         }
     }
 }
@@ -0,0 +1,156 @@
+use crate::collect::Collector;
+use crate::diagnose::Diagnostic;
+use crate::exit_map::{self, ExitRule, FollowUp};
+use crate::warnings::Warnings;
+use crate::Host;
+use libc::pid_t;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// what runs for a host once its command is done: --collect, --on-remote-failure and
+// --map-exit. The jobs wait in a queue and take a slot of -m when they start, so
+// --per-jump-limit, --serialize-per-host and the open files limit hold for them as well
+pub enum Job {
+    // the directory the files go to
+    Collect(PathBuf),
+    Diagnose,
+    MapExit(ExitRule),
+}
+
+pub struct Queued {
+    pub host: Rc<RefCell<Host>>,
+    pub job: Job,
+    pub command: Vec<String>,
+}
+
+impl Queued {
+    // a local --map-exit command does not connect to the host, only -m holds for it
+    pub fn remote(&self) -> bool {
+        !matches!(&self.job, Job::MapExit(rule) if rule.local)
+    }
+
+    fn start(self, env: &[CString], warnings: &Warnings) -> Option<Running> {
+        let started = match &self.job {
+            Job::Collect(dir) => {
+                let name = self.host.borrow().full_name.to_string();
+                Collector::start(&name, &self.command, dir, env).map(Running::Collect)
+            }
+            Job::Diagnose => {
+                Diagnostic::start(&self.host, &self.command, env).map(Running::Diagnose)
+            }
+            Job::MapExit(rule) => {
+                FollowUp::start(&self.host, rule, &self.command, env).map(Running::MapExit)
+            }
+        };
+        match started {
+            Ok(running) => Some(running),
+            Err(e) => {
+                self.fail(e.to_string(), warnings);
+                None
+            }
+        }
+    }
+
+    // a job that was not run is recorded as failed where its result would go
+    fn fail(self, error: String, warnings: &Warnings) {
+        match self.job {
+            Job::Collect(_) => warnings.push(
+                "collect-failed",
+                format!(
+                    "collecting from {} failed: {}",
+                    self.host.borrow().full_name,
+                    error
+                ),
+            ),
+            Job::Diagnose => self.host.borrow_mut().diagnostics = Some(Err(error)),
+            Job::MapExit(rule) => self
+                .host
+                .borrow_mut()
+                .follow_ups
+                .push(exit_map::result(&rule, Err(error))),
+        }
+    }
+}
+
+pub enum Running {
+    Collect(Collector),
+    Diagnose(Diagnostic),
+    MapExit(FollowUp),
+}
+
+impl Running {
+    // the pid its slot is held under
+    pub fn pid(&self) -> pid_t {
+        match self {
+            Running::Collect(collector) => collector.pid(),
+            Running::Diagnose(diagnostic) => diagnostic.pid(),
+            Running::MapExit(follow_up) => follow_up.pid(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        match self {
+            Running::Collect(collector) => collector.is_finished(),
+            Running::Diagnose(diagnostic) => diagnostic.is_finished(),
+            Running::MapExit(follow_up) => follow_up.is_finished(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FollowUps {
+    queue: VecDeque<Queued>,
+    running: Vec<Running>,
+}
+
+impl FollowUps {
+    pub fn push(&mut self, queued: Queued) {
+        self.queue.push_back(queued);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.running.is_empty()
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    // the first queued job `free` has a slot for, in the order they were queued
+    pub fn take_next(&mut self, free: impl Fn(&Queued) -> bool) -> Option<Queued> {
+        let index = self.queue.iter().position(free)?;
+        self.queue.remove(index)
+    }
+
+    // the pid the job's slot is held under, None when it could not be started
+    pub fn start(&mut self, queued: Queued, env: &[CString], warnings: &Warnings) -> Option<pid_t> {
+        let running = queued.start(env, warnings)?;
+        let pid = running.pid();
+        self.running.push(running);
+        Some(pid)
+    }
+
+    // a run that stops early: the jobs still waiting are recorded as not run, the running
+    // ones are killed and handed back to be reaped and recorded like finished ones
+    pub fn stop(&mut self, warnings: &Warnings) -> Vec<Running> {
+        for queued in self.queue.drain(..) {
+            queued.fail("the run was stopped first".to_string(), warnings);
+        }
+        for running in self.running.iter().filter(|running| !running.is_finished()) {
+            let _ = signal::killpg(Pid::from_raw(running.pid()), Signal::SIGTERM);
+        }
+        std::mem::take(&mut self.running)
+    }
+
+    // the jobs that are done, no longer counted as running
+    pub fn take_finished(&mut self) -> Vec<Running> {
+        let (finished, running) = self.running.drain(..).partition(Running::is_finished);
+        self.running = running;
+        finished
+    }
+}
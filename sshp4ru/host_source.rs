@@ -0,0 +1,239 @@
+use crate::known_hosts;
+use crate::utils::glob_match;
+use crate::warnings::Warnings;
+use crate::{parse_host_line, Host, ParseError};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// compressed hosts files are recognized by these, or by their extension
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const ETC_HOSTS: &str = "/etc/hosts";
+
+// hosts parsed from one source, its metadata included
+pub type HostGroup = Vec<Rc<RefCell<Host>>>;
+
+// where hosts come from; Config reads its sources in order and merges the groups they
+// return, so another kind of inventory is another implementation. Each group counts as
+// one source for --interleave-groups.
+pub trait HostSource: std::fmt::Debug {
+    // with `lenient` set, malformed entries are skipped with a warning instead of failing
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError>;
+}
+
+// -f <path>, a directory is one group per file in it
+#[derive(Debug)]
+pub struct FileSource {
+    pub path: String,
+}
+
+impl HostSource for FileSource {
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError> {
+        let mut groups = Vec::new();
+        for file in hosts_files(&self.path)?.iter() {
+            let mut hosts = Vec::new();
+            read_hosts_file(file, &mut hosts, lenient, warnings)?;
+            groups.push(hosts);
+        }
+        Ok(groups)
+    }
+}
+
+// -f - or no -f at all, a terminal is refused rather than waited on
+#[derive(Debug)]
+pub struct StdinSource;
+
+impl HostSource for StdinSource {
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError> {
+        let stdin = io::stdin();
+        if stdin.is_terminal() {
+            return Err(ParseError::IoError(io::Error::other(
+                "No hosts provided from stdin!",
+            )));
+        }
+        // buffered reads on locked stdin
+        let mut hosts = Vec::new();
+        read_hosts(
+            io::BufReader::new(stdin.lock()),
+            &mut hosts,
+            "stdin",
+            lenient,
+            warnings,
+        )?;
+        Ok(vec![hosts])
+    }
+}
+
+// --from-etc-hosts <glob>, every name and alias in /etc/hosts matching it, once, with
+// the address it maps to as `address=` metadata; $SSHP4RU_ETC_HOSTS names another file
+#[derive(Debug)]
+pub struct EtcHostsSource {
+    pub pattern: String,
+}
+
+impl HostSource for EtcHostsSource {
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError> {
+        let path = std::env::var("SSHP4RU_ETC_HOSTS").unwrap_or_else(|_| ETC_HOSTS.to_string());
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        let mut seen = HashSet::new();
+        let mut lines = String::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(address) = fields.next() else {
+                continue;
+            };
+            for name in fields {
+                if glob_match(&self.pattern, name) && seen.insert(name) {
+                    lines.push_str(&format!("{} address={}\n", name, address));
+                }
+            }
+        }
+        let mut hosts = Vec::new();
+        read_hosts(lines.as_bytes(), &mut hosts, &path, lenient, warnings)?;
+        Ok(vec![hosts])
+    }
+}
+
+// --from-known-hosts <glob>, the first name of every entry in ~/.ssh/known_hosts that
+// matches it, a `[host]:port` one with `port=` metadata. Hashed names cannot be read
+// back, and patterns and @cert-authority or @revoked lines are not hosts.
+#[derive(Debug)]
+pub struct KnownHostsSource {
+    pub pattern: String,
+}
+
+impl HostSource for KnownHostsSource {
+    fn read(&self, lenient: bool, warnings: &Warnings) -> Result<Vec<HostGroup>, ParseError> {
+        let path = known_hosts::default_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+        let source = path.display().to_string();
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", source, e)))?;
+        let mut seen = HashSet::new();
+        let mut lines = String::new();
+        for line in text.lines() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('@') {
+                continue;
+            }
+            let name = line
+                .split_whitespace()
+                .next()
+                .and_then(|names| names.split(',').next())
+                .unwrap_or_default();
+            if name.starts_with('|') || name.contains(['*', '?', '!']) {
+                continue;
+            }
+            let (name, port) = match name
+                .strip_prefix('[')
+                .and_then(|name| name.split_once("]:"))
+            {
+                Some((name, port)) => (name, Some(port)),
+                None => (name, None),
+            };
+            if !glob_match(&self.pattern, name) || !seen.insert((name, port)) {
+                continue;
+            }
+            lines.push_str(name);
+            if let Some(port) = port {
+                lines.push_str(&format!(" port={}", port));
+            }
+            lines.push('\n');
+        }
+        let mut hosts = Vec::new();
+        read_hosts(lines.as_bytes(), &mut hosts, &source, lenient, warnings)?;
+        Ok(vec![hosts])
+    }
+}
+
+// a directory stands for every regular file in it, in name order
+fn hosts_files(path: &str) -> Result<Vec<PathBuf>, ParseError> {
+    let path = Path::new(path);
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// gzip and zstd files are decompressed while reading, detected by magic bytes or extension
+fn open_hosts_file(path: &Path) -> Result<Box<dyn BufRead>, ParseError> {
+    // transform error to custom error type
+    let file = std::fs::File::open(path).map_err(ParseError::IoError)?;
+    let mut reader = io::BufReader::new(file);
+    let magic = reader.fill_buf()?;
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    if magic.starts_with(GZIP_MAGIC) || extension == Some("gz") {
+        Ok(Box::new(io::BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        )))
+    } else if magic.starts_with(ZSTD_MAGIC) || extension == Some("zst") {
+        Ok(Box::new(io::BufReader::new(zstd::Decoder::with_buffer(
+            reader,
+        )?)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+// streamed line by line like the compressed files on purpose: of the startup with a
+// 2M line inventory, reading and splitting the lines is under 0.2s of about 6s, so
+// reading the whole file or mapping it would only add its size to memory
+fn read_hosts_file(
+    path: &Path, hosts: &mut Vec<Rc<RefCell<Host>>>, lenient: bool, warnings: &Warnings,
+) -> Result<(), ParseError> {
+    let source = path.display().to_string();
+    read_hosts(open_hosts_file(path)?, hosts, &source, lenient, warnings)
+}
+
+// with `lenient` set, malformed lines are skipped with a warning instead of failing the parse
+pub(crate) fn read_hosts<R: BufRead>(
+    mut reader: R, hosts: &mut Vec<Rc<RefCell<Host>>>, source: &str, lenient: bool,
+    warnings: &Warnings,
+) -> Result<(), ParseError> {
+    let mut line_no = 0;
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while reader.read_until(b'\n', &mut buffer)? > 0 {
+        line_no += 1;
+        let host = parse_host_line(line_no, &buffer);
+        add_host_line(host, line_no, hosts, source, lenient, warnings)?;
+        buffer.clear();
+    }
+    Ok(())
+}
+
+fn add_host_line(
+    host: Result<Option<Host>, ParseError>, line_no: usize, hosts: &mut Vec<Rc<RefCell<Host>>>,
+    source: &str, lenient: bool, warnings: &Warnings,
+) -> Result<(), ParseError> {
+    match host {
+        Ok(Some(host)) => hosts.push(Rc::new(RefCell::new(host))),
+        Ok(None) => {}
+        Err(e) if lenient => warnings.push(
+            "skipped-host-line",
+            format!(
+                "{} line {}: {}",
+                source,
+                line_no,
+                e.to_string().lines().next().unwrap_or_default()
+            ),
+        ),
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// the first address of `authority` that answers within `timeout`
+pub fn connect(authority: impl ToSocketAddrs, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address");
+    for addr in authority.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+// one plain http request to a local agent or collector, `body` with its content type;
+// HTTP/1.0 keeps the reply free of chunked encoding. The body of a 2xx reply, an error
+// with the status line otherwise
+pub fn request(
+    authority: &str, method: &str, path: &str, headers: &[(&str, &str)],
+    body: Option<(&str, &str)>, timeout: Duration,
+) -> io::Result<String> {
+    let mut stream = connect(authority, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, authority);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some((content_type, body)) = body {
+        head.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n",
+            content_type,
+            body.len()
+        ));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if let Some((_, body)) = body {
+        stream.write_all(body.as_bytes())?;
+    }
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status_line = head.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(body.to_string()),
+        _ => Err(io::Error::other(format!("replied: {}", status_line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // serves one request with `reply`, handing back what was sent
+    fn serve_once(reply: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !String::from_utf8_lossy(&request).ends_with("{}") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(reply.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (authority, server)
+    }
+
+    #[test]
+    fn sends_the_body_and_reads_the_reply() {
+        let (authority, server) = serve_once("HTTP/1.0 200 OK\r\n\r\nfine");
+        let body = request(
+            &authority,
+            "POST",
+            "/v1/traces",
+            &[("X-Token", "t")],
+            Some(("application/json", "{}")),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(body, "fine");
+        let sent = server.join().unwrap();
+        assert!(sent.starts_with("POST /v1/traces HTTP/1.0\r\n"));
+        assert!(sent.contains("X-Token: t\r\n"));
+        assert!(sent.ends_with("Content-Length: 2\r\n\r\n{}"));
+    }
+
+    #[test]
+    fn fails_on_an_error_status() {
+        let (authority, server) = serve_once("HTTP/1.0 503 Unavailable\r\n\r\n");
+        let error = request(
+            &authority,
+            "POST",
+            "/",
+            &[],
+            Some(("application/json", "{}")),
+            Duration::from_secs(5),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "replied: HTTP/1.0 503 Unavailable");
+        server.join().unwrap();
+    }
+}
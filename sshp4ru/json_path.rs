@@ -0,0 +1,56 @@
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+// the jq subset --field takes: `.`, `.key`, `.key.nested`, `.list[0]` and `.["odd key"]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    pub fn parse(spec: &str) -> Result<JsonPath, String> {
+        let invalid = || format!("`{}` is not a path like .key.nested or .list[0]", spec);
+        let mut rest = spec.strip_prefix('.').ok_or_else(invalid)?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(invalid)?;
+                let inner = &after[..end];
+                let segment = match inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                    Some(key) => Segment::Key(key.to_string()),
+                    None => Segment::Index(inner.parse().map_err(|_| invalid())?),
+                };
+                segments.push(segment);
+                rest = &after[end + 1..];
+            } else {
+                let rest_key = rest.strip_prefix('.').unwrap_or(rest);
+                // `.a.b` is split on the dots, the first key follows the leading dot directly
+                if rest_key.len() == rest.len() && !segments.is_empty() {
+                    return Err(invalid());
+                }
+                let end = rest_key.find(['.', '[']).unwrap_or(rest_key.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Key(rest_key[..end].to_string()));
+                rest = &rest_key[end..];
+            }
+        }
+        Ok(JsonPath { segments })
+    }
+
+    // None when a key or index along the way is missing
+    pub fn extract<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+}
@@ -0,0 +1,149 @@
+use crate::prompt;
+use crate::{Host, RuntimeError, PROG_NAME};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::Arc;
+
+const SSH_DEFAULT_PORT: u16 = 22;
+
+// where ssh(1) looks when no UserKnownHostsFile is given
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".ssh").join("known_hosts"))
+}
+
+// the hosts file may carry a login, known_hosts only ever has the address
+pub fn address(full_name: &str) -> &str {
+    full_name.rsplit('@').next().unwrap_or(full_name)
+}
+
+// how ssh(1) names a host in known_hosts, non-default ports are bracketed
+fn known_hosts_name(address: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) if port != SSH_DEFAULT_PORT => format!("[{}]:{}", address, port),
+        _ => address.to_string(),
+    }
+}
+
+// ssh-keygen also matches hashed entries, it exits non-zero when the host is not found
+fn is_known(path: &Path, name: &str) -> io::Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let status = Command::new("ssh-keygen")
+        .arg("-F")
+        .arg(name)
+        .arg("-f")
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    Ok(status.success())
+}
+
+// address and name of hosts by the port ssh connects to
+type HostsByPort = BTreeMap<Option<u16>, Vec<(String, Arc<str>)>>;
+
+// the hosts without an entry in `path`; skipped hosts are never connected to
+fn unknown_by_port(
+    path: &Path, hosts: &[Rc<RefCell<Host>>], target_for: &dyn Fn(&Host) -> (String, Option<u16>),
+) -> io::Result<HostsByPort> {
+    let mut unknown = HostsByPort::new();
+    for host in hosts.iter() {
+        let host = host.borrow();
+        if host.cp.skipped {
+            continue;
+        }
+        let (address, port) = target_for(&host);
+        if !is_known(path, &known_hosts_name(&address, port))? {
+            unknown
+                .entry(port)
+                .or_default()
+                .push((address, Arc::clone(&host.full_name)));
+        }
+    }
+    Ok(unknown)
+}
+
+// --check-known-hosts: the names of the hosts ssh would ask about, only `path` is looked
+// at and not the system wide known_hosts
+pub fn check(
+    path: &Path, hosts: &[Rc<RefCell<Host>>], target_for: &dyn Fn(&Host) -> (String, Option<u16>),
+) -> Result<Vec<Arc<str>>, RuntimeError> {
+    let unknown = unknown_by_port(path, hosts, target_for)
+        .map_err(|e| RuntimeError::KnownHostsError(path.display().to_string(), e))?;
+    Ok(unknown
+        .into_values()
+        .flatten()
+        .map(|(_, name)| name)
+        .collect())
+}
+
+// scans the hosts missing from `path` and appends their keys once the user agreed,
+// so the run itself does not stall on host key prompts; `target_for` is the address and
+// port ssh connects to for a host
+pub fn learn(
+    path: &Path, hosts: &[Rc<RefCell<Host>>], target_for: &dyn Fn(&Host) -> (String, Option<u16>),
+) -> Result<usize, RuntimeError> {
+    let known_hosts_error = |e| RuntimeError::KnownHostsError(path.display().to_string(), e);
+
+    // ssh-keyscan takes one port per run, so the unknown hosts are scanned by port
+    let unknown = unknown_by_port(path, hosts, target_for).map_err(known_hosts_error)?;
+    if unknown.is_empty() {
+        return Ok(0);
+    }
+
+    let mut scanned = String::new();
+    for (port, addresses) in unknown.iter() {
+        let mut keyscan = Command::new("ssh-keyscan");
+        if let Some(port) = port {
+            keyscan.arg("-p").arg(port.to_string());
+        }
+        let output = keyscan
+            .arg("--")
+            .args(addresses.iter().map(|(address, _)| address))
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(known_hosts_error)?;
+        scanned.push_str(&String::from_utf8_lossy(&output.stdout));
+    }
+    let unknown: usize = unknown.values().map(Vec::len).sum();
+    let keys: Vec<&str> = scanned
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .collect();
+    // unreachable hosts simply fail in the run, like they would have without scanning
+    if keys.is_empty() {
+        return Ok(0);
+    }
+
+    let question = format!(
+        "[{}] add {} host key{} for {} unknown host{} to {}?",
+        PROG_NAME,
+        keys.len(),
+        if keys.len() == 1 { "" } else { "s" },
+        unknown,
+        if unknown == 1 { "" } else { "s" },
+        path.display()
+    );
+    if !prompt::confirm(&question)? {
+        return Err(RuntimeError::NotConfirmed);
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(known_hosts_error)?;
+    for key in keys.iter() {
+        writeln!(file, "{}", key).map_err(known_hosts_error)?;
+    }
+    file.sync_all().map_err(known_hosts_error)?;
+
+    Ok(keys.len())
+}
@@ -1,50 +1,261 @@
 use fdwatcher::FdEvent;
 use libc::pid_t;
-use nix::sched;
 use nix::sys::wait;
-use nix::unistd::{close, dup2, execvp};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::ffi::CString;
-use std::io::BufRead;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ffi::{CString, OsString};
 use std::io::{self, IsTerminal};
+use std::net::IpAddr;
 use std::os::fd::RawFd;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::{error::Error, fmt};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, error::Error, fmt};
 use twox_hash;
-use utils::PipeFd;
 
+// print!/println! for everything the crate writes to stdout: once the reader of stdout
+// is gone (`| head`) output is dropped instead of panicking, and the run acts on
+// --on-epipe
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::utils::print_stdout(format_args!($($arg)*))
+    };
+}
+
+macro_rules! outln {
+    () => {
+        $crate::utils::print_stdout(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::utils::print_stdout(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+mod aggregate;
+mod audit;
+mod buffer_limit;
+mod check;
+pub mod client;
+#[cfg(feature = "cloud")]
+mod cloud;
+mod collect;
+mod config_file;
+mod control;
+#[cfg(feature = "cli")]
+mod daemon;
+mod deadline;
+mod debug;
+mod diagnose;
+mod discovery;
+#[cfg(feature = "cli")]
+mod doctor;
+mod events;
+mod exit_map;
 mod fdwatcher;
+mod follow_up;
+mod host_source;
+mod http;
+mod json_path;
+mod known_hosts;
+mod notify;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod output_mode;
+mod probe;
+mod prompt;
+mod redact;
+mod report;
+mod report_diff;
+mod report_merge;
+mod retry;
+mod scoreboard;
 pub mod signals;
+mod spawn;
+mod ssh_config;
+mod ssh_options;
+mod theme;
 mod utils;
+mod warnings;
 
+use crate::aggregate::Aggregate;
+use crate::audit::Plan;
+use crate::buffer_limit::{with_marker, BufferLimit, Dropped};
+pub use crate::check::print_check_status;
+#[cfg(feature = "cloud")]
+use crate::cloud::{Address as CloudAddress, CloudSource};
+pub use crate::config_file::ConfigFormat;
+#[cfg(feature = "cli")]
+pub use crate::daemon::serve;
+pub use crate::debug::DebugCategories;
+use crate::discovery::{ConsulSource, DnsSrvSource};
+#[cfg(feature = "cli")]
+pub use crate::doctor::doctor;
+use crate::events::EventSink;
 pub use crate::fdwatcher::Fdwatcher;
-use crate::fdwatcher::PipeType;
-pub use crate::utils::{debug_hosts, generate_seed, monotonic_time_ms};
-use crate::utils::{make_pipe, Color, Colorize};
+use crate::fdwatcher::{OutputConfig, PipeType};
+use crate::host_source::{EtcHostsSource, FileSource, HostSource, KnownHostsSource, StdinSource};
+use crate::json_path::JsonPath;
+pub use crate::notify::{print_summary, print_summary_by, run_notify_command};
+use crate::output_mode::{GroupMode, JoinMode, JsonLinesMode, LineMode, OutputMode};
+use crate::redact::Redactor;
+use crate::report::CompressOutput;
+pub use crate::report_diff::diff_reports;
+pub use crate::report_merge::merge_reports;
+use crate::retry::{ReconnectPolicy, RetryPolicy, MAX_RECONNECTS};
+use crate::scoreboard::Scoreboard;
+use crate::spawn::{CloneSpawner, Spawner};
+use crate::ssh_options::HostKeyPolicy;
+pub use crate::theme::Theme;
+#[cfg(feature = "cli")]
+pub use crate::utils::print_version_json;
+use crate::utils::{
+    acquire_lock, available_memory, command_line, open_files_limit, parse_duration,
+    set_window_size, shell_quote, shell_words, stdout_closed, window_size, Colorize, ResourceUsage,
+    MAX_CHILD_STDIN,
+};
+pub use crate::utils::{debug_hosts, generate_seed, monotonic_time_ms, print_stdout};
+use crate::warnings::Warnings;
 
 pub const PROG_NAME: &str = "sshp4ru";
+#[cfg(feature = "cli")]
 const PROG_FULL_NAME: &str = "Parallel SSH Executor in Rust";
 pub const PROG_VERSION: &str = "0.1.0";
+#[cfg(feature = "cli")]
 const PROG_SOURCE: &str = "https://github.com/DmMeta/sshp4ru";
+#[cfg(feature = "cli")]
 const PROG_LICENSE: &str = "MIT License";
 
 // max characters to process in line and join mode respectively
 const DEFAULT_MAX_LINE_LENGTH: u16 = 1 * 1024;
 const DEFAULT_MAX_OUTPUT_LENGTH: u16 = 8 * 1024;
 const DEFAULT_MAX_SSH_JOBS: u8 = 50;
+// a single failed host is a WARNING, more are CRITICAL
+const DEFAULT_CHECK_WARNING: u32 = 1;
+const DEFAULT_CHECK_CRITICAL: u32 = 2;
+// how long a host below --quarantine-below is left out before it runs again
+const DEFAULT_QUARANTINE: Duration = Duration::from_secs(60 * 60);
+// runs on more hosts than this ask first unless --yes, even without --max-hosts
+const DEFAULT_HOST_COUNT_GUARD: usize = 1000;
 const _POSIX_HOST_NAME_MAX: usize = 255;
 
+const DEFAULT_SSH_PORT: u16 = 22;
+const DEFAULT_BECOME_USER: &str = "root";
+
 const FDW_MAX_EVENTS: usize = 50;
 const FDW_WAIT_TIMEOUT: i32 = -1; // block indefinitely while waiting for events
 
+// how often running follow-up jobs are looked in on, they have no fds in the fdwatcher
+const FOLLOW_UP_POLL_MS: u128 = 50;
+
 const MAX_ARGS: usize = 256;
 
+// fds a job keeps open in the parent (stdout and stderr pipes) plus one spare for the
+// write end that is briefly open while spawning
+const FDS_PER_JOB: u64 = 3;
+// stdio, the epoll fd, lock and report files
+const FDS_RESERVED: u64 = 16;
+// the stack handed to clone(2) for every child
+const CHILD_STACK_SIZE: usize = 8 * 1024 * 1024;
+// roughly what an ssh client keeps resident, only used for `-m auto`
+const SSH_RSS_ESTIMATE: u64 = 8 * 1024 * 1024;
+// ssh mostly waits on the network, so `-m auto` allows many jobs per CPU
+const JOBS_PER_CPU: u64 = 16;
+// what ssh still gets with --clean-env, enough to find its config, keys and the agent
+const CLEAN_ENV_KEEP: [&str; 6] = ["PATH", "HOME", "USER", "LOGNAME", "TERM", "SSH_AUTH_SOCK"];
+
+// one item of every source in turn, keeping the order within a source, so sources listed
+// one after the other (e.g. a file per datacenter) all get jobs from the start
+fn interleave_sources<T>(items: Vec<T>, source: impl Fn(&T) -> usize) -> Vec<T> {
+    let mut queues: BTreeMap<usize, VecDeque<T>> = BTreeMap::new();
+    for item in items {
+        queues.entry(source(&item)).or_default().push_back(item);
+    }
+    let mut interleaved = Vec::new();
+    while !queues.is_empty() {
+        queues.retain(|_, queue| match queue.pop_front() {
+            Some(item) => {
+                interleaved.push(item);
+                true
+            }
+            None => false,
+        });
+    }
+    interleaved
+}
+
+// what runs where, for --serialize-per-host and --per-jump-limit; hosts and their
+// follow-up jobs alike, by pid
+#[derive(Default)]
+struct Slots {
+    // only tracked with --serialize-per-host, by full name as -t trims `name` once started
+    hosts: HashMap<pid_t, Arc<str>>,
+    // only tracked with --per-jump-limit
+    jumps: HashMap<pid_t, String>,
+}
+
+impl Slots {
+    // whether `host` may start another job, -m aside
+    fn free(&self, conf: &Config, host: &Host) -> bool {
+        let host_free =
+            !conf.serialize_per_host || !self.hosts.values().any(|busy| *busy == host.full_name);
+        let jump_free = match (conf.per_jump_limit, conf.ssh_options.jump_target(host)) {
+            (Some(limit), Some(jump)) => {
+                self.jumps.values().filter(|busy| **busy == jump).count() < limit
+            }
+            _ => true,
+        };
+        host_free && jump_free
+    }
+
+    fn claim(&mut self, conf: &Config, host: &Host, pid: pid_t) {
+        if conf.serialize_per_host {
+            self.hosts.insert(pid, Arc::clone(&host.full_name));
+        }
+        if conf.per_jump_limit.is_some() {
+            if let Some(jump) = conf.ssh_options.jump_target(host) {
+                self.jumps.insert(pid, jump);
+            }
+        }
+    }
+
+    fn release(&mut self, pid: pid_t) {
+        self.hosts.remove(&pid);
+        self.jumps.remove(&pid);
+    }
+}
+
+// requeued hosts go behind the ones of the same priority that are still waiting
+fn push_by_priority(pending: &mut VecDeque<Rc<RefCell<Host>>>, host: Rc<RefCell<Host>>) {
+    let priority = host.borrow().priority;
+    let index = pending.partition_point(|waiting| waiting.borrow().priority >= priority);
+    pending.insert(index, host);
+}
+
+// `-m auto`: as many jobs as the open files limit, available memory and CPUs allow
+fn auto_max_jobs() -> u8 {
+    let mut jobs = u8::MAX as u64;
+    if let Ok(limit) = open_files_limit() {
+        jobs = jobs.min(limit.saturating_sub(FDS_RESERVED) / FDS_PER_JOB);
+    }
+    if let Some(memory) = available_memory() {
+        jobs = jobs.min(memory / (CHILD_STACK_SIZE as u64 + SSH_RSS_ESTIMATE));
+    }
+    if let Ok(cpus) = std::thread::available_parallelism() {
+        jobs = jobs.min(cpus.get() as u64 * JOBS_PER_CPU);
+    }
+    jobs.max(1) as u8
+}
+
 #[derive(Debug)]
 pub enum ParseError {
     UnknownOption,
     HelpRequested,
     VersionRequested,
+    VersionJsonRequested,
     ArgCount,
     InvalidColor(String),
     InvalidMaxJobs,
@@ -55,9 +266,13 @@ pub enum ParseError {
     JoinSilentConflict,
     IoError(io::Error),
     ParsePortError,
-    HostnameTooLong(u16, u16, String),
+    HostnameTooLong(usize, u16, String),
     Utf8Error(std::str::Utf8Error),
-    HostFileFormatError(u16, String),
+    HostFileFormatError(usize, String),
+    MissingArgument(String),
+    OptionRequires(String, String),
+    InvalidArgument(String, String),
+    Conflict(String, String),
 }
 
 impl fmt::Display for ParseError {
@@ -66,10 +281,11 @@ impl fmt::Display for ParseError {
             ParseError::UnknownOption => Ok(()),
             ParseError::HelpRequested => Ok(()),
             ParseError::VersionRequested => Ok(()),
+            ParseError::VersionJsonRequested => Ok(()),
             ParseError::ArgCount => write!(f, "no command specified"),
             ParseError::InvalidColor(msg) => write!(f, "invalid value for `-c`: {}", msg),
             ParseError::InvalidMaxJobs => {
-                write!(f, "invalid value for `-m`: must be an integer > 0")
+                write!(f, "invalid value for `-m`: must be an integer > 0 or `auto`")
             }
             ParseError::MaxLineLength => write!(
                 f,
@@ -94,9 +310,19 @@ impl fmt::Display for ParseError {
             ParseError::Utf8Error(err) => write!(f, "{}", err),
             ParseError::HostFileFormatError(line_no, msg) => write!(
                 f,
-                "Host file format error on line: {}\n{}\nEnsure each host is newline separated",
+                "Host file format error on line: {}\n{}\nEnsure each host is newline separated, optionally followed by key=value pairs",
                 line_no, msg
             ),
+            ParseError::MissingArgument(opt) => write!(f, "missing value for `{}`", opt),
+            ParseError::OptionRequires(opt, required) => {
+                write!(f, "`{}` requires `{}`", opt, required)
+            }
+            ParseError::InvalidArgument(opt, msg) => {
+                write!(f, "invalid value for `{}`: {}", opt, msg)
+            }
+            ParseError::Conflict(first, second) => {
+                write!(f, "`{}` and `{}` are mutually exclusive", first, second)
+            }
         }
     }
 }
@@ -123,6 +349,7 @@ impl From<std::str::Utf8Error> for ParseError {
 #[derive(Debug)]
 pub enum RuntimeError {
     SshCommandLengthExceeded(usize),
+    NulInArgument(String),
     ClosePipeError(String),
     PipeCreationError(String),
     CloneProcessError,
@@ -133,6 +360,33 @@ pub enum RuntimeError {
     CloseFdError(nix::errno::Errno),
     WriteStreamError,
     WaitChildProcError(nix::Error),
+    LockHeld(String),
+    LockError(String, io::Error),
+    PlanNotReviewed(String, String),
+    AuditLogError(String, io::Error),
+    EventsSocketError(String, io::Error),
+    TtyUnavailable(io::Error),
+    NotConfirmed,
+    ReportError(String, io::Error),
+    FailedHostsError(String, io::Error),
+    ReceiptError(String, io::Error),
+    OutputLimitExceeded(u64),
+    AbortedOnOutput(String, String),
+    // stdout became a closed pipe with --on-epipe stop: hosts done, jobs stopped
+    StdoutClosed(usize, usize),
+    DeadlineExceeded(Duration),
+    KnownHostsError(String, io::Error),
+    // --check-known-hosts found hosts missing from the known_hosts file
+    UnknownHostKeys(String, Vec<Arc<str>>),
+    ServeError(String, io::Error),
+    ListenTokenError(String, io::Error),
+    ControlDirError(String, io::Error),
+    OutputDirError(String, io::Error),
+    WatchHostsError(String, io::Error),
+    SshUnavailable(String, io::Error),
+    ScoreboardError(String, io::Error),
+    // program, its version, the feature and the version that added it
+    SshTooOld(String, (u32, u32), &'static str, (u32, u32)),
 }
 impl Error for RuntimeError {}
 
@@ -142,6 +396,13 @@ impl fmt::Display for RuntimeError {
             RuntimeError::SshCommandLengthExceeded(len) => {
                 write!(f, "ssh command exceeds max args: {} >= {}", len, MAX_ARGS)
             }
+            RuntimeError::NulInArgument(arg) => {
+                write!(
+                    f,
+                    "command argument `{}` contains a NUL byte",
+                    arg.escape_debug()
+                )
+            }
             RuntimeError::ClosePipeError(pipe_type) => {
                 write!(f, "failed to close {} pipe write end", pipe_type)
             }
@@ -162,6 +423,99 @@ impl fmt::Display for RuntimeError {
             RuntimeError::WaitChildProcError(e) => {
                 write!(f, "failed to wait for child process(waitpid): {}", e)
             }
+            RuntimeError::LockHeld(path) => {
+                write!(f, "lock {} is held by another {} run", path, PROG_NAME)
+            }
+            RuntimeError::LockError(path, e) => write!(f, "failed to lock {}: {}", path, e),
+            RuntimeError::PlanNotReviewed(reviewed, actual) => write!(
+                f,
+                "plan hash {} does not match the reviewed hash {}",
+                actual, reviewed
+            ),
+            RuntimeError::AuditLogError(path, e) => {
+                write!(f, "failed to write audit log {}: {}", path, e)
+            }
+            RuntimeError::EventsSocketError(path, e) => {
+                write!(f, "failed to connect to events socket {}: {}", path, e)
+            }
+            RuntimeError::TtyUnavailable(e) => {
+                write!(f, "interactive prompt needs a terminal (/dev/tty): {}", e)
+            }
+            RuntimeError::NotConfirmed => write!(f, "aborted: run was not confirmed"),
+            RuntimeError::ReportError(path, e) => {
+                write!(f, "failed to write report {}: {}", path, e)
+            }
+            RuntimeError::OutputLimitExceeded(limit) => write!(
+                f,
+                "total output exceeded the hard limit of {} bytes, remaining jobs killed",
+                limit
+            ),
+            RuntimeError::AbortedOnOutput(host, line) => write!(
+                f,
+                "{} printed `{}`, matching --abort-on-output, remaining jobs killed",
+                host, line
+            ),
+            RuntimeError::StdoutClosed(done, stopped) => write!(
+                f,
+                "stdout was closed after {} host{} finished, {} running job{} stopped",
+                done,
+                if *done == 1 { "" } else { "s" },
+                stopped,
+                if *stopped == 1 { "" } else { "s" }
+            ),
+            RuntimeError::DeadlineExceeded(deadline) => write!(
+                f,
+                "the run passed its --deadline of {:?}, remaining jobs killed",
+                deadline
+            ),
+            RuntimeError::ReceiptError(path, e) => {
+                write!(f, "failed to write receipt in {}: {}", path, e)
+            }
+            RuntimeError::ServeError(path, e) => write!(f, "cannot serve on {}: {}", path, e),
+            RuntimeError::ListenTokenError(path, e) => {
+                write!(f, "cannot read the listen token from {}: {}", path, e)
+            }
+            RuntimeError::ControlDirError(path, e) => {
+                write!(
+                    f,
+                    "cannot create the control socket directory {}: {}",
+                    path, e
+                )
+            }
+            RuntimeError::OutputDirError(path, e) => {
+                write!(f, "failed to write host output to {}: {}", path, e)
+            }
+            RuntimeError::WatchHostsError(path, e) => {
+                write!(f, "cannot watch hosts file {}: {}", path, e)
+            }
+            RuntimeError::SshUnavailable(program, e) => write!(f, "cannot run {}: {}", program, e),
+            RuntimeError::ScoreboardError(path, e) => {
+                write!(f, "failed to update scoreboard {}: {}", path, e)
+            }
+            RuntimeError::SshTooOld(program, (major, minor), feature, (need_major, need_minor)) => {
+                write!(
+                    f,
+                    "{} is OpenSSH {}.{}, {} needs OpenSSH {}.{} or newer (pick another with --ssh-path)",
+                    program, major, minor, feature, need_major, need_minor
+                )
+            }
+            RuntimeError::KnownHostsError(path, e) => {
+                write!(f, "failed to update known hosts {}: {}", path, e)
+            }
+            RuntimeError::FailedHostsError(path, e) => {
+                write!(f, "failed to write failed hosts to {}: {}", path, e)
+            }
+            RuntimeError::UnknownHostKeys(path, hosts) => {
+                let names: Vec<&str> = hosts.iter().map(|name| name.as_ref()).collect();
+                write!(
+                    f,
+                    "{} host{} not in {}: {} (add them with --learn-hostkeys or pass --accept-new)",
+                    hosts.len(),
+                    if hosts.len() == 1 { " is" } else { "s are" },
+                    path,
+                    names.join(", ")
+                )
+            }
         }
     }
 }
@@ -171,12 +525,16 @@ pub enum ProgMode {
     Line = 0,
     Group,
     Join,
+    JsonLines,
 }
 
-#[derive(Debug)]
-enum ScriptInput {
-    Stdin(io::Stdin),
-    HostsFile(String),
+// what a run does once stdout turns out to be a closed pipe
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EpipePolicy {
+    // running jobs are stopped and reaped, the run fails
+    Stop,
+    // the hosts run to the end with their output dropped, reports are still written
+    Continue,
 }
 
 #[derive(Debug, Clone)]
@@ -196,8 +554,43 @@ struct ChildProcess {
     output_index: i32,
     exit_code: i32,
     started_time: u128,
+    // when the current attempt first printed anything, the end of connecting for --otlp-endpoint
+    first_output_time: Option<u128>,
     finished_time: u128,
     state: CpState,
+    attempts: u32,
+    // of everything the child wrote, in the order it was read
+    output_digest: twox_hash::XxHash64,
+    output_bytes: u64,
+    // the part of output_bytes read from stderr, -j reads both streams as one
+    stderr_bytes: u64,
+    // exited 0 but wrote to stderr, with --fail-on-stderr
+    failed_on_stderr: bool,
+    // the last bytes read from stderr, or from the one pipe of -j, where ssh reports a
+    // dropped connection
+    stderr_tail: Vec<u8>,
+    // set when output was cut at --max-line-length, --max-output-length or
+    // --per-host-buffer-limit
+    truncated: bool,
+    // what --per-host-buffer-limit dropped from output_buffer
+    dropped: Dropped,
+    usage: ResourceUsage,
+    // times ssh lost the connection, with --reconnect
+    reconnects: u32,
+    // lines counted against --max-lines, both streams together
+    lines_printed: u32,
+    // the line that matched --kill-on-output, the child was terminated when it was read
+    matched_output: Option<String>,
+    // the line that matched --abort-on-output, every child is terminated
+    abort_output: Option<String>,
+    // the --field of the output parsed as JSON, or why it could not be parsed
+    json_value: Option<Result<serde_json::Value, String>>,
+    // not run, a recent receipt showed it already succeeded
+    skipped: bool,
+    // where the SIGUSR1 status line of this child is published while it runs
+    status_slot: Option<usize>,
+    // a retried host waits in the queue until then, with --retry-delay
+    retry_at: Option<u128>,
 }
 
 impl ChildProcess {
@@ -211,21 +604,112 @@ impl ChildProcess {
             output_index: -1,
             exit_code: -1,
             started_time: 0,
+            first_output_time: None,
             finished_time: 0,
             state: CpState::Ready,
+            attempts: 0,
+            output_digest: twox_hash::XxHash64::default(),
+            output_bytes: 0,
+            stderr_bytes: 0,
+            failed_on_stderr: false,
+            stderr_tail: Vec::new(),
+            truncated: false,
+            dropped: Dropped::default(),
+            usage: ResourceUsage::default(),
+            reconnects: 0,
+            lines_printed: 0,
+            matched_output: None,
+            abort_output: None,
+            json_value: None,
+            skipped: false,
+            status_slot: None,
+            retry_at: None,
         }
     }
+
+    fn skip(&mut self) {
+        self.state = CpState::Done;
+        self.exit_code = 0;
+        self.skipped = true;
+        signals::track_skipped();
+    }
+
+    // back to a spawnable state, keeping count of the attempts made so far and what they used
+    fn reset_for_retry(&mut self) {
+        signals::track_requeued();
+        *self = ChildProcess {
+            attempts: self.attempts,
+            usage: self.usage,
+            reconnects: self.reconnects,
+            ..ChildProcess::new()
+        };
+    }
 }
 
 #[derive(Debug)]
 pub struct Host {
-    name: String,
+    // shared rather than copied wherever hosts are tracked by name; `name` and `full_name`
+    // are the same string until -t trims `name`
+    name: Arc<str>,
+    // name as read from the hosts file, `name` may get trimmed for display
+    full_name: Arc<str>,
+    // key=value pairs following the hostname in a hosts file
+    meta: BTreeMap<String, String>,
+    // from --notes, shown when the host fails
+    note: Option<String>,
+    // which hosts file (in the order given) the host was read from, stdin counts as one
+    source: usize,
+    // higher starts first, from `priority=` metadata or --priority-map
+    priority: i64,
+    // TCP connect time to the ssh port measured before the run, with --probe-latency
+    latency: Option<Result<Duration, String>>,
+    // the address connected to and the ssh server's identification, with
+    // --record-server-info
+    address: Option<IpAddr>,
+    banner: Option<Result<String, String>>,
+    // output and exit code of --on-remote-failure, or why it could not run
+    diagnostics: Option<Result<(String, i32), String>>,
+    // what the --map-exit rules for its exit code did
+    follow_ups: Vec<exit_map::FollowUpResult>,
+    // the ssh config's settings for the host's name, ssh applies them itself
+    ssh_defaults: ssh_config::HostDefaults,
     cp: Box<ChildProcess>, // Box or Value
 }
 
 impl Host {
     pub fn as_str(&self) -> &str {
-        self.name.as_str()
+        self.name.as_ref()
+    }
+
+    pub fn meta(&self) -> &BTreeMap<String, String> {
+        &self.meta
+    }
+
+    // replaces `{meta.<key>}` with the host's value, unknown keys are left untouched
+    fn expand_meta(&self, arg: &str) -> String {
+        self.expand_meta_with(arg, Cow::Borrowed)
+    }
+
+    // `fill` turns each value into what goes in its place
+    fn expand_meta_with<'a>(&'a self, arg: &str, fill: impl Fn(&'a str) -> Cow<'a, str>) -> String {
+        let mut expanded = String::with_capacity(arg.len());
+        let mut rest = arg;
+        while let Some(start) = rest.find("{meta.") {
+            let after = &rest[start + "{meta.".len()..];
+            match after.find('}') {
+                Some(end) if self.meta.contains_key(&after[..end]) => {
+                    expanded.push_str(&rest[..start]);
+                    expanded.push_str(&fill(&self.meta[&after[..end]]));
+                    rest = &after[end + 1..];
+                }
+                _ => {
+                    expanded.push_str(&rest[..start + 1]);
+                    rest = &rest[start + 1..];
+                }
+            }
+        }
+        expanded.push_str(rest);
+        expanded
     }
     //public ?
     pub fn cp_exit_code(&self) -> i32 {
@@ -240,198 +724,143 @@ impl Host {
         self.cp.pid
     }
 
-    pub fn hostname<'a>(&'a self) -> &'a String {
-        &self.name
+    // replaces the collected output with the field, so join mode groups hosts by its value;
+    // strings are shown without quotes
+    fn parse_json_output(&mut self, field: &JsonPath) {
+        let parsed = serde_json::from_str::<serde_json::Value>(&self.cp.output_buffer)
+            .map(|output| field.extract(&output).cloned().unwrap_or_default())
+            .map_err(|e| e.to_string());
+        self.cp.output_buffer = match &parsed {
+            Ok(serde_json::Value::String(value)) => format!("{}\n", value),
+            Ok(value) => format!("{}\n", value),
+            Err(e) => format!("(output is not json: {})\n", e),
+        };
+        self.cp.json_value = Some(parsed);
     }
 
-    fn spawn_child_process(&mut self, command: &str, mode: &ProgMode) -> Result<(), RuntimeError> {
-        let mut stdio_fd_pair = PipeFd::default();
-        let mut stdout_fd_pair = PipeFd::default();
-        let mut stderr_fd_pair = PipeFd::default();
-
-        // pipe creation
-        match mode {
-            ProgMode::Join => {
-                stdio_fd_pair = match make_pipe() {
-                    Ok(p) => p,
-                    Err(_) => {
-                        return Err(RuntimeError::PipeCreationError("stdio".to_string()));
-                    }
-                };
-            }
-            _ => {
-                stdout_fd_pair = match make_pipe() {
-                    Ok(p) => p,
-                    Err(_) => {
-                        return Err(RuntimeError::PipeCreationError("stdout".to_string()));
-                    }
-                };
-                stderr_fd_pair = match make_pipe() {
-                    Ok(p) => p,
-                    Err(_) => {
-                        return Err(RuntimeError::PipeCreationError("stderr".to_string()));
-                    }
-                };
-            }
-        }
-
-        if let ProgMode::Join = mode {
-            assert_ne!(stdio_fd_pair, stdout_fd_pair);
-        } else {
-            assert_ne!(stderr_fd_pair, stdio_fd_pair);
-            assert_ne!(stdout_fd_pair, stdio_fd_pair);
-        }
+    pub fn hostname(&self) -> &str {
+        &self.name
+    }
 
-        let mut child_stack = vec![0u8; 8 * 1024 * 1024];
-        let ssh_command: Vec<CString> = command
-            .split_whitespace()
-            .map(|s| CString::new(s).unwrap())
-            .collect();
-        // println!("ssh command: {:?}", ssh_command);
-        // println!("original command {:?}", command);
-        match unsafe {
-            sched::clone(
-            // Box::new(|| child_process()),
-            Box::new( || {
-                match mode {
-                    ProgMode::Join => {
-                        // unwrap is safe here in both cases
-                        if let Err(e) = dup2(stdio_fd_pair.pipe_write_end.unwrap(), 1) {
-                            eprintln!("dup2 stdout error: {}", e);
-                            std::process::exit(3);
-                        }
-                        if let Err(e) = dup2(stdio_fd_pair.pipe_write_end.unwrap(), 2) {
-                            eprintln!("dup2 stderr error: {}", e);
-                            std::process::exit(3);
-                        }
-                    },
-                    _ => {
-                        // newprocess 1> stdout-captured pipe's write end 
-                        if let Err(e) = dup2(stdout_fd_pair.pipe_write_end.unwrap(), 1) {
-                            eprintln!("dup2 stdout error: {}", e);
-                            std::process::exit(3);
-                        }
-                        // newprocess 2> stderr-captured pipe's write end 
-                        if let Err(e) = dup2(stderr_fd_pair.pipe_write_end.unwrap(), 2) {
-                            eprintln!("dup2 stderr error: {}", e);
-                            std::process::exit(3);
-                        }
-                    }
-                }
-                // replace binary with ssh command
-                let _ = execvp(&ssh_command[0], &ssh_command);
-                eprintln!("exec");
-                std::process::exit(3);
-            }),
-            child_stack.as_mut_slice(),
-            sched::CloneFlags::CLONE_FS | sched::CloneFlags::CLONE_IO,
-            None
-            )
-        } // unsafe block end
-        {
-            Ok(pid) => {
-                if let ProgMode::Join = mode {
-                    if let Err(_) = close(stdio_fd_pair.pipe_write_end.unwrap()) {
-                        return Err(RuntimeError::ClosePipeError("stdio".to_string()));
-                    }
-                    self.cp.stdio_fd = stdio_fd_pair.pipe_read_end.unwrap();
-                }
-                else {
-                    if let Err(_) = close(stdout_fd_pair.pipe_write_end.unwrap()) {
-                        return Err(RuntimeError::ClosePipeError("stdout".to_string()));
-                    }
-                    if let Err(_) = close(stderr_fd_pair.pipe_write_end.unwrap()) {
-                        return Err(RuntimeError::ClosePipeError("stderr".to_string()));
-                    }
-                    self.cp.stdout_fd = stdout_fd_pair.pipe_read_end.unwrap();
-                    self.cp.stderr_fd = stderr_fd_pair.pipe_read_end.unwrap();
-                }
-                self.cp.pid = pid.as_raw();
-                self.cp.started_time = monotonic_time_ms();
-                self.cp.state = CpState::Running;
-                Ok(())
-            },
-            Err(_) => {
-                return Err(RuntimeError::CloneProcessError);
-            }
-        }
+    fn spawn_child_process(
+        &mut self, spawner: &dyn Spawner, command: &[String], joined: bool, env: &[CString],
+        stdin: Option<&[u8]>,
+    ) -> Result<(), RuntimeError> {
+        let ssh_command = command
+            .iter()
+            .map(|arg| {
+                CString::new(arg.as_str()).map_err(|_| RuntimeError::NulInArgument(arg.clone()))
+            })
+            .collect::<Result<Vec<CString>, RuntimeError>>()?;
+        let child = spawner.spawn(&ssh_command, joined, env, stdin)?;
+        self.cp.stdout_fd = child.stdout_fd;
+        self.cp.stderr_fd = child.stderr_fd;
+        self.cp.stdio_fd = child.stdio_fd;
+        self.cp.pid = child.pid;
+        self.cp.started_time = monotonic_time_ms();
+        self.cp.state = CpState::Running;
+        self.cp.status_slot = signals::track_running(self.cp.pid, &self.name);
+        self.cp.attempts += 1;
+        Ok(())
     }
 
     fn wait_child_process(
-        &mut self, newline_print: &mut bool, config_params: impl FnOnce() -> (bool, bool, bool),
+        &mut self, spawner: &dyn Spawner, newline_print: &mut bool,
+        config_params: impl FnOnce() -> (bool, bool, bool, bool, Theme),
     ) -> Result<(), RuntimeError> {
-        let (debug_opts, exit_codes, colorize) = config_params();
+        let (debug_opts, exit_codes, color_by_exit, fail_on_stderr, theme) = config_params();
 
-        if let wait::WaitStatus::Exited(pid, exit_code) = wait::waitpid(
-            Some(nix::unistd::Pid::from_raw(self.cp.pid)),
-            Some(wait::WaitPidFlag::empty()),
-        )
-        .map_err(|e| RuntimeError::WaitChildProcError(e))?
-        {
+        let (status, usage) = spawner
+            .wait(self.cp.pid)
+            .map_err(RuntimeError::WaitChildProcError)?;
+        // retried hosts add up the usage of every attempt
+        self.cp.usage.add(&usage);
+
+        // a child killed by a signal (e.g. --kill-on-output) exits like a shell reports it
+        let exited = match status {
+            wait::WaitStatus::Exited(pid, exit_code) => Some((pid, exit_code)),
+            wait::WaitStatus::Signaled(pid, signal, _) => Some((pid, 128 + signal as i32)),
+            _ => None,
+        };
+        if let Some((pid, mut exit_code)) = exited {
+            // output matching --kill-on-output fails the host even if it got to exit cleanly
+            if self.cp.matched_output.is_some() && exit_code == 0 {
+                exit_code = 1;
+            }
+            // so does anything on stderr with --fail-on-stderr
+            if fail_on_stderr && self.cp.stderr_bytes > 0 && exit_code == 0 {
+                self.cp.failed_on_stderr = true;
+                exit_code = 1;
+            }
             self.cp.pid = -2;
             self.cp.state = CpState::Done;
+            signals::track_finished(self.cp.status_slot.take());
             self.cp.exit_code = exit_code;
             self.cp.finished_time = monotonic_time_ms();
 
             if debug_opts || exit_codes {
-                let (magenta, cyan) = if colorize {
-                    (Color::Magenta, Color::Cyan)
-                } else {
-                    (Color::Empty, Color::Empty)
-                };
+                let (magenta, cyan) = (&theme.meta, &theme.hostname);
 
-                let code_color = if !colorize {
-                    Color::Empty
-                } else if self.cp.exit_code == 0 {
-                    Color::Green
+                let code_color = if self.cp.exit_code == 0 {
+                    &theme.exit_ok
                 } else {
-                    Color::Red
+                    &theme.exit_fail
                 };
+                // the final line of a host is told apart from its output by the prefix color
+                let prefix_color = if color_by_exit { code_color } else { cyan };
 
                 let delta = self.cp.finished_time - self.cp.started_time;
 
                 if !*newline_print {
-                    print!("\n");
+                    out!("\n");
                     *newline_print = true;
                 }
 
                 if debug_opts {
-                    print!(
+                    out!(
                         "[{}] {} {} exited: {} ",
-                        PROG_NAME.colorize(&cyan),
-                        pid.to_string().as_str().colorize(&magenta),
-                        self.name.as_str().colorize(&cyan),
-                        self.cp.exit_code.to_string().as_str().colorize(&code_color)
+                        PROG_NAME.colorize(cyan),
+                        pid.to_string().as_str().colorize(magenta),
+                        self.name.as_ref().colorize(cyan),
+                        self.cp.exit_code.to_string().as_str().colorize(code_color)
                     );
                 } else {
-                    print!(
+                    out!(
                         "[{}] exited: {} ",
-                        self.name.as_str().colorize(&cyan),
-                        self.cp.exit_code.to_string().as_str().colorize(&code_color)
+                        self.name.as_ref().colorize(prefix_color),
+                        self.cp.exit_code.to_string().as_str().colorize(code_color)
                     );
                 }
 
-                println!("({} ms)", delta.to_string().as_str().colorize(&magenta));
+                match &self.latency {
+                    Some(Ok(latency)) => outln!(
+                        "({} ms, connect {} ms)",
+                        delta.to_string().as_str().colorize(magenta),
+                        latency.as_millis().to_string().as_str().colorize(magenta)
+                    ),
+                    Some(Err(_)) => outln!(
+                        "({} ms, connect failed)",
+                        delta.to_string().as_str().colorize(magenta)
+                    ),
+                    None => outln!("({} ms)", delta.to_string().as_str().colorize(magenta)),
+                }
             }
         }
 
         Ok(())
     }
 
-    fn register_cp_fd(&self, mode: &ProgMode, watcher: &Fdwatcher) -> Result<(), RuntimeError> {
-        match *mode {
-            ProgMode::Join => {
-                if let Err(_) = watcher.add(self.cp.stdio_fd) {
-                    return Err(RuntimeError::MonitorFdError("EPOLL_CTL_ADD".to_string()));
-                }
+    fn register_cp_fd(&self, joined: bool, watcher: &Fdwatcher) -> Result<(), RuntimeError> {
+        if joined {
+            if let Err(_) = watcher.add(self.cp.stdio_fd) {
+                return Err(RuntimeError::MonitorFdError("EPOLL_CTL_ADD".to_string()));
             }
-            _ => {
-                if let Err(_) = watcher.add(self.cp.stdout_fd) {
-                    return Err(RuntimeError::MonitorFdError("EPOLL_CTL_ADD".to_string()));
-                }
-                if let Err(_) = watcher.add(self.cp.stderr_fd) {
-                    return Err(RuntimeError::MonitorFdError("EPOLL_CTL_ADD".to_string()));
-                }
+        } else {
+            if let Err(_) = watcher.add(self.cp.stdout_fd) {
+                return Err(RuntimeError::MonitorFdError("EPOLL_CTL_ADD".to_string()));
+            }
+            if let Err(_) = watcher.add(self.cp.stderr_fd) {
+                return Err(RuntimeError::MonitorFdError("EPOLL_CTL_ADD".to_string()));
             }
         }
         Ok(())
@@ -440,63 +869,190 @@ impl Host {
 
 #[derive(Debug)]
 struct SshOpts {
-    identity: Option<String>,
+    identities: Vec<String>,
     login: Option<String>,
     quiet: bool,
+    // -A, the local agent is forwarded to every host
+    forward_agent: bool,
     port: Option<u16>,
+    // -J, the bastion of every host without `jump` metadata of its own
+    jump: Option<String>,
     options: Vec<String>,
+    // seconds the remote command may run, enforced by timeout(1) on the remote host
+    remote_timeout: Option<u32>,
+    // the ssh binary, `ssh` from PATH unless --ssh-path is given
+    program: String,
+    // --ssh-config, read instead of ~/.ssh/config by ssh and by sshp4ru
+    config_file: Option<String>,
 }
 
 impl SshOpts {
+    // a host's `port` metadata, from --dns-srv or a hosts file, overrides -p
+    fn port_for(&self, host: &Host) -> Option<u16> {
+        host.meta
+            .get("port")
+            .and_then(|port| port.parse().ok())
+            .or(self.port)
+    }
+
+    // where ssh connects to for a host: the ssh config's HostName and Port unless the
+    // command line says otherwise
+    fn connect_target(&self, host: &Host) -> (String, Option<u16>) {
+        let address = match &host.ssh_defaults.hostname {
+            Some(hostname) => hostname.clone(),
+            None => known_hosts::address(&host.full_name).to_string(),
+        };
+        (address, self.port_for(host).or(host.ssh_defaults.port))
+    }
+
+    // the bastion a host is reached through: its `jump` metadata, else -J, a global
+    // -o ProxyJump or the ssh config's
+    fn jump_target(&self, host: &Host) -> Option<String> {
+        if let Some(jump) = host.meta.get("jump").or(self.jump.as_ref()) {
+            return Some(jump.clone());
+        }
+        self.options
+            .iter()
+            .find_map(|opt| {
+                let (key, value) = opt.split_once(|c: char| c == '=' || c.is_whitespace())?;
+                if key.trim().eq_ignore_ascii_case("ProxyJump") {
+                    Some(value.trim().to_string())
+                } else {
+                    None
+                }
+            })
+            .or_else(|| host.ssh_defaults.proxy_jump.clone())
+    }
+
+    // the newest feature the run needs from ssh and the OpenSSH release that added it
+    fn required_version(&self, hosts: &[Rc<RefCell<Host>>]) -> Option<(&'static str, (u32, u32))> {
+        let option = |name: &str| {
+            self.options.iter().find_map(|opt| {
+                let (key, value) = opt.split_once('=')?;
+                key.eq_ignore_ascii_case(name).then_some(value)
+            })
+        };
+        let mut required = Vec::new();
+        if hosts
+            .iter()
+            .any(|host| self.jump_target(&host.borrow()).is_some())
+        {
+            required.push(("ProxyJump", ssh_options::PROXY_JUMP_SINCE));
+        }
+        if option("ControlPersist").is_some_and(|value| !value.eq_ignore_ascii_case("no")) {
+            required.push(("ControlPersist", ssh_options::CONTROL_PERSIST_SINCE));
+        }
+        required.into_iter().max_by_key(|(_, since)| *since)
+    }
+
+    // the argv of ssh, every argument passed on as it is; ssh joins the remote command
+    // with spaces and the remote shell parses it, like `ssh host <command...>` does
     fn build_ssh_command(
         &self, host: &Host, remote_command: &[String],
-    ) -> Result<String, RuntimeError> {
+    ) -> Result<Vec<String>, RuntimeError> {
+        let remote = remote_command
+            .iter()
+            .map(|arg| host.expand_meta(arg))
+            .collect();
+        self.build_expanded_ssh_command(host, remote)
+    }
+
+    // build_ssh_command for a remote command with {meta.<key>} already filled in
+    fn build_expanded_ssh_command(
+        &self, host: &Host, remote: Vec<String>,
+    ) -> Result<Vec<String>, RuntimeError> {
         // base ssh command part
-        let mut ssh_command = String::from("ssh");
+        let mut ssh_command = vec![self.program.clone()];
 
-        if let Some(id) = &self.identity {
-            ssh_command.push_str(&format!(" -i {}", id));
+        // ahead of the rest, the options given here override what the file sets
+        if let Some(file) = &self.config_file {
+            ssh_command.extend(["-F".to_string(), file.clone()]);
+        }
+        for id in self.identities.iter() {
+            ssh_command.extend(["-i".to_string(), id.clone()]);
         }
         if let Some(login) = &self.login {
-            ssh_command.push_str(&format!(" -l {}", login));
+            ssh_command.extend(["-l".to_string(), login.clone()]);
         }
 
-        if let Some(port) = self.port {
-            ssh_command.push_str(&format!(" -p {}", port));
+        if let Some(port) = self.port_for(host) {
+            ssh_command.extend(["-p".to_string(), port.to_string()]);
         }
         if self.quiet {
-            ssh_command.push_str(" -q");
+            ssh_command.push("-q".to_string());
         }
-        if self.options.len() > 0 {
-            ssh_command.push_str(" -o");
-            for opt in self.options.iter() {
-                ssh_command.push_str(&format!(" {}", opt));
-            }
+        if self.forward_agent {
+            ssh_command.push("-A".to_string());
+        }
+        if let Some(jump) = host.meta.get("jump").or(self.jump.as_ref()) {
+            ssh_command.extend(["-J".to_string(), jump.clone()]);
+        }
+        for opt in self.options.iter() {
+            ssh_command.extend(["-o".to_string(), opt.clone()]);
         }
 
-        ssh_command.push_str(format!(" {} ", host.as_str()).as_str());
+        ssh_command.push(host.as_str().to_string());
 
         // remote command part
-        for opt in remote_command.iter() {
-            ssh_command.push_str(&format!(" {}", opt));
+        match self.remote_timeout {
+            Some(secs) => ssh_command.push(remote_timeout_wrapper(secs, &remote.join(" "))),
+            None => ssh_command.extend(remote),
         }
 
         if ssh_command.len() >= MAX_ARGS {
             return Err(RuntimeError::SshCommandLengthExceeded(ssh_command.len()));
         }
-        // println!("ssh command built: {}", ssh_command);
         Ok(ssh_command)
     }
 }
 
+// the interpreter on the #! line of a command file, `sh` without one
+fn script_interpreter(script: &str) -> Vec<String> {
+    match script
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("#!"))
+    {
+        Some(interpreter) if !interpreter.trim().is_empty() => {
+            interpreter.split_whitespace().map(str::to_string).collect()
+        }
+        _ => vec!["sh".to_string()],
+    }
+}
+
+// sudo reads the password from stdin with --ask-become-pass, -k so it always does and the
+// line is never left for the command; without one it fails rather than wait for a prompt
+fn become_wrapper(user: &str, ask_pass: bool, command: &str) -> String {
+    format!(
+        "sudo {} -u {} -- sh -c {}",
+        if ask_pass { "-S -k -p ''" } else { "-n" },
+        shell_quote(user),
+        shell_quote(command)
+    )
+}
+
+// runs the command under timeout(1) where the remote host has it and as is otherwise
+fn remote_timeout_wrapper(secs: u32, command: &str) -> String {
+    format!(
+        "t=;command -v timeout >/dev/null&&t=\"timeout {}\";exec $t sh -c {}",
+        secs,
+        shell_quote(command)
+    )
+}
+
 impl Default for SshOpts {
     fn default() -> SshOpts {
         SshOpts {
-            identity: None,
+            identities: Vec::new(),
             login: None,
             quiet: false,
+            forward_agent: false,
             port: None,
+            jump: None,
             options: Vec::new(),
+            remote_timeout: None,
+            program: String::from("ssh"),
+            config_file: None,
         }
     }
 }
@@ -505,45 +1061,207 @@ impl Default for SshOpts {
 pub struct Config {
     anonymous: bool,
     color: String,
-    debug: bool,
+    debug: DebugCategories,
     exit_codes: bool,
-    file: ScriptInput,
+    // -f in the order given, stdin when there is none
+    host_sources: Vec<Box<dyn HostSource>>,
     group: bool,
     join: bool,
+    // --json-lines, every line printed as a JSON object
+    json_lines: bool,
     max_jobs: u8,
+    // -m auto, max_jobs was derived from the system limits
+    max_jobs_auto: bool,
     dry_run: bool,
     silent: bool,
     trim: bool,
+    // -x runs this instead of ssh, with `{host}` and `{meta.<key>}` filled in per host
     exec_path: Option<String>,
+    // and its words, split like a shell would
+    exec_template: Vec<String>,
     max_line_length: u16,
     max_output_length: u16,
+    lock: Option<String>,
+    lock_wait: bool,
+    serialize_per_host: bool,
+    per_jump_limit: Option<usize>,
+    // --control-master: the private directory of this run's ControlPath sockets, picked
+    // up front so the ssh options can name it
+    control_dir: Option<PathBuf>,
+    // --mux: the masters are opened before the first job
+    mux: bool,
+    retries: u32,
+    retry_policy: Option<RetryPolicy>,
+    retry_delay: Option<Duration>,
+    compress_output: Option<CompressOutput>,
+    reconnect: Option<ReconnectPolicy>,
+    on_epipe: EpipePolicy,
+    // soft and hard limit, in bytes
+    total_output_limit: Option<(u64, u64)>,
+    per_host_buffer_limit: Option<BufferLimit>,
+    known_hosts: Option<String>,
+    learn_hostkeys: bool,
+    // --tty, every host gets a pty of its own, ssh one on the remote host with -tt
+    tty: bool,
+    // every host has to be in known_hosts before the run starts, --accept-new lets ssh
+    // add the missing ones instead
+    check_known_hosts: bool,
+    accept_new: bool,
+    host_key_policy: Option<HostKeyPolicy>,
+    doctor: bool,
+    serve: bool,
+    // the diff-reports subcommand, the two reports are the command's arguments
+    diff_reports: bool,
+    // the merge-reports subcommand, the reports are the command's arguments
+    merge_reports: bool,
+    // --regression-threshold, percent slower that counts as a regression
+    regression_threshold: Option<u32>,
+    serve_socket: Option<String>,
+    serve_listen: Option<String>,
+    // HTTP requests must carry this file's token as `Authorization: Bearer <token>`
+    serve_token_file: Option<String>,
+    // jobs that do not list their hosts run on this file, reloaded whenever it changes
+    serve_hosts: Option<String>,
+    // the daemon's own options, passed on to the sshp4ru running each job
+    serve_args: Vec<String>,
+    lenient: bool,
+    warnings: Warnings,
+    align: bool,
+    mark_streams: bool,
+    // line mode stops printing a host after this many lines, its pipes are still drained
+    max_lines: Option<u32>,
+    // line mode holds back a host's lines until this many are there (or one matches
+    // --atomic-until) and prints them together
+    atomic_lines: Option<u32>,
+    atomic_until: Option<regex::bytes::Regex>,
+    // a host that wrote to stderr fails even when its command exited 0
+    fail_on_stderr: bool,
+    // a host printing a line matching this is terminated and fails
+    kill_on_output: Option<regex::bytes::Regex>,
+    // any host printing a line matching this stops the whole run
+    abort_on_output: Option<regex::bytes::Regex>,
+    // join mode groups hosts by a field of their output parsed as JSON, the whole
+    // document unless --field is given
+    parse_json: bool,
+    json_field: Option<JsonPath>,
+    // join output parsed as one number per host and summed up (or min, max, avg)
+    aggregate: Option<Vec<Aggregate>>,
+    // -g prints each host's output as one block once it exited
+    buffered: bool,
+    // the [host] prefix of the exit line (or --buffered header) takes the exit code's color
+    color_by_exit: bool,
+    // host listings (-d, SIGUSR1 status) are sorted by name instead of input order
+    sorted: bool,
+    // SIGUSR1 prints the status as one JSON line
+    status_json: bool,
+    theme: Theme,
+    check: bool,
+    check_warning: u32,
+    check_critical: u32,
+    audit_log: Option<String>,
+    events_socket: Option<String>,
+    confirm: bool,
+    max_hosts: Option<usize>,
+    yes: bool,
+    limits: Vec<(String, Vec<String>)>,
+    report: Option<String>,
+    // the report also records how and where it was produced
+    report_env: bool,
+    // -n prints the plan as JSON instead of text
+    plan_json: bool,
+    print_config: Option<ConfigFormat>,
+    // written to the stdin of every child, which otherwise gets /dev/null
+    child_stdin: Option<Vec<u8>>,
+    // the options as given, for --print-config to write out and --config-from to read back
+    given_options: Vec<String>,
+    // the ssh children start from CLEAN_ENV_KEEP instead of our whole environment
+    clean_env: bool,
+    // set in the environment of the ssh children, after --clean-env
+    child_env: Vec<(String, String)>,
+    summary_by: Option<String>,
+    failed_hosts_out: Option<String>,
+    notes: Option<String>,
+    priority_map: Option<String>,
+    // TCP connect latency of every host is measured before the run
+    probe_latency: bool,
+    // the probe also records every host's address and ssh banner for the report
+    record_server_info: bool,
+    // hosts start round-robin across the files they were read from
+    interleave_groups: bool,
+    // rolling per host success rates, updated after every run
+    scoreboard: Option<String>,
+    // hosts whose success rate on the scoreboard is below this percentage are not run
+    quarantine_below: Option<f64>,
+    // a quarantined host runs again once its last run is this old
+    quarantine_for: Option<Duration>,
+    receipts_dir: Option<String>,
+    // remote files fetched from every host the command succeeded on, into a
+    // directory per host under --collect-dir
+    collect: Option<String>,
+    collect_dir: Option<String>,
+    // every host's output is also written to <dir>/<host>.out and .err
+    output_dir: Option<String>,
+    // run on every host whose command failed, its output goes in the report
+    on_remote_failure: Option<String>,
+    // commands run for a host that exited with a given code, on it or here
+    map_exit: Vec<exit_map::ExitRule>,
+    // hosts with a successful receipt for the same command this recent are not run again
+    skip_if_succeeded_within: Option<Duration>,
+    // running jobs are killed once the run takes longer; with receipts, the run is also
+    // estimated up front from the durations they recorded
+    deadline: Option<Duration>,
+    // -m is raised, up to what `-m auto` allows, if the estimate misses the deadline
+    deadline_raise_jobs: bool,
+    require_reviewed: Option<String>,
+    redactor: Redactor,
+    // starts, signals and reaps the jobs
+    spawner: Box<dyn Spawner>,
+    notify: bool,
+    notify_command: Option<String>,
+    #[cfg(feature = "otlp")]
+    otlp_endpoint: Option<String>,
 
     // SSH user options
     ssh_options: SshOpts,
     //base_ssh_command
     remote_command: Vec<String>,
+    // fresh for every run, in its reports, logs and events and `{run_id}` in the command
+    run_id: String,
+    // --export-run-id, the remote command sees the run id as $SSHP4RU_RUN_ID
+    export_run_id: bool,
+    // --become, the user the remote command runs as through sudo
+    become_user: Option<String>,
+    // the sudo password is asked for once and written to every child's stdin
+    ask_become_pass: bool,
     mode: ProgMode,
 }
 
 impl fmt::Debug for Config {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let proc_id = std::process::id().to_string();
-        let colorize = self.color == "auto" || self.color == "on";
-        let (cyan, green) = if colorize {
-            (Color::Cyan, Color::Green)
-        } else {
-            (Color::Empty, Color::Empty)
-        };
+        let (cyan, green) = (self.theme.hostname.clone(), self.theme.stdout.clone());
 
         write!(
             f,
             "[{}] ssh command: [ {}{}{} ",
             PROG_NAME.colorize(&cyan),
             "'".colorize(&green),
-            "ssh".colorize(&green),
+            self.ssh_options.program.as_str().colorize(&green),
             "'".colorize(&green)
         )?;
-        if let Some(id) = &self.ssh_options.identity {
+        if let Some(file) = &self.ssh_options.config_file {
+            write!(
+                f,
+                "{}{}{} {}{}{} ",
+                "'".colorize(&green),
+                "-F".colorize(&green),
+                "'".colorize(&green),
+                "'".colorize(&green),
+                file.as_str().colorize(&green),
+                "'".colorize(&green)
+            )?;
+        }
+        for id in self.ssh_options.identities.iter() {
             write!(
                 f,
                 "{}",
@@ -600,14 +1318,35 @@ impl fmt::Debug for Config {
                 )
             )?;
         }
+        if self.ssh_options.forward_agent {
+            write!(
+                f,
+                "{}{}{} ",
+                "'".colorize(&green),
+                "-A".colorize(&green),
+                "'".colorize(&green)
+            )?;
+        }
+        if let Some(jump) = &self.ssh_options.jump {
+            write!(
+                f,
+                "{}{}{} {}{}{} ",
+                "'".colorize(&green),
+                "-J".colorize(&green),
+                "'".colorize(&green),
+                "'".colorize(&green),
+                jump.as_str().colorize(&green),
+                "'".colorize(&green)
+            )?;
+        }
         for opt in self.ssh_options.options.iter() {
-            print!(
+            out!(
                 "{}{}{} {}{}{} ",
                 "'".colorize(&green),
                 "-o".colorize(&green),
                 "'".colorize(&green),
                 "'".colorize(&green),
-                opt.as_str().colorize(&green),
+                self.redactor.redact(opt).as_ref().colorize(&green),
                 "'".colorize(&green)
             );
         }
@@ -621,7 +1360,7 @@ impl fmt::Debug for Config {
                 format!(
                     "{}{}{}",
                     "'".colorize(&green),
-                    arg.as_str().colorize(&green),
+                    self.redactor.redact(arg).as_ref().colorize(&green),
                     "'".colorize(&green)
                 )
             )?;
@@ -634,28 +1373,62 @@ impl fmt::Debug for Config {
             PROG_NAME.colorize(&cyan),
             proc_id.as_str().colorize(&green)
         )?;
-        writeln!(
-            f,
-            "[{}] mode: {}",
-            PROG_NAME.colorize(&cyan),
-            self.mode().colorize(&green)
-        )?;
+        // the rest is what --print-config resolves to, on one line
         write!(
             f,
-            "[{}] max-jobs: {}",
+            "[{}] config: {}",
             PROG_NAME.colorize(&cyan),
-            self.max_jobs.to_string().as_str().colorize(&green)
+            self.to_json().to_string().as_str().colorize(&green)
         )
     }
 }
 
+// a value that looks like an option means the real value was left out, so it is not
+// consumed; a lone `-` (stdin) is only accepted where the option reads from it
+fn option_value<'a>(
+    args: &'a [String], cnt: usize, opt: &str, allow_stdin: bool,
+) -> Result<&'a String, ParseError> {
+    match args.get(cnt) {
+        Some(value) if value == "-" && allow_stdin => Ok(value),
+        Some(value) if !value.starts_with('-') => Ok(value),
+        _ => Err(ParseError::MissingArgument(opt.to_string())),
+    }
+}
+
 impl Config {
     pub fn new(args: &[String]) -> Result<Config, ParseError> {
         let mut config = Config::default();
         let mut help_opt = false;
+        let mut version_opt = false;
+        let mut json_opt = false;
+        let mut agent_only = false;
         let mut unknown_opt = false;
+        let mut config_from: Option<String> = None;
+        let mut command_file: Option<String> = None;
+        let mut command_via_stdin = false;
+        #[cfg(feature = "cloud")]
+        let mut cloud = CloudSource {
+            provider: String::new(),
+            filters: Vec::new(),
+            address: CloudAddress::Private,
+        };
+        #[cfg(feature = "cloud")]
+        let mut cloud_address = false;
 
         let mut cnt = 0;
+        // subcommands come first, everything else starts with options
+        match args.first().map(String::as_str) {
+            Some("doctor") => config.doctor = true,
+            Some("serve") => config.serve = true,
+            Some("diff-reports") => config.diff_reports = true,
+            Some("merge-reports") => config.merge_reports = true,
+            _ => {}
+        }
+        let subcommand =
+            config.doctor || config.serve || config.diff_reports || config.merge_reports;
+        if subcommand {
+            cnt += 1;
+        }
         while cnt < args.len() {
             let arg = args.get(cnt).unwrap();
             if !(arg.starts_with("-") || arg.starts_with("--")) {
@@ -664,150 +1437,800 @@ impl Config {
 
             match arg.as_str() {
                 "-a" | "--anonymous" => config.anonymous = true,
-                "-d" | "--debug" => config.debug = true,
+                "-d" => config.debug = DebugCategories::all(),
+                "--debug" => {
+                    cnt += 1;
+                    let spec = option_value(args, cnt, "--debug", false)?;
+                    config.debug = DebugCategories::from_spec(spec)
+                        .map_err(|e| ParseError::InvalidArgument("--debug".to_string(), e))?;
+                }
                 "-e" | "--exit-codes" => config.exit_codes = true,
                 "-g" | "--group" => config.group = true,
                 "-j" | "--join" => config.join = true,
                 "-n" | "--dry-run" => config.dry_run = true,
                 "-q" | "--quiet" => config.ssh_options.quiet = true,
+                "-A" | "--forward-agent" => config.ssh_options.forward_agent = true,
                 "-s" | "--silent" => config.silent = true,
                 "-t" | "--trim" => config.trim = true,
                 "-m" | "--max-jobs" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(max_jobs) => config.max_jobs = max_jobs.parse().unwrap_or(0),
-                        None => {
-                            // actual argument not provided
-                            config.max_jobs = 0;
-                            cnt -= 1;
+                    match option_value(args, cnt, "-m", false)?.as_str() {
+                        "auto" => {
+                            config.max_jobs = auto_max_jobs();
+                            config.max_jobs_auto = true;
                         }
+                        max_jobs => config.max_jobs = max_jobs.parse().unwrap_or(0),
                     }
                 }
                 "--max-line-length" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(max_line_length) => {
-                            config.max_line_length = max_line_length.parse().unwrap_or(0)
-                        }
-                        None => {
-                            // actual argument not provided
-                            config.max_line_length = 0;
-                            cnt -= 1;
-                        }
-                    }
+                    let max_line_length = option_value(args, cnt, "--max-line-length", false)?;
+                    config.max_line_length = max_line_length.parse().unwrap_or(0);
                 }
                 "--max-output-length" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(max_output_length) => {
-                            config.max_output_length = max_output_length.parse().unwrap_or(0)
-                        }
-                        None => {
-                            config.max_output_length = 0;
-                            cnt -= 1;
-                        }
-                    }
+                    let max_output_length = option_value(args, cnt, "--max-output-length", false)?;
+                    config.max_output_length = max_output_length.parse().unwrap_or(0);
                 }
-                "-p" | "--port" => {
+                "--lock" => {
+                    cnt += 1;
+                    config.lock = Some(option_value(args, cnt, "--lock", false)?.clone());
+                }
+                "--lock-wait" => config.lock_wait = true,
+                "--serialize-per-host" => config.serialize_per_host = true,
+                "--compress-output" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(port_str) => {
-                            config.ssh_options.port = match port_str.parse::<u16>() {
-                                Ok(port) => Some(port),
-                                Err(_) => return Err(ParseError::ParsePortError),
-                            };
+                    let spec = option_value(args, cnt, "--compress-output", false)?;
+                    config.compress_output =
+                        Some(CompressOutput::from_spec(spec).map_err(|e| {
+                            ParseError::InvalidArgument("--compress-output".to_string(), e)
+                        })?);
+                }
+                "--per-host-buffer-limit" => {
+                    cnt += 1;
+                    let spec = option_value(args, cnt, "--per-host-buffer-limit", false)?;
+                    config.per_host_buffer_limit =
+                        Some(BufferLimit::from_spec(spec).map_err(|e| {
+                            ParseError::InvalidArgument("--per-host-buffer-limit".to_string(), e)
+                        })?);
+                }
+                "--total-output-limit" => {
+                    cnt += 1;
+                    let spec = option_value(args, cnt, "--total-output-limit", false)?;
+                    // <soft>[,<hard>], the hard limit defaults to twice the soft one
+                    let (soft, hard) = match spec.split_once(',') {
+                        Some((soft, hard)) => (soft.parse::<u64>(), hard.parse::<u64>()),
+                        None => (spec.parse::<u64>(), spec.parse::<u64>().map(|s| s * 2)),
+                    };
+                    match (soft, hard) {
+                        (Ok(soft), Ok(hard)) if soft > 0 && hard >= soft => {
+                            config.total_output_limit = Some((soft, hard))
                         }
-                        None => {
-                            //cnt -= 1;
-                            return Err(ParseError::ParsePortError);
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--total-output-limit".to_string(),
+                                "expected <soft>[,<hard>] bytes with 0 < soft <= hard".to_string(),
+                            ))
                         }
                     }
                 }
-                "-c" | "--color" => {
+                "--retries" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(color) => config.color = String::from(color),
-                        None => {
-                            config.color = "".to_string();
-                            cnt -= 1;
+                    match option_value(args, cnt, "--retries", false)?.parse::<u32>() {
+                        Ok(retries) => config.retries = retries,
+                        Err(_) => {
+                            return Err(ParseError::InvalidArgument(
+                                "--retries".to_string(),
+                                "must be an integer >= 0".to_string(),
+                            ))
                         }
                     }
                 }
-                "-l" | "--login" => {
+                "--retry-on" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(login) => config.ssh_options.login = Some(String::from(login)),
-                        None => {
-                            config.ssh_options.login = None;
-                            cnt -= 1;
-                        }
-                    }
+                    let spec = option_value(args, cnt, "--retry-on", false)?;
+                    config.retry_policy =
+                        Some(RetryPolicy::from_spec(spec).map_err(|e| {
+                            ParseError::InvalidArgument("--retry-on".to_string(), e)
+                        })?);
                 }
-                "-i" | "--identity" => {
+                "--retry-delay" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(identity) => {
-                            if let Some(next_arg) = args.get(cnt) {
-                                if next_arg == "-" {
-                                    config.ssh_options.identity = Some(String::from("-"));
-                                } else {
-                                    config.ssh_options.identity = Some(String::from(identity))
-                                }
-                            }
+                    let delay = option_value(args, cnt, "--retry-delay", false)?;
+                    config.retry_delay = Some(parse_duration(delay).map_err(|e| {
+                        ParseError::InvalidArgument("--retry-delay".to_string(), e)
+                    })?);
+                }
+                "--reconnect" => {
+                    cnt += 1;
+                    let spec = option_value(args, cnt, "--reconnect", false)?;
+                    config.reconnect =
+                        Some(ReconnectPolicy::from_spec(spec).map_err(|e| {
+                            ParseError::InvalidArgument("--reconnect".to_string(), e)
+                        })?);
+                }
+                "--on-epipe" => {
+                    cnt += 1;
+                    config.on_epipe = match option_value(args, cnt, "--on-epipe", false)?.as_str() {
+                        "stop" => EpipePolicy::Stop,
+                        "continue" => EpipePolicy::Continue,
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--on-epipe".to_string(),
+                                "must be `stop` or `continue`".to_string(),
+                            ))
                         }
-                        None => {
-                            config.ssh_options.identity = Some("".to_string());
-                            cnt -= 1;
+                    };
+                }
+                "--per-jump-limit" => {
+                    cnt += 1;
+                    match option_value(args, cnt, "--per-jump-limit", false)?.parse::<usize>() {
+                        Ok(limit) if limit > 0 => config.per_jump_limit = Some(limit),
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--per-jump-limit".to_string(),
+                                "must be an integer > 0".to_string(),
+                            ))
                         }
                     }
                 }
-                "-f" | "--file" => {
+                "--align" => config.align = true,
+                "--mark-streams" => config.mark_streams = true,
+                "--json-lines" => config.json_lines = true,
+                "--fail-on-stderr" => config.fail_on_stderr = true,
+                "--kill-on-output" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(file) => {
-                            if let Some(next_arg) = args.get(cnt) {
-                                if next_arg == "-" {
-                                    config.file = ScriptInput::Stdin(io::stdin());
-                                } else {
-                                    config.file = ScriptInput::HostsFile(file.clone());
-                                }
-                            }
-                        }
-                        None => {
-                            config.file = ScriptInput::HostsFile("".to_string());
-                            cnt -= 1;
+                    let pattern = option_value(args, cnt, "--kill-on-output", false)?;
+                    config.kill_on_output =
+                        Some(regex::bytes::Regex::new(pattern).map_err(|e| {
+                            ParseError::InvalidArgument(
+                                "--kill-on-output".to_string(),
+                                e.to_string(),
+                            )
+                        })?);
+                }
+                "--parse-json" => config.parse_json = true,
+                "--field" => {
+                    cnt += 1;
+                    let field = option_value(args, cnt, "--field", false)?;
+                    config.json_field = Some(
+                        JsonPath::parse(field)
+                            .map_err(|e| ParseError::InvalidArgument("--field".to_string(), e))?,
+                    );
+                }
+                "--aggregate" => {
+                    cnt += 1;
+                    let spec = option_value(args, cnt, "--aggregate", false)?;
+                    config.aggregate =
+                        Some(Aggregate::from_spec(spec).map_err(|e| {
+                            ParseError::InvalidArgument("--aggregate".to_string(), e)
+                        })?);
+                }
+                "--abort-on-output" => {
+                    cnt += 1;
+                    let pattern = option_value(args, cnt, "--abort-on-output", false)?;
+                    config.abort_on_output =
+                        Some(regex::bytes::Regex::new(pattern).map_err(|e| {
+                            ParseError::InvalidArgument(
+                                "--abort-on-output".to_string(),
+                                e.to_string(),
+                            )
+                        })?);
+                }
+                "--max-lines" => {
+                    cnt += 1;
+                    let max_lines = option_value(args, cnt, "--max-lines", false)?;
+                    config.max_lines = match max_lines.parse::<u32>() {
+                        Ok(max_lines) if max_lines > 0 => Some(max_lines),
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--max-lines".to_string(),
+                                format!("expected a positive number, got `{}`", max_lines),
+                            ))
                         }
-                    }
+                    };
                 }
-                "-o" | "--option" => {
+                "--atomic-lines" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(option) => config.ssh_options.options.push(option.clone()),
-                        None => {
-                            config.ssh_options.options.push("".to_string());
-                            cnt -= 1;
+                    let atomic_lines = option_value(args, cnt, "--atomic-lines", false)?;
+                    config.atomic_lines = match atomic_lines.parse::<u32>() {
+                        Ok(atomic_lines) if atomic_lines > 0 => Some(atomic_lines),
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--atomic-lines".to_string(),
+                                format!("expected a positive number, got `{}`", atomic_lines),
+                            ))
                         }
-                    }
+                    };
                 }
-                "-x" | "--exec" => {
+                "--atomic-until" => {
                     cnt += 1;
-                    match args.get(cnt) {
-                        Some(exec_path) => config.exec_path = Some(exec_path.clone()),
-                        None => {
-                            config.exec_path = Some("".to_string());
-                            cnt -= 1;
+                    let pattern = option_value(args, cnt, "--atomic-until", false)?;
+                    config.atomic_until = Some(regex::bytes::Regex::new(pattern).map_err(|e| {
+                        ParseError::InvalidArgument("--atomic-until".to_string(), e.to_string())
+                    })?);
+                }
+                "--buffered" => config.buffered = true,
+                "--color-by-exit" => config.color_by_exit = true,
+                "--sorted" => config.sorted = true,
+                "--report-env" => config.report_env = true,
+                "--clean-env" => config.clean_env = true,
+                "--child-env" => {
+                    cnt += 1;
+                    let pair = option_value(args, cnt, "--child-env", false)?;
+                    match pair.split_once('=') {
+                        Some((key, value)) if !key.is_empty() => {
+                            config.child_env.push((key.to_string(), value.to_string()))
+                        }
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--child-env".to_string(),
+                                format!("expected KEY=VALUE, got `{}`", pair),
+                            ))
                         }
                     }
                 }
-                "-v" | "--version" => {
-                    return Err(ParseError::VersionRequested);
-                }
+                "--print-config" => {
+                    // the format is optional, anything else is left for the next option
+                    config.print_config = Some(match args.get(cnt + 1).map(String::as_str) {
+                        Some("toml") => {
+                            cnt += 1;
+                            ConfigFormat::Toml
+                        }
+                        Some("json") => {
+                            cnt += 1;
+                            ConfigFormat::Json
+                        }
+                        _ => ConfigFormat::Json,
+                    });
+                }
+                "--command-file" => {
+                    cnt += 1;
+                    command_file = Some(option_value(args, cnt, "--command-file", false)?.clone());
+                }
+                "--command-via-stdin" => command_via_stdin = true,
+                "--stdin-string" => {
+                    cnt += 1;
+                    let text = option_value(args, cnt, "--stdin-string", false)?;
+                    // a newline is added like a shell here-string (<<<) does
+                    let data = format!("{}\n", text).into_bytes();
+                    if data.len() > MAX_CHILD_STDIN {
+                        return Err(ParseError::InvalidArgument(
+                            "--stdin-string".to_string(),
+                            format!("at most {} bytes can be sent on stdin", MAX_CHILD_STDIN),
+                        ));
+                    }
+                    config.child_stdin = Some(data);
+                }
+                "--config-from" => {
+                    cnt += 1;
+                    config_from = Some(option_value(args, cnt, "--config-from", false)?.clone());
+                }
+                "--report-format" => {
+                    cnt += 1;
+                    config.plan_json =
+                        match option_value(args, cnt, "--report-format", false)?.as_str() {
+                            "text" => false,
+                            "json" => true,
+                            _ => {
+                                return Err(ParseError::InvalidArgument(
+                                    "--report-format".to_string(),
+                                    "expected text or json".to_string(),
+                                ))
+                            }
+                        };
+                }
+                "--status-format" => {
+                    cnt += 1;
+                    config.status_json =
+                        match option_value(args, cnt, "--status-format", false)?.as_str() {
+                            "text" => false,
+                            "json" => true,
+                            _ => {
+                                return Err(ParseError::InvalidArgument(
+                                    "--status-format".to_string(),
+                                    "expected text or json".to_string(),
+                                ))
+                            }
+                        };
+                }
+                "--theme" => {
+                    cnt += 1;
+                    let spec = option_value(args, cnt, "--theme", false)?;
+                    config.theme = Theme::from_spec(spec)
+                        .map_err(|e| ParseError::InvalidArgument("--theme".to_string(), e))?;
+                }
+                "--check" => config.check = true,
+                "--regression-threshold" => {
+                    cnt += 1;
+                    let percent = option_value(args, cnt, "--regression-threshold", false)?;
+                    match percent.parse::<u32>() {
+                        Ok(percent) => config.regression_threshold = Some(percent),
+                        Err(_) => {
+                            return Err(ParseError::InvalidArgument(
+                                "--regression-threshold".to_string(),
+                                "must be a percentage, an integer >= 0".to_string(),
+                            ))
+                        }
+                    }
+                }
+                "--check-warning" => {
+                    cnt += 1;
+                    config.check_warning = option_value(args, cnt, "--check-warning", false)?
+                        .parse()
+                        .unwrap_or(0);
+                    if config.check_warning == 0 {
+                        return Err(ParseError::InvalidArgument(
+                            "--check-warning".to_string(),
+                            "must be an integer > 0".to_string(),
+                        ));
+                    }
+                }
+                "--check-critical" => {
+                    cnt += 1;
+                    config.check_critical = option_value(args, cnt, "--check-critical", false)?
+                        .parse()
+                        .unwrap_or(0);
+                    if config.check_critical == 0 {
+                        return Err(ParseError::InvalidArgument(
+                            "--check-critical".to_string(),
+                            "must be an integer > 0".to_string(),
+                        ));
+                    }
+                }
+                "--audit-log" => {
+                    cnt += 1;
+                    config.audit_log = Some(option_value(args, cnt, "--audit-log", false)?.clone());
+                }
+                "--confirm" => config.confirm = true,
+                "--yes" => config.yes = true,
+                "--remote-timeout" => {
+                    cnt += 1;
+                    match option_value(args, cnt, "--remote-timeout", false)?.parse::<u32>() {
+                        Ok(secs) if secs > 0 => config.ssh_options.remote_timeout = Some(secs),
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--remote-timeout".to_string(),
+                                "must be a number of seconds > 0".to_string(),
+                            ))
+                        }
+                    }
+                }
+                "--max-hosts" => {
+                    cnt += 1;
+                    match option_value(args, cnt, "--max-hosts", false)?.parse::<usize>() {
+                        Ok(max_hosts) if max_hosts > 0 => config.max_hosts = Some(max_hosts),
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--max-hosts".to_string(),
+                                "must be an integer > 0".to_string(),
+                            ))
+                        }
+                    }
+                }
+                "--limit" => {
+                    cnt += 1;
+                    let limit = option_value(args, cnt, "--limit", false)?;
+                    // meta.<key>=<value>[,<value>...]
+                    match limit
+                        .strip_prefix("meta.")
+                        .and_then(|limit| limit.split_once('='))
+                    {
+                        Some((key, values)) if !key.is_empty() && !values.is_empty() => {
+                            config.limits.push((
+                                key.to_string(),
+                                values.split(',').map(str::to_string).collect(),
+                            ))
+                        }
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--limit".to_string(),
+                                "expected meta.<key>=<value>[,<value>...]".to_string(),
+                            ))
+                        }
+                    }
+                }
+                "--collect" => {
+                    cnt += 1;
+                    config.collect = Some(option_value(args, cnt, "--collect", false)?.clone());
+                }
+                "--collect-dir" => {
+                    cnt += 1;
+                    config.collect_dir =
+                        Some(option_value(args, cnt, "--collect-dir", false)?.clone());
+                }
+                "--output-dir" => {
+                    cnt += 1;
+                    config.output_dir =
+                        Some(option_value(args, cnt, "--output-dir", false)?.clone());
+                }
+                "--on-remote-failure" => {
+                    cnt += 1;
+                    config.on_remote_failure =
+                        Some(option_value(args, cnt, "--on-remote-failure", false)?.clone());
+                }
+                "--ssh-config" => {
+                    cnt += 1;
+                    config.ssh_options.config_file =
+                        Some(option_value(args, cnt, "--ssh-config", false)?.clone());
+                }
+                "--map-exit" => {
+                    cnt += 1;
+                    let spec = option_value(args, cnt, "--map-exit", false)?;
+                    let rule = exit_map::ExitRule::from_spec(spec)
+                        .map_err(|e| ParseError::InvalidArgument("--map-exit".to_string(), e))?;
+                    config.map_exit.push(rule);
+                }
+                "--receipts-dir" => {
+                    cnt += 1;
+                    config.receipts_dir =
+                        Some(option_value(args, cnt, "--receipts-dir", false)?.clone());
+                }
+                "--skip-if-succeeded-within" => {
+                    cnt += 1;
+                    let window = option_value(args, cnt, "--skip-if-succeeded-within", false)?;
+                    config.skip_if_succeeded_within =
+                        Some(parse_duration(window).map_err(|e| {
+                            ParseError::InvalidArgument("--skip-if-succeeded-within".to_string(), e)
+                        })?);
+                }
+                "--deadline" => {
+                    cnt += 1;
+                    let deadline = option_value(args, cnt, "--deadline", false)?;
+                    config.deadline =
+                        Some(parse_duration(deadline).map_err(|e| {
+                            ParseError::InvalidArgument("--deadline".to_string(), e)
+                        })?);
+                }
+                "--deadline-raise-jobs" => config.deadline_raise_jobs = true,
+                "--probe-latency" => config.probe_latency = true,
+                "--record-server-info" => config.record_server_info = true,
+                "--export-run-id" => config.export_run_id = true,
+                "--become" => {
+                    // --become-user may have come first
+                    if config.become_user.is_none() {
+                        config.become_user = Some(DEFAULT_BECOME_USER.to_string());
+                    }
+                }
+                "--become-user" => {
+                    cnt += 1;
+                    let user = option_value(args, cnt, "--become-user", false)?;
+                    config.become_user = Some(user.clone());
+                }
+                "--ask-become-pass" => config.ask_become_pass = true,
+                "--interleave-groups" => config.interleave_groups = true,
+                "--scoreboard" => {
+                    cnt += 1;
+                    config.scoreboard =
+                        Some(option_value(args, cnt, "--scoreboard", false)?.clone());
+                }
+                "--quarantine-below" => {
+                    cnt += 1;
+                    let pct = option_value(args, cnt, "--quarantine-below", false)?;
+                    config.quarantine_below = match pct.trim_end_matches('%').parse::<f64>() {
+                        Ok(pct) if (0.0..=100.0).contains(&pct) => Some(pct),
+                        _ => {
+                            return Err(ParseError::InvalidArgument(
+                                "--quarantine-below".to_string(),
+                                format!("expected a percentage from 0 to 100, got `{}`", pct),
+                            ))
+                        }
+                    };
+                }
+                "--quarantine-for" => {
+                    cnt += 1;
+                    let period = option_value(args, cnt, "--quarantine-for", false)?;
+                    config.quarantine_for = Some(parse_duration(period).map_err(|e| {
+                        ParseError::InvalidArgument("--quarantine-for".to_string(), e)
+                    })?);
+                }
+                "--notes" => {
+                    cnt += 1;
+                    config.notes = Some(option_value(args, cnt, "--notes", false)?.clone());
+                }
+                "--priority-map" => {
+                    cnt += 1;
+                    config.priority_map =
+                        Some(option_value(args, cnt, "--priority-map", false)?.clone());
+                }
+                "--failed-hosts-out" => {
+                    cnt += 1;
+                    config.failed_hosts_out =
+                        Some(option_value(args, cnt, "--failed-hosts-out", false)?.clone());
+                }
+                "--summary-by" => {
+                    cnt += 1;
+                    config.summary_by =
+                        Some(option_value(args, cnt, "--summary-by", false)?.clone());
+                }
+                "--report" => {
+                    cnt += 1;
+                    config.report = Some(option_value(args, cnt, "--report", false)?.clone());
+                }
+                "--events-socket" => {
+                    cnt += 1;
+                    config.events_socket =
+                        Some(option_value(args, cnt, "--events-socket", false)?.clone());
+                }
+                "--require-reviewed" => {
+                    cnt += 1;
+                    let hash = option_value(args, cnt, "--require-reviewed", false)?;
+                    config.require_reviewed = Some(hash.to_lowercase());
+                }
+                "--redact" => {
+                    cnt += 1;
+                    let pattern = option_value(args, cnt, "--redact", false)?;
+                    config.redactor.add(pattern).map_err(|e| {
+                        ParseError::InvalidArgument("--redact".to_string(), e.to_string())
+                    })?;
+                }
+                #[cfg(feature = "otlp")]
+                "--otlp-endpoint" => {
+                    cnt += 1;
+                    config.otlp_endpoint =
+                        Some(option_value(args, cnt, "--otlp-endpoint", false)?.clone());
+                }
+                #[cfg(feature = "cloud")]
+                "--cloud" | "--aws-filter" => {
+                    let opt = args[cnt].as_str();
+                    cnt += 1;
+                    let value = option_value(args, cnt, opt, false)?;
+                    let provider = if opt == "--cloud" {
+                        value.as_str()
+                    } else {
+                        // tag:<key>=<value>, or any other filter the aws CLI takes
+                        match value.split_once('=') {
+                            Some((name, values)) if !name.is_empty() && !values.is_empty() => {
+                                cloud.filters.push(value.clone())
+                            }
+                            _ => {
+                                return Err(ParseError::InvalidArgument(
+                                    opt.to_string(),
+                                    "expected <name>=<value>[,<value>...]".to_string(),
+                                ))
+                            }
+                        }
+                        "aws"
+                    };
+                    if !cloud.provider.is_empty() && cloud.provider != provider {
+                        return Err(ParseError::Conflict(
+                            format!("--cloud {}", cloud.provider),
+                            format!("--cloud {}", provider),
+                        ));
+                    }
+                    cloud.provider = provider.to_string();
+                }
+                #[cfg(feature = "cloud")]
+                "--cloud-filter" => {
+                    cnt += 1;
+                    let filter = option_value(args, cnt, "--cloud-filter", false)?;
+                    cloud.filters.push(filter.clone());
+                }
+                #[cfg(feature = "cloud")]
+                "--cloud-address" => {
+                    cnt += 1;
+                    let address = option_value(args, cnt, "--cloud-address", false)?;
+                    cloud.address = CloudAddress::parse(address).ok_or_else(|| {
+                        ParseError::InvalidArgument(
+                            "--cloud-address".to_string(),
+                            "must be `public` or `private`".to_string(),
+                        )
+                    })?;
+                    cloud_address = true;
+                }
+                "--notify" => config.notify = true,
+                "--notify-command" => {
+                    cnt += 1;
+                    config.notify_command =
+                        Some(option_value(args, cnt, "--notify-command", false)?.clone());
+                }
+                "-p" | "--port" => {
+                    cnt += 1;
+                    config.ssh_options.port = match option_value(args, cnt, "-p", false)?.parse() {
+                        Ok(port) => Some(port),
+                        Err(_) => return Err(ParseError::ParsePortError),
+                    };
+                }
+                "-c" | "--color" => {
+                    cnt += 1;
+                    config.color = option_value(args, cnt, "-c", false)?.clone();
+                }
+                "-l" | "--login" => {
+                    cnt += 1;
+                    let login = option_value(args, cnt, "-l", false)?;
+                    config.ssh_options.login = Some(login.clone());
+                }
+                "-J" | "--jump" => {
+                    cnt += 1;
+                    let jump = option_value(args, cnt, "-J", false)?;
+                    config.ssh_options.jump = Some(jump.clone());
+                }
+                "-i" | "--identity" => {
+                    cnt += 1;
+                    let identity = option_value(args, cnt, "-i", false)?;
+                    config.ssh_options.identities.push(identity.clone());
+                }
+                "--agent-only" => agent_only = true,
+                "--known-hosts" => {
+                    cnt += 1;
+                    let path = option_value(args, cnt, "--known-hosts", false)?;
+                    config.known_hosts = Some(path.clone());
+                }
+                "--learn-hostkeys" => config.learn_hostkeys = true,
+                "--tty" => config.tty = true,
+                "--check-known-hosts" => config.check_known_hosts = true,
+                "--accept-new" => config.accept_new = true,
+                "--insecure" | "--strict-host-keys" => {
+                    let policy = if args[cnt] == "--insecure" {
+                        HostKeyPolicy::Insecure
+                    } else {
+                        HostKeyPolicy::Strict
+                    };
+                    match config.host_key_policy {
+                        Some(given) if given != policy => {
+                            return Err(ParseError::Conflict(
+                                given.flag().to_string(),
+                                policy.flag().to_string(),
+                            ))
+                        }
+                        _ => config.host_key_policy = Some(policy),
+                    }
+                }
+                "--lenient" => config.lenient = true,
+                "--socket" => {
+                    cnt += 1;
+                    let path = option_value(args, cnt, "--socket", false)?;
+                    config.serve_socket = Some(path.clone());
+                }
+                "--listen" => {
+                    cnt += 1;
+                    let addr = option_value(args, cnt, "--listen", false)?;
+                    config.serve_listen = Some(addr.clone());
+                }
+                "--listen-token-file" => {
+                    cnt += 1;
+                    let path = option_value(args, cnt, "--listen-token-file", false)?;
+                    config.serve_token_file = Some(path.clone());
+                }
+                "--watch-hosts" => {
+                    cnt += 1;
+                    let path = option_value(args, cnt, "--watch-hosts", false)?;
+                    config.serve_hosts = Some(path.clone());
+                }
+                "-f" | "--file" => {
+                    cnt += 1;
+                    match option_value(args, cnt, "-f", true)?.as_str() {
+                        "-" => config.host_sources.push(Box::new(StdinSource)),
+                        file => config.host_sources.push(Box::new(FileSource {
+                            path: file.to_string(),
+                        })),
+                    }
+                }
+                "--from-etc-hosts" | "--from-known-hosts" => {
+                    let opt = args[cnt].as_str();
+                    cnt += 1;
+                    let pattern = option_value(args, cnt, opt, false)?.clone();
+                    if opt == "--from-etc-hosts" {
+                        config
+                            .host_sources
+                            .push(Box::new(EtcHostsSource { pattern }));
+                    } else {
+                        config
+                            .host_sources
+                            .push(Box::new(KnownHostsSource { pattern }));
+                    }
+                }
+                "--dns-srv" => {
+                    cnt += 1;
+                    let name = option_value(args, cnt, "--dns-srv", false)?;
+                    config
+                        .host_sources
+                        .push(Box::new(DnsSrvSource { name: name.clone() }));
+                }
+                "--consul-service" => {
+                    cnt += 1;
+                    let service = option_value(args, cnt, "--consul-service", false)?;
+                    config.host_sources.push(Box::new(ConsulSource {
+                        service: service.clone(),
+                    }));
+                }
+                "-o" | "--option" => {
+                    cnt += 1;
+                    let option = option_value(args, cnt, "-o", false)?;
+                    config.ssh_options.options.push(
+                        ssh_options::normalize(option)
+                            .map_err(|e| ParseError::InvalidArgument("-o".to_string(), e))?,
+                    );
+                }
+                "--control-master" => config.control_dir = Some(control::control_dir()),
+                "--mux" => {
+                    config.control_dir = Some(control::control_dir());
+                    config.mux = true;
+                }
+                "--ssh-path" => {
+                    cnt += 1;
+                    let program = option_value(args, cnt, "--ssh-path", false)?;
+                    config.ssh_options.program = program.clone();
+                }
+                "-x" | "--exec" => {
+                    cnt += 1;
+                    let exec_path = option_value(args, cnt, "-x", false)?;
+                    config.exec_template = shell_words(exec_path)
+                        .map_err(|e| ParseError::InvalidArgument("-x".to_string(), e))?;
+                    if config.exec_template.is_empty() {
+                        return Err(ParseError::InvalidArgument(
+                            "-x".to_string(),
+                            "no program given".to_string(),
+                        ));
+                    }
+                    config.exec_path = Some(exec_path.clone());
+                }
+                "-v" | "--version" => version_opt = true,
+                "--json" => json_opt = true,
                 "-h" | "--help" => help_opt = true,
+                // the end of the options, the command follows even when it starts with `-`
+                "--" => {
+                    cnt += 1;
+                    break;
+                }
                 _ => unknown_opt = true,
             } // end of match
             cnt += 1;
         } // end of while loop
 
+        let first_option = if subcommand { 1 } else { 0 };
+        // the file's options go first so the ones given here win, its command is only
+        // used when none is given
+        if let Some(path) = config_from {
+            let (options, command) = config_file::read_config_file(&path)
+                .map_err(|e| ParseError::InvalidArgument("--config-from".to_string(), e))?;
+            if options.iter().any(|opt| opt == "--config-from") {
+                return Err(ParseError::InvalidArgument(
+                    "--config-from".to_string(),
+                    format!("{} cannot use --config-from itself", path),
+                ));
+            }
+            let mut expanded = args[..first_option].to_vec();
+            expanded.extend(options);
+            let mut given = args[first_option..cnt].iter();
+            while let Some(arg) = given.next() {
+                if arg == "--config-from" {
+                    given.next();
+                } else {
+                    expanded.push(arg.clone());
+                }
+            }
+            if cnt < args.len() {
+                expanded.extend_from_slice(&args[cnt..]);
+            } else {
+                expanded.extend(command);
+            }
+            return Config::new(&expanded);
+        }
+
+        let mut given = args[first_option..cnt].iter().peekable();
+        while let Some(arg) = given.next() {
+            if arg == "--print-config" {
+                given.next_if(|format| *format == "json" || *format == "toml");
+            } else if arg != "--" {
+                config.given_options.push(arg.clone());
+            }
+        }
+
+        if version_opt {
+            if json_opt {
+                return Err(ParseError::VersionJsonRequested);
+            }
+            return Err(ParseError::VersionRequested);
+        }
+
+        if json_opt {
+            return Err(ParseError::OptionRequires(
+                "--json".to_string(),
+                "--version".to_string(),
+            ));
+        }
+
         if args.len() < 1 {
             return Err(ParseError::ArgCount);
         }
@@ -820,6 +2243,25 @@ impl Config {
             return Err(ParseError::GroupJoinConflict);
         }
 
+        // the line mode presentation has no place in JSON lines
+        for (set, option) in [
+            (config.group, "-g"),
+            (config.join, "-j"),
+            (config.anonymous, "-a"),
+            (config.align, "--align"),
+            (config.mark_streams, "--mark-streams"),
+            (config.max_lines.is_some(), "--max-lines"),
+            (config.atomic_lines.is_some(), "--atomic-lines"),
+            (config.atomic_until.is_some(), "--atomic-until"),
+        ] {
+            if set && config.json_lines {
+                return Err(ParseError::Conflict(
+                    "--json-lines".to_string(),
+                    option.to_string(),
+                ));
+            }
+        }
+
         if config.join && config.silent {
             return Err(ParseError::JoinSilentConflict);
         }
@@ -836,11 +2278,362 @@ impl Config {
             return Err(ParseError::MaxOutputLength);
         }
 
+        if config.deadline_raise_jobs && config.deadline.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--deadline-raise-jobs".to_string(),
+                "--deadline".to_string(),
+            ));
+        }
+
+        if let Some(dir) = &config.control_dir {
+            let taken = config.ssh_options.options.iter().find(|opt| {
+                ["ControlMaster", "ControlPath", "ControlPersist"]
+                    .iter()
+                    .any(|key| {
+                        opt.split('=')
+                            .next()
+                            .is_some_and(|opt| opt.eq_ignore_ascii_case(key))
+                    })
+            });
+            if let Some(opt) = taken {
+                let option = if config.mux {
+                    "--mux"
+                } else {
+                    "--control-master"
+                };
+                return Err(ParseError::Conflict(
+                    option.to_string(),
+                    format!("-o {}", opt),
+                ));
+            }
+            config.ssh_options.options.extend(control::ssh_options(dir));
+        }
+
+        if config.collect.is_some() && config.collect_dir.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--collect".to_string(),
+                "--collect-dir".to_string(),
+            ));
+        }
+
+        if config.collect_dir.is_some() && config.collect.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--collect-dir".to_string(),
+                "--collect".to_string(),
+            ));
+        }
+
+        if config.collect.is_some() && config.exec_path.is_some() {
+            return Err(ParseError::Conflict(
+                "--collect".to_string(),
+                "-x".to_string(),
+            ));
+        }
+
+        if config.become_user.is_some() && config.exec_path.is_some() {
+            return Err(ParseError::Conflict(
+                "--become".to_string(),
+                "-x".to_string(),
+            ));
+        }
+
+        if config.ask_become_pass && config.become_user.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--ask-become-pass".to_string(),
+                "--become".to_string(),
+            ));
+        }
+
+        if config.on_remote_failure.is_some() && config.report.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--on-remote-failure".to_string(),
+                "--report".to_string(),
+            ));
+        }
+
+        if config.on_remote_failure.is_some() && config.exec_path.is_some() {
+            return Err(ParseError::Conflict(
+                "--on-remote-failure".to_string(),
+                "-x".to_string(),
+            ));
+        }
+
+        if config.ssh_options.config_file.is_some() && config.exec_path.is_some() {
+            return Err(ParseError::Conflict(
+                "--ssh-config".to_string(),
+                "-x".to_string(),
+            ));
+        }
+
+        // a local rule runs here either way, a remote one needs ssh
+        if config.map_exit.iter().any(|rule| !rule.local) && config.exec_path.is_some() {
+            return Err(ParseError::Conflict(
+                "--map-exit".to_string(),
+                "-x".to_string(),
+            ));
+        }
+
+        if config.check_warning > config.check_critical {
+            return Err(ParseError::InvalidArgument(
+                "--check-warning".to_string(),
+                format!(
+                    "must not be above the critical threshold ({})",
+                    config.check_critical
+                ),
+            ));
+        }
+
+        if config.skip_if_succeeded_within.is_some() && config.receipts_dir.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--skip-if-succeeded-within".to_string(),
+                "--receipts-dir".to_string(),
+            ));
+        }
+
+        if config.lock_wait && config.lock.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--lock-wait".to_string(),
+                "--lock".to_string(),
+            ));
+        }
+
+        // without keepalives a dead connection can hang until TCP gives up, hours later
+        if config.reconnect.is_some() {
+            for (key, value) in [("ServerAliveInterval", "15"), ("ServerAliveCountMax", "3")] {
+                let prefix = format!("{}=", key.to_lowercase());
+                if !config
+                    .ssh_options
+                    .options
+                    .iter()
+                    .any(|opt| opt.to_lowercase().starts_with(&prefix))
+                {
+                    config
+                        .ssh_options
+                        .options
+                        .push(format!("{}={}", key, value));
+                }
+            }
+        }
+
+        if config.plan_json && !config.dry_run {
+            return Err(ParseError::OptionRequires(
+                "--report-format".to_string(),
+                "-n".to_string(),
+            ));
+        }
+
+        if config.quarantine_below.is_some() && config.scoreboard.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--quarantine-below".to_string(),
+                "--scoreboard".to_string(),
+            ));
+        }
+
+        if config.quarantine_for.is_some() && config.quarantine_below.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--quarantine-for".to_string(),
+                "--quarantine-below".to_string(),
+            ));
+        }
+
+        if config.report_env && config.report.is_none() {
+            return Err(ParseError::OptionRequires(
+                "--report-env".to_string(),
+                "--report".to_string(),
+            ));
+        }
+
+        if config.retry_policy.is_some() && config.retries == 0 {
+            return Err(ParseError::OptionRequires(
+                "--retry-on".to_string(),
+                "--retries".to_string(),
+            ));
+        }
+
+        if config.retry_delay.is_some() && config.retries == 0 {
+            return Err(ParseError::OptionRequires(
+                "--retry-delay".to_string(),
+                "--retries".to_string(),
+            ));
+        }
+
+        if agent_only {
+            if !config.ssh_options.identities.is_empty() {
+                return Err(ParseError::Conflict(
+                    "--agent-only".to_string(),
+                    "-i".to_string(),
+                ));
+            }
+            if env::var_os("SSH_AUTH_SOCK").is_none() {
+                return Err(ParseError::InvalidArgument(
+                    "--agent-only".to_string(),
+                    "SSH_AUTH_SOCK is not set, no agent to use".to_string(),
+                ));
+            }
+            // no key files at all, so ssh only offers what the agent holds
+            config.ssh_options.options.extend([
+                "IdentityFile=none".to_string(),
+                "IdentitiesOnly=no".to_string(),
+            ]);
+        }
+
+        if let Some(path) = &config.known_hosts {
+            let option = ssh_options::normalize(&format!("UserKnownHostsFile={}", path))
+                .map_err(|e| ParseError::InvalidArgument("--known-hosts".to_string(), e))?;
+            config.ssh_options.options.push(option);
+        }
+
+        if config.accept_new && !config.check_known_hosts {
+            return Err(ParseError::OptionRequires(
+                "--accept-new".to_string(),
+                "--check-known-hosts".to_string(),
+            ));
+        }
+        if config.check_known_hosts && config.exec_path.is_some() {
+            return Err(ParseError::Conflict(
+                "--check-known-hosts".to_string(),
+                "-x".to_string(),
+            ));
+        }
+        if let Some(policy) = config.host_key_policy {
+            // what the shorthand sets cannot also be given another way
+            let insecure = policy == HostKeyPolicy::Insecure;
+            for (given, option) in [
+                (config.accept_new, "--accept-new"),
+                (insecure && config.known_hosts.is_some(), "--known-hosts"),
+                (insecure && config.check_known_hosts, "--check-known-hosts"),
+                (insecure && config.learn_hostkeys, "--learn-hostkeys"),
+            ] {
+                if given {
+                    return Err(ParseError::Conflict(
+                        policy.flag().to_string(),
+                        option.to_string(),
+                    ));
+                }
+            }
+            for option in policy.options() {
+                let (key, _) = option.split_once('=').unwrap_or_default();
+                if ssh_options::find(&config.ssh_options.options, key).is_some() {
+                    return Err(ParseError::Conflict(
+                        policy.flag().to_string(),
+                        format!("-o {}", key),
+                    ));
+                }
+            }
+            config
+                .ssh_options
+                .options
+                .extend(policy.options().iter().map(|option| option.to_string()));
+        }
+        if config.accept_new {
+            config
+                .ssh_options
+                .options
+                .push("StrictHostKeyChecking=accept-new".to_string());
+        }
+
+        if config.buffered && !config.group {
+            return Err(ParseError::OptionRequires(
+                "--buffered".to_string(),
+                "-g".to_string(),
+            ));
+        }
+
+        if config.json_field.is_some() && !config.parse_json {
+            return Err(ParseError::OptionRequires(
+                "--field".to_string(),
+                "--parse-json".to_string(),
+            ));
+        }
+
+        // only join mode keeps the whole output of a host around to parse
+        if config.parse_json && !config.join {
+            return Err(ParseError::OptionRequires(
+                "--parse-json".to_string(),
+                "-j".to_string(),
+            ));
+        }
+        if config.aggregate.is_some() && !config.join {
+            return Err(ParseError::OptionRequires(
+                "--aggregate".to_string(),
+                "-j".to_string(),
+            ));
+        }
+        if config.parse_json && config.json_field.is_none() {
+            config.json_field = JsonPath::parse(".").ok();
+        }
+
+        if config.max_lines.is_some() && (config.group || config.join) {
+            return Err(ParseError::Conflict(
+                "--max-lines".to_string(),
+                if config.group { "-g" } else { "-j" }.to_string(),
+            ));
+        }
+
+        for (set, option) in [
+            (config.atomic_lines.is_some(), "--atomic-lines"),
+            (config.atomic_until.is_some(), "--atomic-until"),
+        ] {
+            if set && (config.group || config.join) {
+                return Err(ParseError::Conflict(
+                    option.to_string(),
+                    if config.group { "-g" } else { "-j" }.to_string(),
+                ));
+            }
+        }
+
+        if config.mark_streams && config.join {
+            return Err(ParseError::Conflict(
+                "--mark-streams".to_string(),
+                "-j".to_string(),
+            ));
+        }
+
+        // the pty joins stdout and stderr like -j does
+        if config.tty && !config.join {
+            return Err(ParseError::OptionRequires(
+                "--tty".to_string(),
+                "-j".to_string(),
+            ));
+        }
+        if config.tty {
+            config.spawner = Box::new(CloneSpawner { tty: true });
+        }
+
+        // -j reads both streams from one pipe, stderr cannot be told apart
+        if config.fail_on_stderr && config.join {
+            return Err(ParseError::Conflict(
+                "--fail-on-stderr".to_string(),
+                "-j".to_string(),
+            ));
+        }
+
+        if config.check && config.join {
+            return Err(ParseError::Conflict(
+                "--check".to_string(),
+                "-j".to_string(),
+            ));
+        }
+
+        if config.check && config.exit_codes {
+            return Err(ParseError::Conflict(
+                "--check".to_string(),
+                "-e".to_string(),
+            ));
+        }
+
+        // the status line is the only output in check mode
+        if config.check {
+            config.silent = true;
+        }
+
         assert!(!(config.join && config.group));
         if config.join {
             config.mode = ProgMode::Join;
         } else if config.group {
             config.mode = ProgMode::Group;
+        } else if config.json_lines {
+            config.mode = ProgMode::JsonLines;
         }
 
         if !["auto", "on", "off"].contains(&config.color.as_str()) {
@@ -854,107 +2647,695 @@ impl Config {
             config.color = "off".to_string();
         }
 
+        if config.color == "off" {
+            config.theme = Theme::plain();
+        }
+
+        // without the `cli` feature the caller decides how to report these
         if help_opt {
+            #[cfg(feature = "cli")]
             utils::print_usage(io::stdout(), &config.color)?;
             return Err(ParseError::HelpRequested);
         }
 
         if unknown_opt {
+            #[cfg(feature = "cli")]
             utils::print_usage(io::stderr(), &config.color)?;
             return Err(ParseError::UnknownOption);
         }
 
         config.remote_command = args[cnt..].to_vec();
 
+        if let Some(path) = &command_file {
+            if !config.remote_command.is_empty() {
+                return Err(ParseError::InvalidArgument(
+                    "--command-file".to_string(),
+                    "the file is the command, none can be given as well".to_string(),
+                ));
+            }
+            let script = std::fs::read_to_string(path).map_err(|e| {
+                ParseError::InvalidArgument(
+                    "--command-file".to_string(),
+                    format!("{}: {}", path, e),
+                )
+            })?;
+            if command_via_stdin {
+                if config.child_stdin.is_some() {
+                    return Err(ParseError::Conflict(
+                        "--command-via-stdin".to_string(),
+                        "--stdin-string".to_string(),
+                    ));
+                }
+                if script.len() > MAX_CHILD_STDIN {
+                    return Err(ParseError::InvalidArgument(
+                        "--command-file".to_string(),
+                        format!("at most {} bytes can be sent on stdin", MAX_CHILD_STDIN),
+                    ));
+                }
+                config.remote_command = script_interpreter(&script);
+                config.remote_command.push("-s".to_string());
+                config.child_stdin = Some(script.into_bytes());
+            } else {
+                // one argument the remote shell unquotes back into the script
+                config.remote_command = vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    shell_quote(&script).into_owned(),
+                ];
+            }
+        } else if command_via_stdin {
+            return Err(ParseError::OptionRequires(
+                "--command-via-stdin".to_string(),
+                "--command-file".to_string(),
+            ));
+        }
+
+        // read after any -f, like a hosts file given last
+        #[cfg(feature = "cloud")]
+        if !cloud.provider.is_empty() {
+            config.host_sources.push(Box::new(cloud));
+        } else if !cloud.filters.is_empty() {
+            return Err(ParseError::OptionRequires(
+                "--cloud-filter".to_string(),
+                "--cloud".to_string(),
+            ));
+        } else if cloud_address {
+            return Err(ParseError::OptionRequires(
+                "--cloud-address".to_string(),
+                "--cloud".to_string(),
+            ));
+        }
+
+        if config.serve {
+            if config.serve_socket.is_none() && config.serve_listen.is_none() {
+                return Err(ParseError::OptionRequires(
+                    "serve".to_string(),
+                    "--socket` or `--listen".to_string(),
+                ));
+            }
+            if !config.host_sources.is_empty() {
+                return Err(ParseError::Conflict("serve".to_string(), "-f".to_string()));
+            }
+            if !config.remote_command.is_empty() {
+                return Err(ParseError::InvalidArgument(
+                    "serve".to_string(),
+                    "takes no command, clients send it with each job".to_string(),
+                ));
+            }
+            if config.serve_token_file.is_some() && config.serve_listen.is_none() {
+                return Err(ParseError::OptionRequires(
+                    "--listen-token-file".to_string(),
+                    "--listen".to_string(),
+                ));
+            }
+            let mut serve_args = args[1..cnt].iter();
+            while let Some(arg) = serve_args.next() {
+                if [
+                    "--socket",
+                    "--listen",
+                    "--listen-token-file",
+                    "--watch-hosts",
+                ]
+                .contains(&arg.as_str())
+                {
+                    serve_args.next();
+                } else {
+                    config.serve_args.push(arg.clone());
+                }
+            }
+        } else if config.serve_socket.is_some() {
+            return Err(ParseError::OptionRequires(
+                "--socket".to_string(),
+                "serve".to_string(),
+            ));
+        } else if config.serve_listen.is_some() {
+            return Err(ParseError::OptionRequires(
+                "--listen".to_string(),
+                "serve".to_string(),
+            ));
+        } else if config.serve_token_file.is_some() {
+            return Err(ParseError::OptionRequires(
+                "--listen-token-file".to_string(),
+                "serve".to_string(),
+            ));
+        } else if config.serve_hosts.is_some() {
+            return Err(ParseError::OptionRequires(
+                "--watch-hosts".to_string(),
+                "serve".to_string(),
+            ));
+        }
+
+        if config.diff_reports {
+            if config.remote_command.len() != 2 {
+                return Err(ParseError::InvalidArgument(
+                    "diff-reports".to_string(),
+                    "takes two reports, <old.json> <new.json>".to_string(),
+                ));
+            }
+        } else if config.merge_reports {
+            if config.remote_command.len() < 2 {
+                return Err(ParseError::InvalidArgument(
+                    "merge-reports".to_string(),
+                    "takes two or more reports".to_string(),
+                ));
+            }
+            if !config.host_sources.is_empty() {
+                return Err(ParseError::Conflict(
+                    "merge-reports".to_string(),
+                    "-f".to_string(),
+                ));
+            }
+        }
+        if config.regression_threshold.is_some() && !config.diff_reports {
+            return Err(ParseError::OptionRequires(
+                "--regression-threshold".to_string(),
+                "diff-reports".to_string(),
+            ));
+        }
+
         Ok(config)
     }
 
     pub fn parse_hosts(&self) -> Result<Vec<Rc<RefCell<Host>>>, ParseError> {
-        let bad_chars = ['\n', ' ', '\0', '#'];
-        let begins_with_bad_char = |s: &str| -> bool { s.starts_with(&bad_chars[..]) };
-        let mut line_no = 0;
-
-        let process_line = |line: &str,
-                            line_no: u32,
-                            hosts: &mut Vec<Rc<RefCell<Host>>>|
-         -> Result<(), ParseError> {
-            if !begins_with_bad_char(&line) && line.ends_with("\n") {
-                if line.chars().count() >= _POSIX_HOST_NAME_MAX {
-                    return Err(ParseError::HostnameTooLong(
-                        line_no as u16,
-                        _POSIX_HOST_NAME_MAX as u16,
-                        line.to_string(),
-                    ));
+        let stdin: [Box<dyn HostSource>; 1] = [Box::new(StdinSource)];
+        let sources = if self.host_sources.is_empty() {
+            &stdin[..]
+        } else {
+            &self.host_sources[..]
+        };
+
+        // hosts already listed by an earlier group are skipped, repeats within one are kept
+        let mut hosts: Vec<Rc<RefCell<Host>>> = Vec::new();
+        let mut seen: HashSet<Arc<str>> = HashSet::new();
+        let groups = sources
+            .iter()
+            .map(|source| source.read(self.lenient, &self.warnings))
+            .collect::<Result<Vec<_>, ParseError>>()?;
+        for (index, mut group) in groups.into_iter().flatten().enumerate() {
+            group.retain(|host| !seen.contains(&host.borrow().name));
+            for host in group.iter() {
+                host.borrow_mut().source = index;
+            }
+            seen.extend(group.iter().map(|host| Arc::clone(&host.borrow().name)));
+            hosts.append(&mut group);
+        }
+
+        // every --limit has to match one of its values
+        hosts.retain(|host| {
+            let host = host.borrow();
+            self.limits.iter().all(|(key, values)| {
+                host.meta
+                    .get(key)
+                    .is_some_and(|value| values.contains(value))
+            })
+        });
+
+        // aliases resolve the way ssh resolves them, a default file that cannot be read
+        // is only worth a warning
+        if self.exec_path.is_none() {
+            let given = self.ssh_options.config_file.as_deref();
+            match ssh_config::SshConfig::load(given) {
+                Ok(Some(ssh_config)) => {
+                    for host in hosts.iter() {
+                        let mut host = host.borrow_mut();
+                        host.ssh_defaults =
+                            ssh_config.lookup(known_hosts::address(&host.full_name));
+                    }
                 }
-                let cp = Box::new(ChildProcess::new());
-                hosts.push(Rc::new(RefCell::new(Host {
-                    name: line.trim().to_string(),
-                    cp,
-                })));
-            } else if !line.ends_with("\n") && !begins_with_bad_char(&line) {
-                return Err(ParseError::HostFileFormatError(
-                    line_no as u16,
-                    line.to_string(),
-                ));
+                Ok(None) => {}
+                Err(e) if given.is_some() => {
+                    return Err(ParseError::InvalidArgument("--ssh-config".to_string(), e))
+                }
+                Err(e) => self.warnings.push("ssh-config-unreadable", e),
             }
-            Ok(())
-        };
+        }
 
-        match &self.file {
-            ScriptInput::HostsFile(file) => {
-                // transform error to custom error type
-                let file = std::fs::File::open(file).map_err(ParseError::IoError)?;
-                let mut reader = io::BufReader::new(file);
-                let mut hosts: Vec<Rc<RefCell<Host>>> = Vec::new();
-                let mut buffer: Vec<u8> = Vec::new();
+        if let Some(path) = &self.notes {
+            let notes = read_notes(path)?;
+            for host in hosts.iter() {
+                let mut host = host.borrow_mut();
+                host.note = notes
+                    .get(&*host.full_name)
+                    .or_else(|| notes.get(&*host.name))
+                    .cloned();
+            }
+        }
 
-                while reader.read_until(b'\n', &mut buffer)? > 0 {
-                    line_no += 1;
-                    let line = std::str::from_utf8(&buffer)?;
-                    process_line(line, line_no, &mut hosts)?;
-                    buffer.clear();
+        if let Some(path) = &self.priority_map {
+            let priorities = read_priority_map(path)?;
+            for host in hosts.iter() {
+                let mut host = host.borrow_mut();
+                let priority = priorities
+                    .get(&*host.full_name)
+                    .or_else(|| priorities.get(&*host.name))
+                    .copied();
+                if let Some(priority) = priority {
+                    host.priority = priority;
                 }
+            }
+        }
 
-                Ok(hosts)
+        if let (Some(path), Some(threshold)) = (&self.scoreboard, self.quarantine_below) {
+            let scoreboard = Scoreboard::load(path)?;
+            let period = self.quarantine_for.unwrap_or(DEFAULT_QUARANTINE);
+            let before = hosts.len();
+            hosts.retain(|host| {
+                let host = host.borrow();
+                match scoreboard.quarantined(&host.full_name, threshold, period) {
+                    Some((rate, runs)) => {
+                        self.warnings.push(
+                            "quarantined",
+                            format!(
+                                "{} not run, it succeeded in {:.0}% of its last {} runs",
+                                host.full_name, rate, runs
+                            ),
+                        );
+                        false
+                    }
+                    None => true,
+                }
+            });
+            if before > 0 && hosts.is_empty() {
+                return Err(ParseError::InvalidArgument(
+                    "--quarantine-below".to_string(),
+                    "every host is quarantined".to_string(),
+                ));
             }
-            ScriptInput::Stdin(stdin) => {
-                if stdin.is_terminal() {
-                    return Err(ParseError::IoError(io::Error::new(
-                        io::ErrorKind::Other,
-                        "No hosts provided from stdin!",
-                    )));
+        }
+
+        // -t shows only the first label, which may no longer tell hosts apart
+        if self.trim {
+            let mut trimmed: HashMap<&str, &str> = HashMap::new();
+            let names: Vec<Arc<str>> = hosts
+                .iter()
+                .map(|host| Arc::clone(&host.borrow().name))
+                .collect();
+            for name in names.iter().map(|name| &**name) {
+                let short = name.split('.').next().unwrap_or_default();
+                match trimmed.get(short) {
+                    Some(first) if *first != name => self.warnings.push(
+                        "trimmed-name-collision",
+                        format!(
+                            "`{}` and `{}` are both shown as `{}` with -t",
+                            first, name, short
+                        ),
+                    ),
+                    Some(_) => {}
+                    None => {
+                        trimmed.insert(short, name);
+                    }
                 }
-                // buffered reads on locked stdin
-                let mut reader = io::BufReader::new(stdin.lock());
-                let mut hosts: Vec<Rc<RefCell<Host>>> = Vec::new();
-                let mut buffer: Vec<u8> = Vec::new();
+            }
+        }
+
+        Ok(hosts)
+    }
 
-                while reader.read_until(b'\n', &mut buffer)? > 0 {
-                    line_no += 1;
-                    let line = std::str::from_utf8(&buffer)?;
-                    process_line(line, line_no, &mut hosts)?;
-                    buffer.clear();
+    fn command_for(&self, host: &Host) -> Result<Vec<String>, RuntimeError> {
+        self.command_with_run_id(host, &self.run_id)
+    }
+
+    fn write_receipt(&self, host: &Host) -> Result<(), RuntimeError> {
+        let Some(dir) = &self.receipts_dir else {
+            return Ok(());
+        };
+        let command = command_line(&self.planned_command_for(host)?);
+        report::write_receipt(dir, host, &self.redactor.redact(&command), &self.run_id)
+            .map_err(|e| RuntimeError::ReceiptError(dir.clone(), e))
+    }
+
+    // the command as planned and recorded in receipts: `{run_id}` is left as it is, so
+    // the same command is the same from one run to the next
+    fn planned_command_for(&self, host: &Host) -> Result<Vec<String>, RuntimeError> {
+        self.command_with_run_id(host, "{run_id}")
+    }
+
+    fn command_with_run_id(&self, host: &Host, run_id: &str) -> Result<Vec<String>, RuntimeError> {
+        let remote_command: Vec<String> = self
+            .remote_command
+            .iter()
+            .map(|arg| arg.replace("{run_id}", run_id))
+            .collect();
+        match &self.exec_path {
+            // the words of the template filled in one by one, so a host or a value with
+            // spaces stays one argument; the command follows as further arguments
+            Some(_) => Ok(self
+                .exec_template
+                .iter()
+                .map(|word| host.expand_meta(&word.replace("{host}", &host.full_name)))
+                .chain(remote_command.iter().map(|arg| host.expand_meta(arg)))
+                .collect()),
+            None => {
+                // filled in once, before sudo quotes the command
+                let mut remote_command: Vec<String> = remote_command
+                    .iter()
+                    .map(|arg| host.expand_meta(arg))
+                    .collect();
+                // an interactive login is left alone, there is no command to export it to
+                if self.export_run_id && !remote_command.is_empty() {
+                    remote_command.insert(0, format!("export SSHP4RU_RUN_ID={};", run_id));
+                }
+                if let Some(user) = self
+                    .become_user
+                    .as_ref()
+                    .filter(|_| !remote_command.is_empty())
+                {
+                    remote_command = vec![become_wrapper(
+                        user,
+                        self.ask_become_pass,
+                        &remote_command.join(" "),
+                    )];
                 }
-                Ok(hosts)
+                let mut ssh_command = self
+                    .ssh_options
+                    .build_expanded_ssh_command(host, remote_command)?;
+                // twice, ssh's stdin is the local pty but it may not be a terminal for sudo
+                if self.tty {
+                    ssh_command.insert(1, "-tt".to_string());
+                }
+                Ok(ssh_command)
             }
         }
     }
-
-    pub fn debugging(&self) -> bool {
-        self.debug
+
+    // hosts reached through a jump host are not probed, their port is not reachable from here
+    fn probe_hosts(&self, hosts: &[Rc<RefCell<Host>>]) {
+        let probed: Vec<&Rc<RefCell<Host>>> = hosts
+            .iter()
+            .filter(|host| self.ssh_options.jump_target(&host.borrow()).is_none())
+            .collect();
+        let targets: Vec<(Arc<str>, u16)> = probed
+            .iter()
+            .map(|host| {
+                let (address, port) = self.ssh_options.connect_target(&host.borrow());
+                (Arc::from(address), port.unwrap_or(DEFAULT_SSH_PORT))
+            })
+            .collect();
+        let results = probe::probe_hosts(&targets, self.max_jobs as usize, self.record_server_info);
+        for (host, probe) in probed.iter().zip(results) {
+            let mut host = host.borrow_mut();
+            if self.record_server_info {
+                host.address = probe.address;
+                // no banner without a connection, the reason is the connect's
+                host.banner = match &probe.latency {
+                    Ok(_) => probe.banner,
+                    Err(e) => Some(Err(e.clone())),
+                };
+            }
+            if self.probe_latency {
+                host.latency = Some(probe.latency);
+            }
+        }
+    }
+
+    // fails once up front rather than once per host when ssh is missing or too old for
+    // what the run asks of it; clients other than OpenSSH are trusted
+    fn check_ssh(&self, hosts: &[Rc<RefCell<Host>>]) -> Result<(), RuntimeError> {
+        if self.exec_path.is_some() {
+            return Ok(());
+        }
+        let required = self.ssh_options.required_version(hosts);
+        let program = &self.ssh_options.program;
+        if required.is_none() && program == "ssh" {
+            return Ok(());
+        }
+        let version = ssh_options::openssh_version(program)
+            .map_err(|e| RuntimeError::SshUnavailable(program.clone(), e))?;
+        match (version, required) {
+            (Some(version), Some((feature, since))) if version < since => Err(
+                RuntimeError::SshTooOld(program.clone(), version, feature, since),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn plan(&self, hosts: &[Rc<RefCell<Host>>]) -> Result<Plan, RuntimeError> {
+        let mut plan = Vec::with_capacity(hosts.len());
+        for host in hosts.iter() {
+            let host = host.borrow();
+            plan.push((host.name.to_string(), self.planned_command_for(&host)?));
+        }
+        Ok(plan)
+    }
+
+    // KEY=VALUE pairs for execvpe, later --child-env values win
+    fn child_environment(&self) -> Vec<CString> {
+        let mut vars: BTreeMap<OsString, OsString> = env::vars_os()
+            .filter(|(key, _)| {
+                !self.clean_env
+                    || key
+                        .to_str()
+                        .is_some_and(|key| CLEAN_ENV_KEEP.contains(&key))
+            })
+            .collect();
+        for (key, value) in self.child_env.iter() {
+            vars.insert(key.into(), value.into());
+        }
+        vars.into_iter()
+            .filter_map(|(key, value)| {
+                let mut pair = key.into_vec();
+                pair.push(b'=');
+                pair.extend(value.into_vec());
+                CString::new(pair).ok()
+            })
+            .collect()
+    }
+
+    // the options that shaped the run, with secrets passed through --redact
+    fn to_json(&self) -> serde_json::Value {
+        let redact = |s: &str| self.redactor.redact(s).into_owned();
+        serde_json::json!({
+            "mode": self.mode(),
+            "tty": self.tty,
+            "max_jobs": self.max_jobs,
+            "max_jobs_auto": self.max_jobs_auto,
+            "login": self.ssh_options.login,
+            "port": self.ssh_options.port,
+            "jump": self.ssh_options.jump,
+            "become": self.become_user,
+            "ask_become_pass": self.ask_become_pass,
+            "forward_agent": self.ssh_options.forward_agent,
+            "identities": self.ssh_options.identities,
+            "ssh_options": self.ssh_options.options.iter().map(|opt| redact(opt)).collect::<Vec<_>>(),
+            "remote_command": self.remote_command.iter().map(|arg| redact(arg)).collect::<Vec<_>>(),
+            "remote_timeout": self.ssh_options.remote_timeout,
+            "ssh_path": self.ssh_options.program,
+            "ssh_config": self.ssh_options.config_file,
+            "host_keys": self.host_key_policy.map(|policy| format!("{:?}", policy).to_lowercase()),
+            "clean_env": self.clean_env,
+            "child_env": self.child_env.iter().map(|(key, value)| format!("{}={}", key, redact(value))).collect::<Vec<_>>(),
+            "exec_path": self.exec_path,
+            "retries": self.retries,
+            "retry_delay_ms": self.retry_delay.map(|delay| delay.as_millis() as u64),
+            "reconnect": self.reconnect.map(|policy| format!("{:?}", policy).to_lowercase()),
+            "limits": self.limits,
+            "lock": self.lock,
+            "max_line_length": self.max_line_length,
+            "max_output_length": self.max_output_length,
+            "total_output_limit": self.total_output_limit,
+            "per_host_buffer_limit": self.per_host_buffer_limit.map(|limit| limit.to_string()),
+            "anonymous": self.anonymous,
+            "silent": self.silent,
+            "trim": self.trim,
+        })
+    }
+
+    // what --print-config writes: the options and command to run again with --config-from,
+    // and the values they resolved to
+    pub fn resolved_config(&self, format: ConfigFormat) -> String {
+        let redact = |s: &String| self.redactor.redact(s).into_owned();
+        let config = serde_json::json!({
+            "options": self.given_options.iter().map(redact).collect::<Vec<_>>(),
+            "command": self.remote_command.iter().map(redact).collect::<Vec<_>>(),
+            "resolved": self.to_json(),
+        });
+        match format {
+            ConfigFormat::Json => serde_json::to_string_pretty(&config).unwrap_or_default(),
+            ConfigFormat::Toml => config_file::to_toml(&config).trim_end().to_string(),
+        }
+    }
+
+    // estimates the run from the durations in the hosts' receipts, hosts without one are
+    // taken to be average; returns the jobs to run with
+    fn check_deadline(
+        &self, pending: &[Rc<RefCell<Host>>], dir: &str, deadline: Duration, max_jobs: u8,
+    ) -> u8 {
+        let durations: Vec<Option<u64>> = pending
+            .iter()
+            .map(|host| report::last_duration_ms(dir, &host.borrow()))
+            .collect();
+        let known: Vec<u64> = durations.iter().flatten().copied().collect();
+        if known.is_empty() {
+            return max_jobs;
+        }
+        let average = known.iter().sum::<u64>() / known.len() as u64;
+        let durations: Vec<u64> = durations
+            .iter()
+            .map(|duration| duration.unwrap_or(average))
+            .collect();
+
+        let deadline_ms = deadline.as_millis() as u64;
+        let estimate = deadline::makespan(&durations, max_jobs as usize);
+        if self.debug.scheduler {
+            outln!(
+                "[{}] estimated {:?} at -m {} from {} receipts",
+                PROG_NAME.colorize(&self.theme.hostname),
+                Duration::from_millis(estimate),
+                max_jobs,
+                known.len()
+            );
+        }
+        if estimate <= deadline_ms {
+            return max_jobs;
+        }
+        let raised = if self.deadline_raise_jobs {
+            deadline::jobs_needed(&durations, deadline_ms, max_jobs, auto_max_jobs())
+        } else {
+            None
+        };
+        // said before starting, the warnings list is only printed once the run is over
+        match raised {
+            Some(jobs) => eprintln!(
+                "[{}] -m {} raised to {} to finish within the deadline of {:?} (estimated {:?})",
+                PROG_NAME,
+                max_jobs,
+                jobs,
+                deadline,
+                Duration::from_millis(deadline::makespan(&durations, jobs as usize))
+            ),
+            None => eprintln!(
+                "[{}] estimated {:?} at -m {}, past the deadline of {:?}",
+                PROG_NAME,
+                Duration::from_millis(estimate),
+                max_jobs,
+                deadline
+            ),
+        }
+        raised.unwrap_or(max_jobs)
+    }
+
+    // what a run would do, for approval tooling to diff between review and execution;
+    // `index` is the order jobs start in, up to max_jobs at a time, each as soon as a job
+    // before it finished
+    pub fn plan_json(
+        &self, hosts: &[Rc<RefCell<Host>>],
+    ) -> Result<serde_json::Value, RuntimeError> {
+        let plan = self.plan(hosts)?;
+        let mut order: Vec<usize> = (0..plan.len()).collect();
+        if self.interleave_groups {
+            order = interleave_sources(order, |&i| hosts[i].borrow().source);
+        }
+        order.sort_by_key(|&i| Reverse(hosts[i].borrow().priority));
+        let entries: Vec<serde_json::Value> = order
+            .iter()
+            .map(|&i| &plan[i])
+            .enumerate()
+            .map(|(index, (host, command))| {
+                let argv: Vec<String> = command
+                    .iter()
+                    .map(|arg| self.redactor.redact(arg).into_owned())
+                    .collect();
+                serde_json::json!({
+                    "index": index,
+                    "host": host,
+                    "priority": hosts[order[index]].borrow().priority,
+                    "argv": argv,
+                })
+            })
+            .collect();
+        Ok(serde_json::json!({
+            "plan_hash": audit::plan_hash(&plan),
+            "mode": self.mode(),
+            "max_jobs": self.max_jobs,
+            "timeouts": {
+                "remote_secs": self.ssh_options.remote_timeout,
+            },
+            "lock_wait": self.lock_wait,
+            "retries": self.retries,
+            "hosts": entries,
+        }))
+    }
+
+    pub fn plan_hash(&self, hosts: &[Rc<RefCell<Host>>]) -> Result<String, RuntimeError> {
+        Ok(audit::plan_hash(&self.plan(hosts)?))
+    }
+
+    pub fn debugging(&self) -> bool {
+        self.debug.any()
+    }
+    pub fn print_warnings(&self) {
+        self.warnings.print();
+    }
+    pub fn serving(&self) -> bool {
+        self.serve
+    }
+    pub fn doctor(&self) -> bool {
+        self.doctor
+    }
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+    pub fn diffing_reports(&self) -> bool {
+        self.diff_reports
+    }
+    pub fn merging_reports(&self) -> bool {
+        self.merge_reports
+    }
+    pub fn debug(&self) -> DebugCategories {
+        self.debug
+    }
+    pub fn color(&self) -> &str {
+        self.color.as_str()
+    }
+    // a fresh one for every run, modes keep state across the hosts of a run
+    fn output_mode(&self) -> Box<dyn OutputMode> {
+        match self.mode {
+            ProgMode::Line => Box::new(LineMode),
+            ProgMode::Group => Box::new(GroupMode::default()),
+            ProgMode::Join => Box::new(JoinMode::new()),
+            ProgMode::JsonLines => Box::new(JsonLinesMode::default()),
+        }
+    }
+
+    pub fn mode(&self) -> &str {
+        self.output_mode().name()
+    }
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+    pub fn notify(&self) -> bool {
+        self.notify
+    }
+    pub fn print_config(&self) -> Option<ConfigFormat> {
+        self.print_config
+    }
+    pub fn plan_format_json(&self) -> bool {
+        self.plan_json
+    }
+    pub fn sorted(&self) -> bool {
+        self.sorted
+    }
+    pub fn status_json(&self) -> bool {
+        self.status_json
     }
-    pub fn color(&self) -> &str {
-        self.color.as_str()
+    pub fn summary_by(&self) -> Option<&str> {
+        self.summary_by.as_deref()
     }
-    pub fn mode(&self) -> &str {
-        match self.mode {
-            ProgMode::Line => "LINE",
-            ProgMode::Group => "GROUP",
-            ProgMode::Join => "JOIN",
-        }
+    pub fn notify_command(&self) -> Option<&str> {
+        self.notify_command.as_deref()
     }
-    pub fn dry_run(&self) -> bool {
-        self.dry_run
+    pub fn tty(&self) -> bool {
+        self.tty
+    }
+    pub fn check(&self) -> Option<(u32, u32)> {
+        if self.check {
+            Some((self.check_warning, self.check_critical))
+        } else {
+            None
+        }
     }
 }
 
@@ -963,82 +3344,328 @@ impl Default for Config {
         Config {
             anonymous: false,
             color: "auto".to_string(),
-            debug: false,
+            debug: DebugCategories::default(),
             exit_codes: false,
-            file: ScriptInput::Stdin(io::stdin()),
+            host_sources: Vec::new(),
             group: false,
             join: false,
+            json_lines: false,
             max_jobs: DEFAULT_MAX_SSH_JOBS,
+            max_jobs_auto: false,
             dry_run: false,
             silent: false,
             trim: false,
             exec_path: None,
+            exec_template: Vec::new(),
             max_line_length: DEFAULT_MAX_LINE_LENGTH,
             max_output_length: DEFAULT_MAX_OUTPUT_LENGTH,
+            lock: None,
+            lock_wait: false,
+            serialize_per_host: false,
+            per_jump_limit: None,
+            control_dir: None,
+            mux: false,
+            retries: 0,
+            retry_delay: None,
+            retry_policy: None,
+            compress_output: None,
+            reconnect: None,
+            on_epipe: EpipePolicy::Stop,
+            total_output_limit: None,
+            per_host_buffer_limit: None,
+            known_hosts: None,
+            learn_hostkeys: false,
+            tty: false,
+            check_known_hosts: false,
+            accept_new: false,
+            host_key_policy: None,
+            doctor: false,
+            serve: false,
+            diff_reports: false,
+            merge_reports: false,
+            regression_threshold: None,
+            serve_socket: None,
+            serve_listen: None,
+            serve_token_file: None,
+            serve_hosts: None,
+            serve_args: Vec::new(),
+            lenient: false,
+            warnings: Warnings::default(),
+            align: false,
+            mark_streams: false,
+            max_lines: None,
+            atomic_lines: None,
+            atomic_until: None,
+            fail_on_stderr: false,
+            kill_on_output: None,
+            abort_on_output: None,
+            parse_json: false,
+            json_field: None,
+            aggregate: None,
+            buffered: false,
+            color_by_exit: false,
+            sorted: false,
+            status_json: false,
+            theme: Theme::default(),
+            check: false,
+            check_warning: DEFAULT_CHECK_WARNING,
+            check_critical: DEFAULT_CHECK_CRITICAL,
+            audit_log: None,
+            events_socket: None,
+            confirm: false,
+            max_hosts: None,
+            yes: false,
+            limits: Vec::new(),
+            report: None,
+            report_env: false,
+            plan_json: false,
+            print_config: None,
+            child_stdin: None,
+            given_options: Vec::new(),
+            clean_env: false,
+            child_env: Vec::new(),
+            summary_by: None,
+            failed_hosts_out: None,
+            notes: None,
+            priority_map: None,
+            scoreboard: None,
+            probe_latency: false,
+            record_server_info: false,
+            interleave_groups: false,
+            quarantine_below: None,
+            quarantine_for: None,
+            receipts_dir: None,
+            collect: None,
+            collect_dir: None,
+            output_dir: None,
+            on_remote_failure: None,
+            map_exit: Vec::new(),
+            skip_if_succeeded_within: None,
+            deadline: None,
+            deadline_raise_jobs: false,
+            require_reviewed: None,
+            redactor: Redactor::default(),
+            spawner: Box::new(CloneSpawner::default()),
+            notify: false,
+            notify_command: None,
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: None,
             ssh_options: Default::default(),
             remote_command: Vec::new(),
+            run_id: utils::new_uuid(),
+            export_run_id: false,
+            become_user: None,
+            ask_become_pass: false,
             mode: ProgMode::Line,
         }
     }
 }
 
-fn finish_join_mode(hosts: &mut Vec<Rc<RefCell<Host>>>, colorize: bool) {
-    let num_hosts = hosts.len();
-    let seed = generate_seed();
-    let mut unique_hosts = 0;
-    let mut hosts_map: HashMap<u64, (u32, Vec<Rc<RefCell<Host>>>)> = HashMap::new();
-    let (magenta, cyan) = if colorize {
-        (Color::Magenta, Color::Cyan)
-    } else {
-        (Color::Empty, Color::Empty)
-    };
-
-    for h in hosts.iter() {
-        let mut host = h.borrow_mut();
-        if host.cp.output_index >= 0 {
+// `<host> <note>` per line, blank lines and `#` comments are ignored
+fn read_notes(path: &str) -> Result<HashMap<String, String>, ParseError> {
+    let mut notes = HashMap::new();
+    for (line_no, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
             continue;
         }
-        let hash = twox_hash::XxHash64::oneshot(seed, host.cp.output_buffer.as_bytes());
-        if hosts_map.contains_key(&hash) {
-            hosts_map.get_mut(&hash).unwrap().0 += 1;
-            hosts_map.get_mut(&hash).unwrap().1.push(Rc::clone(&h));
-            host.cp.output_index = unique_hosts;
-        } else {
-            hosts_map.insert(hash, (1, vec![Rc::clone(&h)]));
-            unique_hosts += 1;
+        match line.split_once(char::is_whitespace) {
+            Some((host, note)) => notes.insert(host.to_string(), note.trim().to_string()),
+            None => {
+                return Err(ParseError::InvalidArgument(
+                    "--notes".to_string(),
+                    format!("line {} has a host but no note", line_no + 1),
+                ))
+            }
+        };
+    }
+    Ok(notes)
+}
+
+// `<host> <priority>` per line, the same as `priority=` in a hosts file
+fn read_priority_map(path: &str) -> Result<HashMap<String, i64>, ParseError> {
+    let mut priorities = HashMap::new();
+    for (line_no, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
+        let priority = line
+            .split_once(char::is_whitespace)
+            .and_then(|(host, priority)| Some((host, priority.trim().parse::<i64>().ok()?)));
+        match priority {
+            Some((host, priority)) => priorities.insert(host.to_string(), priority),
+            None => {
+                return Err(ParseError::InvalidArgument(
+                    "--priority-map".to_string(),
+                    format!("line {} is not `<host> <priority>`", line_no + 1),
+                ))
+            }
+        };
+    }
+    Ok(priorities)
+}
+
+fn parse_host_line(line_no: usize, buffer: &[u8]) -> Result<Option<Host>, ParseError> {
+    let bad_chars = ['\n', ' ', '\0', '#'];
+    let begins_with_bad_char = |s: &str| -> bool { s.starts_with(&bad_chars[..]) };
+
+    let line = std::str::from_utf8(buffer)?;
+    if begins_with_bad_char(line) {
+        return Ok(None);
+    }
+    if !line.ends_with("\n") {
+        return Err(ParseError::HostFileFormatError(line_no, line.to_string()));
     }
 
-    println!(
-        "finished with {} unique result{}\n",
-        unique_hosts.to_string().as_str().colorize(&magenta),
-        if unique_hosts == 1 { "" } else { "s" }
-    );
+    // <hostname> [key=value ...]
+    let mut fields = line.split_whitespace();
+    let name = fields.next().unwrap_or_default();
+    if name.chars().count() >= _POSIX_HOST_NAME_MAX {
+        return Err(ParseError::HostnameTooLong(
+            line_no,
+            _POSIX_HOST_NAME_MAX as u16,
+            line.to_string(),
+        ));
+    }
+    let mut meta = BTreeMap::new();
+    for field in fields {
+        match field.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                meta.insert(key.to_string(), value.to_string());
+            }
+            _ => return Err(ParseError::HostFileFormatError(line_no, line.to_string())),
+        }
+    }
+    let priority = match meta.get("priority").map(|priority| priority.parse::<i64>()) {
+        Some(Ok(priority)) => priority,
+        Some(Err(_)) => return Err(ParseError::HostFileFormatError(line_no, line.to_string())),
+        None => 0,
+    };
+    if let Some(Err(_)) = meta.get("port").map(|port| port.parse::<u16>()) {
+        return Err(ParseError::HostFileFormatError(line_no, line.to_string()));
+    }
+    let name: Arc<str> = Arc::from(name);
+    Ok(Some(Host {
+        name: Arc::clone(&name),
+        full_name: name,
+        meta,
+        note: None,
+        source: 0,
+        priority,
+        latency: None,
+        address: None,
+        banner: None,
+        diagnostics: None,
+        follow_ups: Vec::new(),
+        ssh_defaults: ssh_config::HostDefaults::default(),
+        cp: Box::new(ChildProcess::new()),
+    }))
+}
 
-    for (_, (num_same, grouped_hosts)) in hosts_map.iter() {
-        print!(
-            "hosts ({}/{}):",
-            num_same.to_string().as_str().colorize(&magenta),
-            num_hosts.to_string().as_str().colorize(&magenta)
+// --tty: the hosts still running get the new size of our terminal, the kernel passes
+// it on to ssh as SIGWINCH and ssh to the remote pty
+fn resize_ptys(conf: &Config, hosts: &[Rc<RefCell<Host>>]) {
+    let Some(size) = window_size() else {
+        return;
+    };
+    for host in hosts {
+        let host = host.borrow();
+        // a pty that is already closed has nothing left to resize
+        if matches!(host.cp.state, CpState::Running) && host.cp.stdio_fd >= 0 {
+            let _ = set_window_size(host.cp.stdio_fd, &size);
+        }
+    }
+    if conf.debug.signals {
+        outln!(
+            "[{}] SIGWINCH, ptys resized to {}x{}",
+            PROG_NAME,
+            size.ws_col,
+            size.ws_row
         );
+    }
+}
 
-        for host in grouped_hosts.iter() {
-            let host = host.borrow();
-            print!(" {}", host.name.as_str().colorize(&cyan));
+fn kill_running(spawner: &dyn Spawner, hosts: &[Rc<RefCell<Host>>]) {
+    for host in hosts.iter() {
+        let host = host.borrow();
+        if let CpState::Running = host.cp.state {
+            let _ = spawner.signal(host.cp.pid, nix::sys::signal::Signal::SIGTERM);
+        }
+    }
+}
+
+// kill_running, then waits for them so none is left behind and records how they exited;
+// the hosts that were stopped
+fn stop_running(spawner: &dyn Spawner, hosts: &[Rc<RefCell<Host>>]) -> Vec<Rc<RefCell<Host>>> {
+    kill_running(spawner, hosts);
+    let mut stopped = Vec::new();
+    for h in hosts.iter() {
+        let mut host = h.borrow_mut();
+        if !matches!(host.cp.state, CpState::Running) {
+            continue;
+        }
+        if let Ok((status, usage)) = spawner.wait(host.cp.pid) {
+            host.cp.usage.add(&usage);
+            host.cp.exit_code = match status {
+                wait::WaitStatus::Exited(_, exit_code) => exit_code,
+                wait::WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+                _ => -1,
+            };
         }
+        host.cp.pid = -2;
+        host.cp.state = CpState::Done;
+        signals::track_finished(host.cp.status_slot.take());
+        host.cp.finished_time = monotonic_time_ms();
+        stopped.push(Rc::clone(h));
+    }
+    stopped
+}
 
-        // grouped_hosts vector has always at least one element
-        let last_host = grouped_hosts.last().unwrap().borrow();
+// the --notes entry of a failed host, after its output to help triage it
+fn print_note(host: &Host, anonymous: bool, theme: &Theme) {
+    let note = match &host.note {
+        Some(note) if host.cp.exit_code != 0 => note,
+        _ => return,
+    };
+    if anonymous {
+        outln!("{} {}", "note:".colorize(&theme.meta), note);
+    } else {
+        outln!(
+            "[{}] {} {}",
+            host.name.as_ref().colorize(&theme.hostname),
+            "note:".colorize(&theme.meta),
+            note
+        );
+    }
+}
 
-        if last_host.cp.output_buffer.is_empty() {
-            print!("{}", "- no output -".colorize(&magenta));
+// -g --buffered: the whole output of a host, headed by how it exited
+fn print_group_block(host: &Host, anonymous: bool, color_by_exit: bool, theme: &Theme) {
+    if !anonymous {
+        let code_color = if host.cp.exit_code == 0 {
+            &theme.exit_ok
         } else {
-            print!("\n{}", last_host.cp.output_buffer);
-            if !last_host.cp.output_buffer.ends_with('\n') {
-                println!();
-            }
-        }
-        println!();
+            &theme.exit_fail
+        };
+        let prefix_color = if color_by_exit {
+            code_color
+        } else {
+            &theme.hostname
+        };
+        outln!(
+            "[{}] exit={} ({} ms)",
+            host.name.as_ref().colorize(prefix_color),
+            host.cp.exit_code.to_string().as_str().colorize(code_color),
+            (host.cp.finished_time - host.cp.started_time)
+                .to_string()
+                .as_str()
+                .colorize(&theme.meta)
+        );
+    }
+    out!("{}", with_marker(&host.cp.output_buffer, host.cp.dropped));
+    if !host.cp.output_buffer.is_empty() && !host.cp.output_buffer.ends_with('\n') {
+        outln!();
     }
 }
 
@@ -1046,124 +3673,565 @@ pub fn run(
     conf: &Config, hosts: &mut Vec<Rc<RefCell<Host>>>, fdwatcher: &mut Fdwatcher,
 ) -> Result<(), RuntimeError> {
     let mut done: u16 = 0;
-    let mut remaining = 0;
+    let mut remaining: u8 = 0;
 
     let colorize = conf.color == "auto" || conf.color == "on";
-    let (cyan, magenta) = if colorize {
-        (Color::Cyan, Color::Magenta)
-    } else {
-        (Color::Empty, Color::Empty)
+    let (cyan, magenta) = (conf.theme.hostname.clone(), conf.theme.meta.clone());
+
+    let run_started = monotonic_time_ms();
+    if conf.debug.scheduler {
+        outln!(
+            "[{}] run id {}",
+            PROG_NAME.colorize(&cyan),
+            conf.run_id.as_str().colorize(&magenta)
+        );
+    }
+
+    conf.check_ssh(hosts)?;
+
+    let plan = conf.plan(hosts)?;
+    if let Some(reviewed) = &conf.require_reviewed {
+        let actual = audit::plan_hash(&plan);
+        if *reviewed != actual {
+            return Err(RuntimeError::PlanNotReviewed(reviewed.clone(), actual));
+        }
+    }
+
+    // a safety net against pointing a command at a whole inventory by accident
+    let host_count_guard = conf.max_hosts.unwrap_or(DEFAULT_HOST_COUNT_GUARD);
+    if hosts.len() > host_count_guard && !conf.yes {
+        let question = format!(
+            "[{}] {} hosts is more than {}, run anyway?",
+            PROG_NAME,
+            hosts.len(),
+            host_count_guard
+        );
+        if !prompt::confirm(&question)? {
+            return Err(RuntimeError::NotConfirmed);
+        }
+    }
+
+    if conf.confirm {
+        let question = format!(
+            "[{}] run on {} host{} (plan {})?",
+            PROG_NAME,
+            hosts.len(),
+            if hosts.len() == 1 { "" } else { "s" },
+            audit::plan_hash(&plan)
+        );
+        if !prompt::confirm(&question)? {
+            return Err(RuntimeError::NotConfirmed);
+        }
+    }
+
+    // asked once for all hosts, ahead of whatever else goes to their stdin
+    let child_stdin = match &conf.become_user {
+        Some(user) if conf.ask_become_pass => {
+            let prompt = format!("[{}] sudo password for {}: ", PROG_NAME, user);
+            let mut data = format!("{}\n", prompt::password(&prompt)?).into_bytes();
+            data.extend(conf.child_stdin.as_deref().unwrap_or_default());
+            Some(data)
+        }
+        _ => conf.child_stdin.clone(),
+    };
+
+    // held until run returns, so the whole execution is covered by the lock
+    let _lock = match &conf.lock {
+        Some(path) => {
+            let lock = acquire_lock(path, conf.lock_wait)?;
+            if conf.debug.scheduler {
+                outln!(
+                    "[{}] lock acquired: {}",
+                    PROG_NAME.colorize(&cyan),
+                    path.as_str().colorize(&magenta)
+                );
+            }
+            Some(lock)
+        }
+        None => None,
+    };
+
+    // the receipts are read under the lock, so concurrent sweeps do not both skip or both run
+    // skipped hosts stay in the list as done, the status signal handler holds on to it
+    if let (Some(dir), Some(window)) = (&conf.receipts_dir, conf.skip_if_succeeded_within) {
+        let since = monotonic_time_ms().saturating_sub(window.as_millis());
+        for host in hosts.iter() {
+            let command = command_line(&conf.planned_command_for(&host.borrow())?);
+            let command = conf.redactor.redact(&command);
+            if report::succeeded_since(dir, &host.borrow(), &command, since) {
+                host.borrow_mut().cp.skip();
+                done += 1;
+                if conf.debug.scheduler {
+                    outln!(
+                        "[{}] {} succeeded within {:?}, skipped",
+                        PROG_NAME.colorize(&cyan),
+                        host.borrow().name.as_ref().colorize(&cyan),
+                        window
+                    );
+                }
+            }
+        }
+    }
+
+    if conf.learn_hostkeys {
+        let path = match &conf.known_hosts {
+            Some(path) => Some(PathBuf::from(path)),
+            None => known_hosts::default_path(),
+        };
+        if let Some(path) = path {
+            let learned =
+                known_hosts::learn(&path, hosts, &|host| conf.ssh_options.connect_target(host))?;
+            if conf.debug.ssh {
+                outln!(
+                    "[{}] learned {} host keys into {}",
+                    PROG_NAME.colorize(&cyan),
+                    learned.to_string().as_str().colorize(&magenta),
+                    path.display().to_string().as_str().colorize(&magenta)
+                );
+            }
+        }
+    }
+
+    // up front, rather than every unknown host prompting for its key at once
+    if conf.check_known_hosts {
+        let path = match &conf.known_hosts {
+            Some(path) => Some(PathBuf::from(path)),
+            None => known_hosts::default_path(),
+        };
+        if let Some(path) = path {
+            let unknown =
+                known_hosts::check(&path, hosts, &|host| conf.ssh_options.connect_target(host))?;
+            if !unknown.is_empty() && !conf.accept_new {
+                return Err(RuntimeError::UnknownHostKeys(
+                    path.display().to_string(),
+                    unknown,
+                ));
+            }
+            for name in unknown.iter() {
+                conf.warnings.push(
+                    "unknown-host-key",
+                    format!(
+                        "{} is not in {}, its key is accepted on first connect",
+                        name,
+                        path.display()
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut events = match &conf.events_socket {
+        Some(path) => Some(
+            EventSink::connect(path)
+                .map_err(|e| RuntimeError::EventsSocketError(path.clone(), e))?,
+        ),
+        None => None,
+    };
+    if let Some(events) = events.as_mut() {
+        events.run_started(conf.mode(), hosts.len(), &conf.run_id);
+    }
+
+    if let Some(dir) = &conf.receipts_dir {
+        std::fs::create_dir_all(dir).map_err(|e| RuntimeError::ReceiptError(dir.clone(), e))?;
+    }
+
+    if let Some(dir) = &conf.output_dir {
+        std::fs::create_dir_all(dir).map_err(|e| RuntimeError::OutputDirError(dir.clone(), e))?;
+    }
+
+    // kept until the run returns, whichever way it does
+    let _control_dir = match &conf.control_dir {
+        Some(dir) => Some(
+            control::ControlDir::create(dir, &conf.ssh_options.program)
+                .map_err(|e| RuntimeError::ControlDirError(dir.display().to_string(), e))?,
+        ),
+        None => None,
     };
 
     //only for group mode
     let mut newline_group_print = true;
+    let mut output_mode = conf.output_mode();
 
-    let mut events_map: HashMap<i32, FdEvent> = if conf.mode() == "JOIN" {
+    let mut events_map: HashMap<i32, FdEvent> = if output_mode.joined() {
         HashMap::with_capacity(hosts.len())
     } else {
         HashMap::with_capacity(hosts.len() * 2)
     };
 
-    if conf.mode() == "JOIN" && io::stdout().is_terminal() {
-        print!(
-            "[{}] finished {}/{}\r",
+    output_mode.run_started(usize::from(done), hosts.len(), &conf.theme);
+
+    // widest hostname as it will be displayed, i.e. after trimming
+    let prefix_width = if conf.align {
+        hosts
+            .iter()
+            .map(|host| {
+                let host = host.borrow();
+                let name = if conf.trim {
+                    host.name.split('.').next().unwrap_or_default()
+                } else {
+                    host.name.as_ref()
+                };
+                name.chars().count()
+            })
+            .max()
+            .unwrap_or(0)
+    } else {
+        0
+    };
+
+    if conf.max_jobs_auto && conf.debug.scheduler {
+        outln!(
+            "[{}] -m auto: {} jobs",
             PROG_NAME.colorize(&cyan),
-            done.to_string().as_str().colorize(&magenta),
-            hosts.len().to_string().as_str().colorize(&magenta)
+            conf.max_jobs.to_string().as_str().colorize(&magenta)
         );
     }
 
-    let mut hosts_iter = hosts.iter().peekable();
+    // every job holds pipes open, so stay within the open files limit
+    let mut max_jobs = conf.max_jobs;
+    if let Ok(limit) = open_files_limit() {
+        let affordable = limit.saturating_sub(FDS_RESERVED) / FDS_PER_JOB;
+        if affordable < max_jobs as u64 {
+            max_jobs = affordable.max(1) as u8;
+            conf.warnings.push(
+                "lowered-concurrency",
+                format!(
+                    "-m {} lowered to {} to stay within the open files limit of {}",
+                    conf.max_jobs, max_jobs, limit
+                ),
+            );
+        }
+    }
 
-    while hosts_iter.peek().is_some() || remaining > 0 {
-        //spawn jobs
-        while hosts_iter.peek().is_some() && remaining < conf.max_jobs {
-            let host = hosts_iter.next().unwrap();
+    if (conf.probe_latency || conf.record_server_info) && conf.exec_path.is_none() {
+        conf.probe_hosts(hosts);
+        if conf.debug.scheduler {
+            outln!(
+                "[{}] probed {} hosts in {} ms",
+                PROG_NAME.colorize(&cyan),
+                hosts.len().to_string().as_str().colorize(&magenta),
+                (monotonic_time_ms() - run_started)
+                    .to_string()
+                    .as_str()
+                    .colorize(&magenta)
+            );
+        }
+    }
 
-            let command = match &conf.exec_path {
-                Some(exec_path) => exec_path,
-                None => &conf
-                    .ssh_options
-                    .build_ssh_command(&host.borrow(), &conf.remote_command)?,
+    // built once, every child gets the same environment
+    let child_env = conf.child_environment();
+
+    // without an agent ssh silently forwards nothing
+    if conf.ssh_options.forward_agent
+        && conf.debug.ssh
+        && !child_env
+            .iter()
+            .any(|pair| pair.as_bytes().starts_with(b"SSH_AUTH_SOCK="))
+    {
+        outln!(
+            "[{}] -A: no SSH_AUTH_SOCK in the environment of ssh, there is no agent to forward",
+            PROG_NAME.colorize(&cyan)
+        );
+    }
+
+    if conf.mux && conf.exec_path.is_none() {
+        let true_command = ["true".to_string()];
+        let mut commands = Vec::with_capacity(hosts.len());
+        for host in hosts.iter().filter(|host| !host.borrow().cp.skipped) {
+            let host = host.borrow();
+            commands.push((
+                conf.ssh_options.build_ssh_command(&host, &true_command)?,
+                conf.ssh_options.jump_target(&host),
+            ));
+        }
+        let ready = control::prewarm(
+            &commands,
+            max_jobs as usize,
+            conf.per_jump_limit,
+            &child_env,
+        );
+        if conf.debug.scheduler {
+            outln!(
+                "[{}] --mux: {}/{} masters ready",
+                PROG_NAME.colorize(&cyan),
+                ready.to_string().as_str().colorize(&magenta),
+                commands.len().to_string().as_str().colorize(&magenta)
+            );
+        }
+    }
+
+    // bytes read from all children so far, checked against --total-output-limit
+    let mut total_output: u64 = 0;
+    // --on-epipe is acted on once
+    let mut stdout_closed_seen = false;
+    let mut buffer_output = true;
+
+    let mut pending: Vec<Rc<RefCell<Host>>> = hosts
+        .iter()
+        .filter(|host| !host.borrow().cp.skipped)
+        .cloned()
+        .collect();
+    if conf.interleave_groups {
+        pending = interleave_sources(pending, |host| host.borrow().source);
+    }
+    // the queue stays sorted by priority, hosts of the same priority keep their order
+    pending.sort_by_key(|host| Reverse(host.borrow().priority));
+    if let (Some(deadline), Some(dir)) = (conf.deadline, &conf.receipts_dir) {
+        max_jobs = conf.check_deadline(&pending, dir, deadline, max_jobs);
+    }
+    let deadline_at = conf
+        .deadline
+        .map(|deadline| run_started + deadline.as_millis());
+    let mut pending: VecDeque<Rc<RefCell<Host>>> = pending.into();
+    // --collect, --on-remote-failure and --map-exit, queued as hosts finish
+    let mut follow_ups = follow_up::FollowUps::default();
+    let default_retry_policy = RetryPolicy::default();
+    let retry_policy = conf.retry_policy.as_ref().unwrap_or(&default_retry_policy);
+    let mut slots = Slots::default();
+
+    while !pending.is_empty() || remaining > 0 || !follow_ups.is_empty() {
+        // follow-up jobs of finished hosts go first, they take slots like hosts do
+        while usize::from(remaining) + follow_ups.running() < usize::from(max_jobs) {
+            let job =
+                follow_ups.take_next(|job| !job.remote() || slots.free(conf, &job.host.borrow()));
+            let Some(job) = job else {
+                break;
+            };
+            let (host, remote) = (Rc::clone(&job.host), job.remote());
+            if let Some(pid) = follow_ups.start(job, &child_env, &conf.warnings) {
+                if remote {
+                    slots.claim(conf, &host.borrow(), pid);
+                }
+            }
+        }
+
+        //spawn jobs
+        while usize::from(remaining) + follow_ups.running() < usize::from(max_jobs) {
+            // skip over hosts that already have a child running or whose jump host is
+            // saturated, keeping the original order
+            let now = monotonic_time_ms();
+            let next = if conf.serialize_per_host
+                || conf.per_jump_limit.is_some()
+                || conf.retry_delay.is_some()
+            {
+                pending.iter().position(|h| {
+                    let h = h.borrow();
+                    h.cp.retry_at.is_none_or(|retry_at| retry_at <= now) && slots.free(conf, &h)
+                })
+            } else {
+                Some(0)
             };
+            let host = match next.and_then(|i| pending.remove(i)) {
+                Some(host) => host,
+                None => break,
+            };
+
+            let command = conf.command_for(&host.borrow())?;
 
             //spawn child process
-            host.borrow_mut()
-                .spawn_child_process(command.as_str(), &conf.mode)?;
-            if conf.debug {
-                println!(
+            host.borrow_mut().spawn_child_process(
+                conf.spawner.as_ref(),
+                &command,
+                output_mode.joined(),
+                &child_env,
+                child_stdin.as_deref(),
+            )?;
+            slots.claim(conf, &host.borrow(), host.borrow().cp.pid);
+            if conf.debug.scheduler {
+                outln!(
                     "[{}] {} {} spawned",
                     PROG_NAME.colorize(&cyan),
                     host.borrow().cp.pid.to_string().as_str().colorize(&magenta),
-                    host.borrow().name.as_str().colorize(&cyan)
+                    host.borrow().name.as_ref().colorize(&cyan)
                 );
             }
 
             //store fd events
-            match conf.mode {
-                ProgMode::Join => {
-                    events_map.insert(
-                        host.borrow().cp.stdio_fd,
-                        FdEvent::new(Rc::clone(&host), PipeType::StdIO),
-                    );
-                }
-                _ => {
-                    events_map.insert(
-                        host.borrow().cp.stdout_fd,
-                        FdEvent::new(Rc::clone(&host), PipeType::StdOut),
-                    );
-                    events_map.insert(
-                        host.borrow().cp.stderr_fd,
-                        FdEvent::new(Rc::clone(&host), PipeType::StdErr),
-                    );
+            let pipes = if output_mode.joined() {
+                vec![(host.borrow().cp.stdio_fd, PipeType::StdIO)]
+            } else {
+                vec![
+                    (host.borrow().cp.stdout_fd, PipeType::StdOut),
+                    (host.borrow().cp.stderr_fd, PipeType::StdErr),
+                ]
+            };
+            for (fd, pipe_type) in pipes {
+                let mut event = FdEvent::new(Rc::clone(&host), pipe_type);
+                if let Some(dir) = &conf.output_dir {
+                    event.write_to_dir(Path::new(dir))?;
                 }
+                events_map.insert(fd, event);
             }
 
             //trim
             if conf.trim {
-                let name = host.borrow().name.clone();
-                host.borrow_mut().name = name
-                    .split('.')
-                    .nth(0)
-                    .ok_or_else(|| RuntimeError::TrimError)?
-                    .to_string();
+                let trimmed = {
+                    let host = host.borrow();
+                    let short = host.name.split('.').next().ok_or(RuntimeError::TrimError)?;
+                    (short.len() < host.name.len()).then(|| Arc::from(short))
+                };
+                if let Some(name) = trimmed {
+                    host.borrow_mut().name = name;
+                }
             }
 
             //register fd to epoll
-            host.borrow().register_cp_fd(&conf.mode, &fdwatcher)?;
+            host.borrow()
+                .register_cp_fd(output_mode.joined(), &fdwatcher)?;
+
+            if let Some(events) = events.as_mut() {
+                events.host_started(&host.borrow());
+            }
 
             remaining += 1;
         }
 
+        // woken up in time to stop at the deadline, or to start the next retry
+        let retry_at = pending
+            .iter()
+            .filter_map(|host| host.borrow().cp.retry_at)
+            .min();
+        let poll_at = (follow_ups.running() > 0).then(|| monotonic_time_ms() + FOLLOW_UP_POLL_MS);
+        let timeout = match deadline_at.into_iter().chain(retry_at).chain(poll_at).min() {
+            Some(wake_at) => wake_at
+                .saturating_sub(monotonic_time_ms())
+                .min(i32::MAX as u128) as i32,
+            None => FDW_WAIT_TIMEOUT,
+        };
         let mut completed_events: [RawFd; FDW_MAX_EVENTS] = [0; FDW_MAX_EVENTS];
         let num_completed_events =
-            fdwatcher.wait(&mut completed_events, FDW_MAX_EVENTS, FDW_WAIT_TIMEOUT)?;
+            fdwatcher.wait(&mut completed_events, FDW_MAX_EVENTS, timeout)?;
+        if deadline_at.is_some_and(|deadline_at| monotonic_time_ms() >= deadline_at) {
+            stop_run(
+                conf,
+                hosts,
+                &mut follow_ups,
+                &plan,
+                run_started,
+                &mut events,
+            )?;
+            return Err(RuntimeError::DeadlineExceeded(
+                conf.deadline.unwrap_or_default(),
+            ));
+        }
+        for job in follow_ups.take_finished() {
+            slots.release(job.pid());
+            finish_follow_up(conf, job);
+        }
+        if conf.tty && signals::take_resized() {
+            resize_ptys(conf, hosts);
+        }
+        if conf.debug.io {
+            outln!(
+                "[{}] {} fds ready",
+                PROG_NAME.colorize(&cyan),
+                num_completed_events.to_string().as_str().colorize(&magenta)
+            );
+        }
 
         for event_fd in completed_events[..num_completed_events].iter() {
             if let Some(event) = events_map.get_mut(event_fd) {
-                //last_host is used to stimulate the newline print behavior in group mode
-                //without utilizing a static mut global variable
-                let mut last_host: Option<String> = None;
-                let config_req_params = || -> (bool, ProgMode, u16, u16, bool, bool) {
-                    (
-                        conf.silent,
-                        conf.mode.clone(),
-                        conf.max_line_length,
-                        conf.max_output_length,
-                        conf.anonymous,
+                let config_req_params = || -> OutputConfig {
+                    OutputConfig {
+                        silent: conf.silent,
+                        max_line_length: conf.max_line_length,
+                        max_output_length: conf.max_output_length,
+                        anonymous: conf.anonymous,
                         colorize,
-                    )
+                        prefix_width,
+                        mark_streams: conf.mark_streams,
+                        max_lines: conf.max_lines,
+                        atomic_lines: conf.atomic_lines,
+                        atomic_until: conf.atomic_until.clone(),
+                        kill_on_output: conf.kill_on_output.clone(),
+                        abort_on_output: conf.abort_on_output.clone(),
+                        buffered: conf.buffered,
+                        buffer_limit: conf.per_host_buffer_limit,
+                        theme: conf.theme.clone(),
+                        buffer_output,
+                    }
                 };
+                let output_before = event.get_host().borrow().cp.output_bytes;
 
                 // read from the active fd and output if mode is not join,
                 // untill the child process is done writing or it would block
                 let data_read = event.read_active_fd(
                     &fdwatcher,
-                    &mut last_host,
+                    conf.spawner.as_ref(),
+                    output_mode.as_mut(),
                     &mut newline_group_print,
+                    &conf.redactor,
                     config_req_params,
                 )?;
 
+                let bytes_read = event.get_host().borrow().cp.output_bytes - output_before;
+                if conf.debug.io {
+                    outln!(
+                        "[{}] fd {} ({}) read {} bytes",
+                        PROG_NAME.colorize(&cyan),
+                        event_fd.to_string().as_str().colorize(&magenta),
+                        event.get_host().borrow().name.as_ref().colorize(&cyan),
+                        bytes_read.to_string().as_str().colorize(&magenta)
+                    );
+                }
+                let abort_output = event.get_host().borrow().cp.abort_output.clone();
+                if let Some(line) = abort_output {
+                    stop_run(
+                        conf,
+                        hosts,
+                        &mut follow_ups,
+                        &plan,
+                        run_started,
+                        &mut events,
+                    )?;
+                    let host = event.get_host().borrow().full_name.to_string();
+                    return Err(RuntimeError::AbortedOnOutput(host, line));
+                }
+                if stdout_closed() && !stdout_closed_seen {
+                    stdout_closed_seen = true;
+                    if conf.on_epipe == EpipePolicy::Stop {
+                        let stopped = stop_run(
+                            conf,
+                            hosts,
+                            &mut follow_ups,
+                            &plan,
+                            run_started,
+                            &mut events,
+                        )?;
+                        return Err(RuntimeError::StdoutClosed(done as usize, stopped));
+                    }
+                    conf.warnings.push(
+                        "stdout-closed",
+                        "stdout was closed, the output of the remaining hosts is dropped"
+                            .to_string(),
+                    );
+                }
+
+                total_output += bytes_read;
+                if let Some((soft, hard)) = conf.total_output_limit {
+                    if total_output >= hard {
+                        stop_run(
+                            conf,
+                            hosts,
+                            &mut follow_ups,
+                            &plan,
+                            run_started,
+                            &mut events,
+                        )?;
+                        return Err(RuntimeError::OutputLimitExceeded(hard));
+                    }
+                    if total_output >= soft && buffer_output {
+                        buffer_output = false;
+                        conf.warnings.push(
+                            "output-limit",
+                            format!(
+                                "total output passed {} bytes, output was no longer buffered",
+                                soft
+                            ),
+                        );
+                    }
+                }
+
                 //check if child is done writing and close the pipe.
                 let pipe_done: bool = (event.get_host().borrow().cp.stderr_fd == -2
                     && event.get_host().borrow().cp.stdout_fd == -2)
@@ -1171,35 +4239,316 @@ pub fn run(
 
                 if data_read && pipe_done {
                     // need to delegate errors
-                    let config_wait_params =
-                        || -> (bool, bool, bool) { (conf.debug, conf.exit_codes, colorize) };
+                    // buffered blocks carry the exit code in their header instead
+                    // --color-by-exit ends every host with the exit line
+                    let config_wait_params = || -> (bool, bool, bool, bool, Theme) {
+                        (
+                            conf.debug.scheduler,
+                            (conf.exit_codes || conf.color_by_exit) && !conf.buffered,
+                            conf.color_by_exit,
+                            conf.fail_on_stderr,
+                            conf.theme.clone(),
+                        )
+                    };
 
-                    event
-                        .get_host()
-                        .borrow_mut()
-                        .wait_child_process(&mut newline_group_print, config_wait_params)?;
+                    let pid = event.get_host().borrow().cp.pid;
+                    event.get_host().borrow_mut().wait_child_process(
+                        conf.spawner.as_ref(),
+                        &mut newline_group_print,
+                        config_wait_params,
+                    )?;
+                    slots.release(pid);
+                    if let Some(events) = events.as_mut() {
+                        events.host_finished(&event.get_host().borrow(), pid);
+                    }
                     remaining -= 1;
-                    done += 1;
 
-                    if conf.mode() == "JOIN" && io::stdout().is_terminal() {
-                        print!(
-                            "[{}] finished {}/{}\r",
-                            PROG_NAME.colorize(&cyan),
-                            done.to_string().as_str().colorize(&magenta),
-                            hosts.len().to_string().as_str().colorize(&magenta)
+                    let host = event.get_host();
+                    conf.write_receipt(&host.borrow())?;
+
+                    // failed attempts that are still within budget go back to the queue
+                    let (exit_code, attempts) = {
+                        let host = host.borrow();
+                        (host.cp.exit_code, host.cp.attempts)
+                    };
+                    let connection_lost = {
+                        let host = host.borrow();
+                        retry::connection_lost(
+                            exit_code,
+                            host.cp.output_bytes > host.cp.stderr_bytes,
+                            &host.cp.stderr_tail,
+                        )
+                    };
+                    if conf.reconnect.is_some() && connection_lost {
+                        let reconnects = {
+                            let mut host = host.borrow_mut();
+                            host.cp.reconnects += 1;
+                            host.cp.reconnects
+                        };
+                        let rerun = conf.reconnect == Some(ReconnectPolicy::Rerun)
+                            && reconnects <= MAX_RECONNECTS;
+                        conf.warnings.push(
+                            "connection-lost",
+                            format!(
+                                "{} lost the connection{}",
+                                host.borrow().full_name,
+                                if rerun {
+                                    format!(", running again ({}/{})", reconnects, MAX_RECONNECTS)
+                                } else {
+                                    String::new()
+                                }
+                            ),
                         );
+                        if rerun {
+                            host.borrow_mut().cp.reset_for_retry();
+                            push_by_priority(&mut pending, host);
+                            continue;
+                        }
+                    }
+                    if attempts <= conf.retries && retry_policy.should_retry(exit_code) {
+                        let delay = conf
+                            .retry_delay
+                            .map(|delay| retry::backoff(delay, attempts))
+                            .unwrap_or_default();
+                        if conf.debug.scheduler {
+                            outln!(
+                                "[{}] {} exited {}, retrying ({}/{}) in {} ms",
+                                PROG_NAME.colorize(&cyan),
+                                host.borrow().name.as_ref().colorize(&cyan),
+                                exit_code.to_string().as_str().colorize(&magenta),
+                                attempts,
+                                conf.retries,
+                                delay.as_millis()
+                            );
+                        }
+                        host.borrow_mut().cp.reset_for_retry();
+                        if conf.retry_delay.is_some() {
+                            host.borrow_mut().cp.retry_at =
+                                Some(monotonic_time_ms() + delay.as_millis());
+                        }
+                        push_by_priority(&mut pending, host);
+                        continue;
+                    }
+                    done += 1;
+
+                    if let (Some(glob), Some(dir), 0) =
+                        (&conf.collect, &conf.collect_dir, exit_code)
+                    {
+                        let command = conf
+                            .ssh_options
+                            .build_ssh_command(&host.borrow(), &collect::remote_command(glob))?;
+                        let dir = collect::host_dir(dir, &host.borrow().full_name);
+                        follow_ups.push(follow_up::Queued {
+                            host: Rc::clone(&host),
+                            job: follow_up::Job::Collect(dir),
+                            command,
+                        });
+                    }
+
+                    if let Some(command) =
+                        conf.on_remote_failure.as_ref().filter(|_| exit_code != 0)
+                    {
+                        let ssh_command = conf
+                            .ssh_options
+                            .build_ssh_command(&host.borrow(), std::slice::from_ref(command))?;
+                        follow_ups.push(follow_up::Queued {
+                            host: Rc::clone(&host),
+                            job: follow_up::Job::Diagnose,
+                            command: ssh_command,
+                        });
+                    }
 
-                        if usize::from(done) == hosts.len() {
-                            print!("\n\n");
+                    for rule in conf.map_exit.iter().filter(|rule| rule.code == exit_code) {
+                        let command = if rule.local {
+                            rule.local_command(&host.borrow())
+                        } else {
+                            conf.ssh_options.build_ssh_command(
+                                &host.borrow(),
+                                std::slice::from_ref(&rule.command),
+                            )?
+                        };
+                        if conf.debug.scheduler {
+                            outln!(
+                                "[{}] exit {} of {}, running {}",
+                                PROG_NAME.colorize(&cyan),
+                                exit_code,
+                                host.borrow().full_name.as_ref().colorize(&cyan),
+                                rule.command
+                            );
                         }
+                        follow_ups.push(follow_up::Queued {
+                            host: Rc::clone(&host),
+                            job: follow_up::Job::MapExit(rule.clone()),
+                            command,
+                        });
+                    }
+
+                    if conf.buffered {
+                        print_group_block(
+                            &host.borrow(),
+                            conf.anonymous,
+                            conf.color_by_exit,
+                            &conf.theme,
+                        );
+                    }
+                    if !conf.silent {
+                        print_note(&host.borrow(), conf.anonymous, &conf.theme);
+                    }
+                    if let Some(field) = &conf.json_field {
+                        host.borrow_mut().parse_json_output(field);
                     }
+                    output_mode.host_finished(&host, usize::from(done), hosts.len(), &conf.theme);
                 }
             }
         }
     } // main event loop
 
-    if conf.mode() == "JOIN" {
-        finish_join_mode(hosts, colorize);
+    output_mode.run_finished(hosts, conf);
+
+    for host in hosts.iter() {
+        let host = host.borrow();
+        if let Some(line) = &host.cp.matched_output {
+            conf.warnings.push(
+                "killed-on-output",
+                format!("{} was terminated after printing: {}", host.full_name, line),
+            );
+        }
+        if host.cp.failed_on_stderr {
+            conf.warnings.push(
+                "failed-on-stderr",
+                format!(
+                    "{} exited 0 but wrote {} bytes to stderr",
+                    host.full_name, host.cp.stderr_bytes
+                ),
+            );
+        }
+        if host.cp.truncated {
+            conf.warnings.push(
+                "truncated-output",
+                format!("output of {} was truncated", host.full_name),
+            );
+        }
+        for follow_up in host.follow_ups.iter() {
+            let failure = match &follow_up.outcome {
+                Ok((_, 0)) => continue,
+                Ok((_, exit_code)) => format!("exited with {}", exit_code),
+                Err(e) => e.clone(),
+            };
+            conf.warnings.push(
+                "map-exit-failed",
+                format!(
+                    "{} for exit {} of {} failed: {}",
+                    follow_up.command, follow_up.code, host.full_name, failure
+                ),
+            );
+        }
+    }
+
+    record_run(conf, hosts, &plan, run_started, &mut events)
+}
+
+// what a follow-up job did goes to its host for the report, a failed collection is a warning
+fn finish_follow_up(conf: &Config, job: follow_up::Running) {
+    match job {
+        follow_up::Running::Collect(collector) => {
+            let host = collector.host.clone();
+            match collector.finish() {
+                Ok(()) if conf.debug.scheduler => outln!(
+                    "[{}] collected from {}",
+                    PROG_NAME.colorize(&conf.theme.hostname),
+                    host.as_str().colorize(&conf.theme.hostname)
+                ),
+                Ok(()) => {}
+                Err(e) => conf.warnings.push(
+                    "collect-failed",
+                    format!("collecting from {} failed: {}", host, e),
+                ),
+            }
+        }
+        follow_up::Running::Diagnose(diagnostic) => diagnostic.finish(&conf.redactor),
+        follow_up::Running::MapExit(follow_up) => follow_up.finish(&conf.redactor),
+    }
+}
+
+// a run stopped before every host finished still waits for the running children and
+// follow-up jobs, and records them and what finished before; how many hosts were stopped
+fn stop_run(
+    conf: &Config, hosts: &[Rc<RefCell<Host>>], follow_ups: &mut follow_up::FollowUps, plan: &Plan,
+    run_started: u128, events: &mut Option<EventSink>,
+) -> Result<usize, RuntimeError> {
+    let stopped = stop_running(conf.spawner.as_ref(), hosts);
+    for host in stopped.iter() {
+        conf.write_receipt(&host.borrow())?;
+    }
+    for job in follow_ups.stop(&conf.warnings) {
+        finish_follow_up(conf, job);
+    }
+    record_run(conf, hosts, plan, run_started, events)?;
+    Ok(stopped.len())
+}
+
+// the report, scoreboard, failed hosts, audit log and telemetry of a run
+fn record_run(
+    conf: &Config, hosts: &[Rc<RefCell<Host>>], plan: &Plan, run_started: u128,
+    events: &mut Option<EventSink>,
+) -> Result<(), RuntimeError> {
+    let (cyan, magenta) = (&conf.theme.hostname, &conf.theme.meta);
+    if let Some(events) = events.as_mut() {
+        events.run_finished(hosts);
+    }
+
+    if let Some(path) = &conf.report {
+        let mut report =
+            report::build_report(hosts, conf.mode(), run_started, conf.summary_by.as_deref());
+        report["warnings"] = conf.warnings.to_json();
+        report["run_id"] = serde_json::json!(conf.run_id);
+        if conf.report_env {
+            report["environment"] = report::environment(conf.to_json(), run_started);
+        }
+        let written = report::write_report(path, &report, conf.compress_output.as_ref())
+            .map_err(|e| RuntimeError::ReportError(path.clone(), e))?;
+        if conf.debug.scheduler {
+            outln!(
+                "[{}] report written to {}",
+                PROG_NAME.colorize(cyan),
+                written.display().to_string().as_str().colorize(magenta)
+            );
+        }
+    }
+
+    if let Some(path) = &conf.scoreboard {
+        let mut scoreboard =
+            Scoreboard::load(path).map_err(|e| RuntimeError::ScoreboardError(path.clone(), e))?;
+        scoreboard.record(hosts);
+        scoreboard
+            .save(path)
+            .map_err(|e| RuntimeError::ScoreboardError(path.clone(), e))?;
+    }
+
+    if let Some(path) = &conf.failed_hosts_out {
+        report::write_failed_hosts(path, hosts)
+            .map_err(|e| RuntimeError::FailedHostsError(path.clone(), e))?;
+    }
+
+    if let Some(path) = &conf.audit_log {
+        audit::append_record(path, plan, hosts, &conf.redactor, &conf.run_id)
+            .map_err(|e| RuntimeError::AuditLogError(path.clone(), e))?;
+    }
+
+    // telemetry is best effort and never changes the outcome of the run
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &conf.otlp_endpoint {
+        let finished = monotonic_time_ms();
+        if let Err(e) = otlp::export(
+            endpoint,
+            hosts,
+            conf.mode(),
+            &conf.run_id,
+            run_started,
+            finished,
+        ) {
+            eprintln!("[{}] otlp export to {} failed: {}", PROG_NAME, endpoint, e);
+        }
     }
 
     Ok(())
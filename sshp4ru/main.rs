@@ -1,10 +1,24 @@
 use nix::unistd::dup2;
 use sshp4ru::signals::SignalHandler;
 use sshp4ru::RuntimeError;
-use sshp4ru::{debug_hosts, Config, ParseError, PROG_NAME, PROG_VERSION};
+use sshp4ru::{
+    debug_hosts, diff_reports, doctor, merge_reports, print_check_status, print_summary,
+    print_summary_by, print_version_json, run_notify_command, serve, Config, ParseError, PROG_NAME,
+    PROG_VERSION,
+};
 use std::os::unix::io::AsRawFd;
 use std::process::ExitCode;
 
+// the library's outln!, a closed stdout drops the output instead of panicking
+macro_rules! outln {
+    () => {
+        sshp4ru::print_stdout(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        sshp4ru::print_stdout(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
 fn main() -> ExitCode {
     let mut exit_code: ExitCode = ExitCode::SUCCESS;
     let start_time = std::time::Instant::now();
@@ -15,20 +29,52 @@ fn main() -> ExitCode {
             std::process::exit(0);
         }
         ParseError::VersionRequested => {
-            println!("{} {}", PROG_NAME, PROG_VERSION);
+            outln!("{} {}", PROG_NAME, PROG_VERSION);
+            std::process::exit(0);
+        }
+        ParseError::VersionJsonRequested => {
+            if let Err(e) = print_version_json(std::io::stdout()) {
+                eprintln!("{}", e);
+                std::process::exit(3);
+            }
             std::process::exit(0);
         }
         ParseError::UnknownOption => {
             std::process::exit(2);
         }
         _ => {
-            println!("{}", err);
+            outln!("{}", err);
             std::process::exit(2);
         }
     });
 
+    if let Some(format) = config.print_config() {
+        outln!("{}", config.resolved_config(format));
+        return ExitCode::SUCCESS;
+    }
+
+    if config.doctor() {
+        return ExitCode::from(doctor(&config));
+    }
+
+    if config.diffing_reports() {
+        return ExitCode::from(diff_reports(&config));
+    }
+
+    if config.merging_reports() {
+        return ExitCode::from(merge_reports(&config));
+    }
+
+    if config.serving() {
+        if let Err(err) = serve(&config) {
+            eprintln!("{}", err);
+            return ExitCode::from(3);
+        }
+        return ExitCode::SUCCESS;
+    }
+
     let mut hosts = config.parse_hosts().unwrap_or_else(|err| {
-        println!("{}", err);
+        outln!("{}", err);
         std::process::exit(2);
     });
 
@@ -54,24 +100,66 @@ fn main() -> ExitCode {
 
     // signals
     let colorize = config.color() == "auto" || config.color() == "on";
-    let mut signal_handler = SignalHandler::new(&hosts, hosts.len(), colorize);
+    let mut signal_handler =
+        SignalHandler::new(hosts.len(), colorize, config.status_json(), config.sorted());
     signal_handler.register_signals();
+    if config.tty() {
+        signal_handler.register_sigwinch();
+    }
+    if config.debug().signals {
+        outln!(
+            "[{}] handlers registered for SIGINT, SIGTERM, SIGUSR1{}",
+            PROG_NAME,
+            if config.tty() { ", SIGWINCH" } else { "" }
+        );
+    }
 
     //debugging
-    if config.debugging() {
-        debug_hosts(&hosts, colorize);
-        println!("{:?}", config);
+    if config.debug().ssh {
+        debug_hosts(&hosts, config.theme(), config.sorted());
+        outln!("{:?}", config);
     }
 
-    if config.dry_run() {
-        println!("(dry run)");
+    if config.dry_run() && config.plan_format_json() {
+        match config.plan_json(&hosts) {
+            Ok(plan) => outln!("{}", plan),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        }
+    } else if config.dry_run() {
+        outln!("(dry run)");
+        match config.plan_hash(&hosts) {
+            Ok(hash) => outln!("plan hash: {}", hash),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        }
     } else {
         sshp4ru::run(&config, &mut hosts, &mut fdwatcher).unwrap_or_else(|err: RuntimeError| {
             match err {
-                RuntimeError::SshCommandLengthExceeded(_) | RuntimeError::TrimError => {
+                RuntimeError::SshCommandLengthExceeded(_)
+                | RuntimeError::NulInArgument(_)
+                | RuntimeError::TrimError
+                | RuntimeError::PlanNotReviewed(_, _)
+                | RuntimeError::TtyUnavailable(_)
+                | RuntimeError::UnknownHostKeys(_, _)
+                | RuntimeError::NotConfirmed
+                | RuntimeError::SshUnavailable(_, _)
+                | RuntimeError::SshTooOld(..) => {
                     eprintln!("{}", err);
                     std::process::exit(2);
                 }
+                // a host said something the operator asked to stop on, the run ran out of time or
+                // nobody reads its output any more
+                RuntimeError::AbortedOnOutput(_, _)
+                | RuntimeError::DeadlineExceeded(_)
+                | RuntimeError::StdoutClosed(_, _) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
                 _ => {
                     eprintln!("{}", err);
                     std::process::exit(3);
@@ -96,16 +184,39 @@ fn main() -> ExitCode {
                 exit_code = ExitCode::from(1);
             }
         }
+
+        if let Some((warning, critical)) = config.check() {
+            let status = print_check_status(&hosts, warning, critical, start_time.elapsed());
+            exit_code = ExitCode::from(status);
+        }
+
+        if let Some(key) = config.summary_by() {
+            print_summary_by(&hosts, key, config.theme());
+        }
+
+        if config.notify() {
+            print_summary(&hosts, start_time.elapsed(), config.theme(), colorize);
+        }
+
+        if let Some(command) = config.notify_command() {
+            if let Err(error) =
+                run_notify_command(command, &hosts, start_time.elapsed(), config.run_id())
+            {
+                eprintln!("notify command error: {}", error);
+            }
+        }
     }
 
+    config.print_warnings();
+
     let delta = start_time.elapsed();
-    if config.debugging() {
+    if config.debug().scheduler {
         let (cyan, reset, magenta) = if colorize {
             ("\x1b[36m", "\x1b[0m", "\x1b[35m")
         } else {
             ("", "", "")
         };
-        println!(
+        outln!(
             "[{}{}{}] finished ({}{:0.5}{} ms)",
             cyan,
             PROG_NAME,
@@ -0,0 +1,130 @@
+use crate::report::{latency_totals, output_totals, summarize_by, throughput, total_usage};
+use crate::theme::Theme;
+use crate::utils::{Color, Colorize};
+use crate::Host;
+use crate::PROG_NAME;
+use std::cell::RefCell;
+use std::io::{self, IsTerminal};
+use std::process::{Command, ExitStatus};
+use std::rc::Rc;
+use std::time::Duration;
+
+const BELL: &str = "\x07";
+
+fn count_results(hosts: &[Rc<RefCell<Host>>]) -> (usize, usize) {
+    let failed = hosts
+        .iter()
+        .filter(|host| host.borrow().cp.exit_code != 0)
+        .count();
+    (hosts.len() - failed, failed)
+}
+
+pub fn print_summary(
+    hosts: &[Rc<RefCell<Host>>], elapsed: Duration, theme: &Theme, colorize: bool,
+) {
+    let (succeeded, failed) = count_results(hosts);
+    let bold = if colorize { Color::Bold } else { Color::Empty };
+    let (cyan, green, red) = (&theme.hostname, &theme.exit_ok, &theme.exit_fail);
+
+    // the bell is only useful (and harmless) when a human is watching the terminal
+    if io::stdout().is_terminal() {
+        out!("{}", BELL);
+    }
+    outln!(
+        "{}",
+        format!(
+            "[{}] finished: {} succeeded, {} failed ({} ms)",
+            PROG_NAME.colorize(cyan),
+            succeeded.to_string().as_str().colorize(green),
+            failed.to_string().as_str().colorize(red),
+            elapsed.as_millis()
+        )
+        .as_str()
+        .colorize(&bold)
+    );
+
+    let usage = total_usage(hosts);
+    outln!(
+        "[{}] ssh children used {} ms cpu ({} user, {} system), peak rss {} KiB",
+        PROG_NAME.colorize(cyan),
+        usage.cpu_ms(),
+        usage.user_ms,
+        usage.system_ms,
+        usage.max_rss_kb
+    );
+
+    let (output_bytes, slowest) = output_totals(hosts);
+    out!(
+        "[{}] read {} bytes ({} bytes/s)",
+        PROG_NAME.colorize(cyan),
+        output_bytes,
+        throughput(output_bytes, elapsed.as_millis())
+    );
+    match slowest {
+        Some((name, rate)) => outln!(
+            ", slowest {} ({} bytes/s)",
+            name.as_str().colorize(cyan),
+            rate
+        ),
+        None => outln!(),
+    }
+
+    // only hosts probed with --probe-latency count here
+    match latency_totals(hosts) {
+        (Some((avg, slowest, max)), failed) => outln!(
+            "[{}] connect latency avg {} ms, slowest {} ({} ms), {} unreachable",
+            PROG_NAME.colorize(cyan),
+            avg,
+            slowest.as_str().colorize(cyan),
+            max,
+            failed
+        ),
+        (None, failed) if failed > 0 => outln!(
+            "[{}] connect latency unknown, {} unreachable",
+            PROG_NAME.colorize(cyan),
+            failed
+        ),
+        (None, _) => {}
+    }
+}
+
+pub fn print_summary_by(hosts: &[Rc<RefCell<Host>>], key: &str, theme: &Theme) {
+    let (cyan, magenta, green, red) = (
+        &theme.hostname,
+        &theme.meta,
+        &theme.exit_ok,
+        &theme.exit_fail,
+    );
+
+    outln!(
+        "[{}] summary by {}:",
+        PROG_NAME.colorize(cyan),
+        key.colorize(magenta)
+    );
+    for (value, group) in summarize_by(hosts, key).iter() {
+        outln!(
+            "  {}: {} succeeded, {} failed (avg {} ms, max {} ms)",
+            value.as_str().colorize(magenta),
+            group.succeeded.to_string().as_str().colorize(green),
+            group.failed.to_string().as_str().colorize(red),
+            group.avg_ms(),
+            group.max_ms
+        );
+    }
+}
+
+pub fn run_notify_command(
+    command: &str, hosts: &[Rc<RefCell<Host>>], elapsed: Duration, run_id: &str,
+) -> io::Result<ExitStatus> {
+    let (succeeded, failed) = count_results(hosts);
+
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("SSHP4RU_TOTAL", hosts.len().to_string())
+        .env("SSHP4RU_SUCCEEDED", succeeded.to_string())
+        .env("SSHP4RU_FAILED", failed.to_string())
+        .env("SSHP4RU_DURATION_MS", elapsed.as_millis().to_string())
+        .env("SSHP4RU_RUN_ID", run_id)
+        .status()
+}
@@ -0,0 +1,152 @@
+use crate::http;
+use crate::{CpState, Host};
+use crate::{PROG_NAME, PROG_VERSION};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+const OTLP_DEFAULT_PATH: &str = "/v1/traces";
+const OTLP_TIMEOUT: Duration = Duration::from_secs(5);
+// SpanKind and StatusCode values from the OTLP trace protobuf definitions
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_CLIENT: u8 = 3;
+const STATUS_CODE_OK: u8 = 1;
+const STATUS_CODE_ERROR: u8 = 2;
+
+fn random_id(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        v => json!({ "stringValue": v.as_str().unwrap_or_default() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn ms_to_unix_nanos(ms: u128) -> String {
+    (ms * 1_000_000).to_string()
+}
+
+fn build_traces(
+    hosts: &[Rc<RefCell<Host>>], mode: &str, run_id: &str, started: u128, finished: u128,
+) -> Value {
+    let trace_id = random_id(16);
+    let root_id = random_id(8);
+
+    let mut spans = vec![json!({
+        "traceId": trace_id,
+        "spanId": root_id,
+        "name": format!("{} run", PROG_NAME),
+        "kind": SPAN_KIND_INTERNAL,
+        "startTimeUnixNano": ms_to_unix_nanos(started),
+        "endTimeUnixNano": ms_to_unix_nanos(finished),
+        "attributes": [
+            attribute("sshp4ru.mode", json!(mode)),
+            attribute("sshp4ru.run_id", json!(run_id)),
+            attribute("sshp4ru.hosts", json!(hosts.len())),
+        ],
+    })];
+
+    for host in hosts.iter() {
+        let host = host.borrow();
+        // hosts that never ran (e.g. after a fatal error) have no timing to report
+        if let CpState::Ready = host.cp.state {
+            continue;
+        }
+        let status = if host.cp.exit_code == 0 {
+            STATUS_CODE_OK
+        } else {
+            STATUS_CODE_ERROR
+        };
+        let host_id = random_id(8);
+        spans.push(json!({
+            "traceId": trace_id,
+            "spanId": host_id,
+            "parentSpanId": root_id,
+            "name": &*host.name,
+            "kind": SPAN_KIND_CLIENT,
+            "startTimeUnixNano": ms_to_unix_nanos(host.cp.started_time),
+            "endTimeUnixNano": ms_to_unix_nanos(host.cp.finished_time),
+            "attributes": [
+                attribute("net.peer.name", json!(&*host.name)),
+                attribute("process.exit_code", json!(host.cp.exit_code)),
+            ],
+            "status": { "code": status },
+        }));
+
+        // ssh prints nothing of the host before the session is up, so its first output
+        // ends the connect phase; a host that printed nothing was connecting all along
+        let connected = host.cp.first_output_time.unwrap_or(host.cp.finished_time);
+        let mut phases = vec![("connect", host.cp.started_time, connected)];
+        if host.cp.first_output_time.is_some() {
+            phases.push(("execute", connected, host.cp.finished_time));
+        }
+        for (phase, start, end) in phases {
+            spans.push(json!({
+                "traceId": trace_id,
+                "spanId": random_id(8),
+                "parentSpanId": host_id,
+                "name": phase,
+                "kind": SPAN_KIND_INTERNAL,
+                "startTimeUnixNano": ms_to_unix_nanos(start),
+                "endTimeUnixNano": ms_to_unix_nanos(end),
+            }));
+        }
+    }
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", json!(PROG_NAME)),
+                    attribute("service.version", json!(PROG_VERSION)),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": PROG_NAME, "version": PROG_VERSION },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+// only plain http endpoints are supported (e.g. a local collector): http://host:port[/path]
+fn split_endpoint(endpoint: &str) -> io::Result<(&str, &str)> {
+    let rest = endpoint.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only http:// endpoints are supported",
+        )
+    })?;
+    match rest.find('/') {
+        Some(i) if i + 1 < rest.len() => Ok((&rest[..i], &rest[i..])),
+        Some(i) => Ok((&rest[..i], OTLP_DEFAULT_PATH)),
+        None => Ok((rest, OTLP_DEFAULT_PATH)),
+    }
+}
+
+pub fn export(
+    endpoint: &str, hosts: &[Rc<RefCell<Host>>], mode: &str, run_id: &str, started: u128,
+    finished: u128,
+) -> io::Result<()> {
+    let (authority, path) = split_endpoint(endpoint)?;
+    let body = build_traces(hosts, mode, run_id, started, finished).to_string();
+    http::request(
+        authority,
+        "POST",
+        path,
+        &[],
+        Some(("application/json", &body)),
+        OTLP_TIMEOUT,
+    )
+    .map(|_| ())
+    .map_err(|e| io::Error::new(e.kind(), format!("collector: {}", e)))
+}
@@ -0,0 +1,344 @@
+use crate::aggregate;
+use crate::buffer_limit::with_marker;
+use crate::fdwatcher::{FdEvent, OutputConfig, PipeType};
+use crate::redact::Redactor;
+use crate::theme::Theme;
+use crate::utils::{generate_seed, Colorize};
+use crate::{Config, Host, RuntimeError, PROG_NAME};
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::rc::Rc;
+use std::sync::Arc;
+
+// what is done with the output of the hosts, picked by Config for every run; a mode
+// keeps whatever it needs across hosts
+pub trait OutputMode {
+    // LINE, GROUP, ... as the reports and the event stream name it
+    fn name(&self) -> &'static str;
+
+    // whether a host writes stdout and stderr to one pipe instead of one each
+    fn joined(&self) -> bool {
+        false
+    }
+
+    // a pipe is about to be read until it would block
+    fn read_started(&mut self) {}
+
+    // a chunk read from one of the host's pipes, not called with -s; `newline_print`
+    // tells whether the terminal is at the start of a line
+    fn output(
+        &mut self, event: &mut FdEvent, data: &[u8], config: &OutputConfig, redactor: &Redactor,
+        newline_print: &mut bool,
+    ) -> Result<(), RuntimeError>;
+
+    // the host closed the pipe, whatever is still held back goes out
+    fn pipe_closed(
+        &mut self, event: &mut FdEvent, config: &OutputConfig, redactor: &Redactor,
+        newline_print: &mut bool,
+    ) -> Result<(), RuntimeError>;
+
+    // before the first host is started, `done` counts the skipped ones
+    fn run_started(&mut self, _done: usize, _total: usize, _theme: &Theme) {}
+
+    // the host exited and is not retried, `done` counts it
+    fn host_finished(
+        &mut self, _host: &Rc<RefCell<Host>>, _done: usize, _total: usize, _theme: &Theme,
+    ) {
+    }
+
+    // every host is done
+    fn run_finished(&mut self, _hosts: &[Rc<RefCell<Host>>], _conf: &Config) {}
+}
+
+// every line as it comes, prefixed with the host
+#[derive(Debug, Default)]
+pub struct LineMode;
+
+impl OutputMode for LineMode {
+    fn name(&self) -> &'static str {
+        "LINE"
+    }
+
+    fn output(
+        &mut self, event: &mut FdEvent, data: &[u8], config: &OutputConfig, redactor: &Redactor,
+        _newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        event.process_line_buf(data, config, redactor);
+        Ok(())
+    }
+
+    fn pipe_closed(
+        &mut self, event: &mut FdEvent, config: &OutputConfig, redactor: &Redactor,
+        _newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        event.output_line_buf(config, redactor);
+        Ok(())
+    }
+}
+
+// output printed under a header of the host, or held until it exits with --buffered
+#[derive(Debug, Default)]
+pub struct GroupMode {
+    // the host printed last during the current read, the header is not repeated for it
+    last_host: Option<Arc<str>>,
+}
+
+impl OutputMode for GroupMode {
+    fn name(&self) -> &'static str {
+        "GROUP"
+    }
+
+    fn read_started(&mut self) {
+        self.last_host = None;
+    }
+
+    fn output(
+        &mut self, event: &mut FdEvent, data: &[u8], config: &OutputConfig, redactor: &Redactor,
+        newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        if config.buffered {
+            event.buffer_group_buf(data, config, redactor);
+            return Ok(());
+        }
+        event
+            .process_group_buf(data, &self.last_host, newline_print, config, redactor)
+            .map_err(|_| RuntimeError::WriteStreamError)?;
+        self.last_host = Some(Arc::clone(&event.get_host().borrow().name));
+        Ok(())
+    }
+
+    fn pipe_closed(
+        &mut self, event: &mut FdEvent, config: &OutputConfig, redactor: &Redactor,
+        newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        event
+            .flush_group_buf(&self.last_host, newline_print, config, redactor)
+            .map_err(|_| RuntimeError::WriteStreamError)?;
+        self.last_host = Some(Arc::clone(&event.get_host().borrow().name));
+        Ok(())
+    }
+}
+
+// by the hash of the output, how many hosts printed it and which
+type OutputGroups = HashMap<u64, (u32, Vec<Rc<RefCell<Host>>>)>;
+
+// hosts grouped by identical output, filled in as they finish so the progress line can
+// tell how many distinct outputs there are so far
+#[derive(Debug)]
+pub struct JoinMode {
+    seed: u64,
+    unique: i32,
+    groups: OutputGroups,
+}
+
+impl JoinMode {
+    pub fn new() -> JoinMode {
+        JoinMode {
+            seed: generate_seed(),
+            unique: 0,
+            groups: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, h: &Rc<RefCell<Host>>) {
+        let mut host = h.borrow_mut();
+        if host.cp.output_index >= 0 {
+            return;
+        }
+        let hash = twox_hash::XxHash64::oneshot(self.seed, host.cp.output_buffer.as_bytes());
+        match self.groups.get_mut(&hash) {
+            Some((num_same, grouped_hosts)) => {
+                *num_same += 1;
+                grouped_hosts.push(Rc::clone(h));
+            }
+            None => {
+                self.groups.insert(hash, (1, vec![Rc::clone(h)]));
+                self.unique += 1;
+            }
+        }
+        host.cp.output_index = self.unique - 1;
+    }
+
+    fn print_groups(&self, num_hosts: usize, theme: &Theme) {
+        let unique_hosts = self.unique;
+        let (magenta, cyan) = (&theme.meta, &theme.hostname);
+
+        outln!(
+            "finished with {} unique result{}\n",
+            unique_hosts.to_string().as_str().colorize(magenta),
+            if unique_hosts == 1 { "" } else { "s" }
+        );
+
+        for (num_same, grouped_hosts) in self.groups.values() {
+            // every host of a group has the same output, so any of them has its size
+            let output_bytes = grouped_hosts[0].borrow().cp.output_buffer.len();
+            // output cut at the cap may hide differences, so the group may be merged wrongly
+            let truncated = grouped_hosts.iter().any(|host| host.borrow().cp.truncated);
+            out!(
+                "hosts ({}/{}, {} byte{}{}):",
+                num_same.to_string().as_str().colorize(magenta),
+                num_hosts.to_string().as_str().colorize(magenta),
+                output_bytes.to_string().as_str().colorize(magenta),
+                if output_bytes == 1 { "" } else { "s" },
+                if truncated {
+                    format!(", {}", "truncated".colorize(&theme.exit_fail))
+                } else {
+                    String::new()
+                }
+            );
+
+            for host in grouped_hosts.iter() {
+                let host = host.borrow();
+                out!(" {}", host.name.as_ref().colorize(cyan));
+            }
+
+            // grouped_hosts vector has always at least one element
+            let last_host = grouped_hosts.last().unwrap().borrow();
+
+            if last_host.cp.output_buffer.is_empty() {
+                out!("{}", "- no output -".colorize(magenta));
+            } else {
+                out!(
+                    "\n{}",
+                    with_marker(&last_host.cp.output_buffer, last_host.cp.dropped)
+                );
+                if !last_host.cp.output_buffer.ends_with('\n') {
+                    outln!();
+                }
+            }
+            outln!();
+        }
+    }
+}
+
+impl OutputMode for JoinMode {
+    fn name(&self) -> &'static str {
+        "JOIN"
+    }
+
+    // the output is grouped as a whole, the way it would show in a terminal
+    fn joined(&self) -> bool {
+        true
+    }
+
+    fn output(
+        &mut self, event: &mut FdEvent, data: &[u8], config: &OutputConfig, _redactor: &Redactor,
+        _newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        // past the soft --total-output-limit nothing more is collected
+        if config.buffer_output {
+            event.process_join_buf(data, config);
+        }
+        Ok(())
+    }
+
+    fn pipe_closed(
+        &mut self, event: &mut FdEvent, config: &OutputConfig, redactor: &Redactor,
+        _newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        event.output_join_buf(config, redactor);
+        Ok(())
+    }
+
+    fn run_started(&mut self, done: usize, total: usize, theme: &Theme) {
+        if io::stdout().is_terminal() {
+            out!(
+                "[{}] finished {}/{}\r",
+                PROG_NAME.colorize(&theme.hostname),
+                done.to_string().as_str().colorize(&theme.meta),
+                total.to_string().as_str().colorize(&theme.meta)
+            );
+        }
+    }
+
+    fn host_finished(
+        &mut self, host: &Rc<RefCell<Host>>, done: usize, total: usize, theme: &Theme,
+    ) {
+        self.add(host);
+        if io::stdout().is_terminal() {
+            out!(
+                "[{}] finished {}/{}, {} unique output{} so far\r",
+                PROG_NAME.colorize(&theme.hostname),
+                done.to_string().as_str().colorize(&theme.meta),
+                total.to_string().as_str().colorize(&theme.meta),
+                self.unique.to_string().as_str().colorize(&theme.meta),
+                if self.unique == 1 { "" } else { "s" }
+            );
+
+            if done == total {
+                out!("\n\n");
+            }
+        }
+    }
+
+    fn run_finished(&mut self, hosts: &[Rc<RefCell<Host>>], conf: &Config) {
+        self.print_groups(hosts.len(), &conf.theme);
+        if let Some(aggregates) = &conf.aggregate {
+            aggregate::print_aggregates(hosts, aggregates, &conf.theme);
+        }
+    }
+}
+
+// --json-lines, every line an object of its own with the host and the stream it came
+// from, for tools that read the output as it comes
+#[derive(Debug, Default)]
+pub struct JsonLinesMode {
+    // what each pipe printed after its last newline, by its fd
+    partial: HashMap<i32, Vec<u8>>,
+}
+
+impl JsonLinesMode {
+    fn print_line(event: &FdEvent, line: &[u8], redactor: &Redactor) {
+        let stream = match event.pipe_type() {
+            PipeType::StdErr => "stderr",
+            _ => "stdout",
+        };
+        let line = String::from_utf8_lossy(line);
+        outln!(
+            "{}",
+            json!({
+                "host": event.get_host().borrow().name.as_ref(),
+                "stream": stream,
+                "line": redactor.redact(&line),
+            })
+        );
+    }
+}
+
+impl OutputMode for JsonLinesMode {
+    fn name(&self) -> &'static str {
+        "JSON_LINES"
+    }
+
+    // lines are cut at --max-line-length like in line mode
+    fn output(
+        &mut self, event: &mut FdEvent, data: &[u8], config: &OutputConfig, redactor: &Redactor,
+        _newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        let partial = self.partial.entry(event.fd()).or_default();
+        for &byte in data {
+            if byte == b'\n' {
+                Self::print_line(event, partial, redactor);
+                partial.clear();
+            } else if partial.len() < config.max_line_length as usize {
+                partial.push(byte);
+            } else {
+                event.get_host().borrow_mut().cp.truncated = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn pipe_closed(
+        &mut self, event: &mut FdEvent, _config: &OutputConfig, redactor: &Redactor,
+        _newline_print: &mut bool,
+    ) -> Result<(), RuntimeError> {
+        match self.partial.remove(&event.fd()) {
+            Some(partial) if !partial.is_empty() => Self::print_line(event, &partial, redactor),
+            _ => (),
+        }
+        Ok(())
+    }
+}
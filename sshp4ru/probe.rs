@@ -0,0 +1,94 @@
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// a host slower than this to accept a connection is reported as unreachable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// servers may send lines before their identification, as long as these all together
+const MAX_BANNER_LINES: usize = 16;
+
+// what one connect to a host's ssh port found out
+pub struct Probe {
+    // known once the name resolved, even when the connect then fails
+    pub address: Option<IpAddr>,
+    pub latency: Result<Duration, String>,
+    // the server's identification string, e.g. SSH-2.0-OpenSSH_9.6, when asked for and
+    // connected
+    pub banner: Option<Result<String, String>>,
+}
+
+// how long a TCP connect to the ssh port takes, the handshake is left to ssh; with
+// `read_banner` the server's identification is read as well
+pub fn probe(host: &str, port: u16, read_banner: bool) -> Probe {
+    // `user@host` is how hosts files pick the login for a single host
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    let addr = match (host, port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None) => return Probe::failed(None, "no address".to_string()),
+        Err(e) => return Probe::failed(None, e.to_string()),
+    };
+    let started = Instant::now();
+    let stream = match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+        Ok(stream) => stream,
+        Err(e) => return Probe::failed(Some(addr.ip()), e.to_string()),
+    };
+    let latency = started.elapsed();
+    Probe {
+        address: Some(addr.ip()),
+        latency: Ok(latency),
+        banner: read_banner.then(|| banner(stream).map_err(|e| e.to_string())),
+    }
+}
+
+impl Probe {
+    fn failed(address: Option<IpAddr>, error: String) -> Probe {
+        Probe {
+            address,
+            latency: Err(error),
+            banner: None,
+        }
+    }
+}
+
+// the first line starting with `SSH-`, sent by the server before anything else is said
+fn banner(stream: TcpStream) -> std::io::Result<String> {
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    for _ in 0..MAX_BANNER_LINES {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        if text.starts_with("SSH-") {
+            return Ok(text.trim_end().to_string());
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "no ssh identification from the server",
+    ))
+}
+
+// probes `parallel` hosts at a time on their own threads, results in the order of `targets`
+pub fn probe_hosts(targets: &[(Arc<str>, u16)], parallel: usize, read_banner: bool) -> Vec<Probe> {
+    let mut results = Vec::with_capacity(targets.len());
+    for batch in targets.chunks(parallel.max(1)) {
+        std::thread::scope(|scope| {
+            let probes: Vec<_> = batch
+                .iter()
+                .map(|(host, port)| scope.spawn(move || probe(host, *port, read_banner)))
+                .collect();
+            for probe in probes {
+                results.push(
+                    probe
+                        .join()
+                        .unwrap_or_else(|_| Probe::failed(None, "probe panicked".to_string())),
+                );
+            }
+        });
+    }
+    results
+}
@@ -0,0 +1,52 @@
+use crate::RuntimeError;
+use nix::sys::termios::{self, LocalFlags, SetArg};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+
+const TTY_PATH: &str = "/dev/tty";
+
+// stdin may carry the hosts list (and is /dev/null while children run), so questions
+// always go through the controlling terminal instead
+fn open_tty() -> Result<File, RuntimeError> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(TTY_PATH)
+        .map_err(RuntimeError::TtyUnavailable)
+}
+
+pub fn confirm(question: &str) -> Result<bool, RuntimeError> {
+    let mut tty = open_tty()?;
+    let answer = (|| -> io::Result<String> {
+        write!(tty, "{} [y/N] ", question)?;
+        tty.flush()?;
+        let mut answer = String::new();
+        io::BufReader::new(&tty).read_line(&mut answer)?;
+        Ok(answer)
+    })()
+    .map_err(RuntimeError::TtyUnavailable)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "YES" | "Yes"))
+}
+
+// read without echo, the terminal is restored whether or not the read worked
+pub fn password(prompt: &str) -> Result<String, RuntimeError> {
+    let mut tty = open_tty()?;
+    let password = (|| -> io::Result<String> {
+        write!(tty, "{}", prompt)?;
+        tty.flush()?;
+        let saved = termios::tcgetattr(&tty)?;
+        let mut silent = saved.clone();
+        silent.local_flags.remove(LocalFlags::ECHO);
+        termios::tcsetattr(&tty, SetArg::TCSAFLUSH, &silent)?;
+        let mut password = String::new();
+        let read = io::BufReader::new(&tty).read_line(&mut password);
+        termios::tcsetattr(&tty, SetArg::TCSAFLUSH, &saved)?;
+        // the newline typed was not echoed either
+        writeln!(tty)?;
+        read?;
+        Ok(password.trim_end_matches(['\r', '\n']).to_string())
+    })()
+    .map_err(RuntimeError::TtyUnavailable)?;
+    Ok(password)
+}
@@ -0,0 +1,77 @@
+use regex::bytes;
+use regex::Regex;
+use std::borrow::Cow;
+
+const REDACTED: &str = "****";
+
+// a line this long without a newline is redacted as it is
+const LINE_LIMIT: usize = 64 * 1024;
+
+// string patterns are used for commands and debug output, byte patterns for raw child output
+#[derive(Debug, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    byte_patterns: Vec<bytes::Regex>,
+}
+
+impl Redactor {
+    pub fn add(&mut self, pattern: &str) -> Result<(), regex::Error> {
+        self.patterns.push(Regex::new(pattern)?);
+        self.byte_patterns.push(bytes::Regex::new(pattern)?);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn redact<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        let mut redacted = Cow::Borrowed(s);
+        for pattern in self.patterns.iter() {
+            if let Cow::Owned(replaced) = pattern.replace_all(&redacted, REDACTED) {
+                redacted = Cow::Owned(replaced);
+            }
+        }
+        redacted
+    }
+
+    pub fn redact_bytes<'a>(&self, buffer: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut redacted = Cow::Borrowed(buffer);
+        for pattern in self.byte_patterns.iter() {
+            if let Cow::Owned(replaced) = pattern.replace_all(&redacted, REDACTED.as_bytes()) {
+                redacted = Cow::Owned(replaced);
+            }
+        }
+        redacted
+    }
+}
+
+// child output arrives in chunks that may cut a secret in two, so it is redacted a line
+// at a time and the partial last line waits for the next chunk
+#[derive(Debug, Default)]
+pub struct RedactStream {
+    tail: Vec<u8>,
+}
+
+impl RedactStream {
+    // the complete lines so far, redacted; nothing is held back without patterns
+    pub fn push<'a>(&mut self, redactor: &Redactor, data: &'a [u8]) -> Cow<'a, [u8]> {
+        if redactor.is_empty() {
+            return Cow::Borrowed(data);
+        }
+        self.tail.extend_from_slice(data);
+        let end = match self.tail.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None if self.tail.len() >= LINE_LIMIT => self.tail.len(),
+            None => return Cow::Owned(Vec::new()),
+        };
+        let lines: Vec<u8> = self.tail.drain(..end).collect();
+        Cow::Owned(redactor.redact_bytes(&lines).into_owned())
+    }
+
+    // the pipe was closed, the last line may lack a newline
+    pub fn finish(&mut self, redactor: &Redactor) -> Vec<u8> {
+        let tail = std::mem::take(&mut self.tail);
+        redactor.redact_bytes(&tail).into_owned()
+    }
+}
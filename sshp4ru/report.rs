@@ -0,0 +1,393 @@
+use crate::utils::{hostname, monotonic_time_ms, rfc3339_utc, ResourceUsage};
+use crate::{CpState, Host};
+use crate::{PROG_NAME, PROG_VERSION};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
+
+// hosts lacking the key are still counted, under their own group
+const NO_META_VALUE: &str = "(none)";
+// below this, compressing an artifact saves too little to be worth the extra step to read it
+const DEFAULT_COMPRESS_THRESHOLD: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+// --compress-output <gzip|zstd>[,<min-bytes>]
+#[derive(Debug, Clone, Copy)]
+pub struct CompressOutput {
+    compression: Compression,
+    threshold: u64,
+}
+
+impl CompressOutput {
+    pub fn from_spec(spec: &str) -> Result<CompressOutput, String> {
+        let (format, threshold) = match spec.split_once(',') {
+            Some((format, threshold)) => (
+                format,
+                threshold
+                    .parse::<u64>()
+                    .map_err(|_| format!("`{}` is not a size in bytes", threshold))?,
+            ),
+            None => (spec, DEFAULT_COMPRESS_THRESHOLD),
+        };
+        let compression = match format {
+            "gzip" => Compression::Gzip,
+            "zstd" => Compression::Zstd,
+            _ => {
+                return Err(format!(
+                    "unknown format `{}` (expected gzip or zstd)",
+                    format
+                ))
+            }
+        };
+        Ok(CompressOutput {
+            compression,
+            threshold,
+        })
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.compression {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GroupSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub total_ms: u128,
+    pub max_ms: u128,
+}
+
+impl GroupSummary {
+    pub fn avg_ms(&self) -> u128 {
+        self.total_ms / (self.succeeded + self.failed).max(1) as u128
+    }
+}
+
+pub fn summarize_by(hosts: &[Rc<RefCell<Host>>], key: &str) -> BTreeMap<String, GroupSummary> {
+    let mut groups: BTreeMap<String, GroupSummary> = BTreeMap::new();
+    for host in hosts.iter() {
+        let host = host.borrow();
+        let value = host.meta.get(key).map_or(NO_META_VALUE, String::as_str);
+        let group = groups.entry(value.to_string()).or_default();
+        if host.cp.exit_code == 0 {
+            group.succeeded += 1;
+        } else {
+            group.failed += 1;
+        }
+        if let CpState::Done = host.cp.state {
+            let duration = host.cp.finished_time - host.cp.started_time;
+            group.total_ms += duration;
+            group.max_ms = group.max_ms.max(duration);
+        }
+    }
+    groups
+}
+
+// what all the ssh children of the run used, for sizing the machine running it
+pub fn total_usage(hosts: &[Rc<RefCell<Host>>]) -> ResourceUsage {
+    let mut total = ResourceUsage::default();
+    for host in hosts.iter() {
+        total.add(&host.borrow().cp.usage);
+    }
+    total
+}
+
+// bytes per second, runs shorter than a millisecond count as one
+pub fn throughput(bytes: u64, ms: u128) -> u64 {
+    (bytes as u128 * 1000 / ms.max(1)) as u64
+}
+
+fn host_throughput(host: &Host) -> Option<u64> {
+    match host.cp.state {
+        CpState::Done if !host.cp.skipped => Some(throughput(
+            host.cp.output_bytes,
+            host.cp.finished_time - host.cp.started_time,
+        )),
+        _ => None,
+    }
+}
+
+// bytes read from all hosts, and the host that sent its output slowest, to spot slow
+// links when collecting logs or files
+pub fn output_totals(hosts: &[Rc<RefCell<Host>>]) -> (u64, Option<(String, u64)>) {
+    let mut total = 0;
+    let mut slowest: Option<(String, u64)> = None;
+    for host in hosts.iter() {
+        let host = host.borrow();
+        total += host.cp.output_bytes;
+        if host.cp.output_bytes == 0 {
+            continue;
+        }
+        if let Some(rate) = host_throughput(&host) {
+            if slowest.as_ref().is_none_or(|(_, slowest)| rate < *slowest) {
+                slowest = Some((host.name.to_string(), rate));
+            }
+        }
+    }
+    (total, slowest)
+}
+
+fn host_entry(host: &Host) -> Value {
+    let duration_ms = match host.cp.state {
+        CpState::Done => Some((host.cp.finished_time - host.cp.started_time) as u64),
+        _ => None,
+    };
+    json!({
+        "host": &*host.name,
+        "meta": host.meta,
+        "note": host.note,
+        "exit_code": host.cp.exit_code,
+        "attempts": host.cp.attempts,
+        "skipped": host.cp.skipped,
+        "reconnects": host.cp.reconnects,
+        "killed_on_output": host.cp.matched_output,
+        "json_value": host.cp.json_value.as_ref().and_then(|value| value.as_ref().ok()),
+        "json_error": host.cp.json_value.as_ref().and_then(|value| value.as_ref().err()),
+        "duration_ms": duration_ms,
+        "connect_ms": host.latency.as_ref().and_then(|latency| latency.as_ref().ok()).map(|latency| latency.as_millis() as u64),
+        "connect_error": host.latency.as_ref().and_then(|latency| latency.as_ref().err()),
+        "address": host.address.map(|address| address.to_string()),
+        "ssh_banner": host.banner.as_ref().and_then(|banner| banner.as_ref().ok()),
+        "ssh_banner_error": host.banner.as_ref().and_then(|banner| banner.as_ref().err()),
+        "cpu_ms": host.cp.usage.cpu_ms(),
+        "max_rss_kb": host.cp.usage.max_rss_kb,
+        "output_bytes": host.cp.output_bytes,
+        "stderr_bytes": host.cp.stderr_bytes,
+        "failed_on_stderr": host.cp.failed_on_stderr,
+        "output_digest": format!("{:016x}", host.cp.output_digest.finish()),
+        "bytes_per_sec": host_throughput(host),
+        "diagnostics": host.diagnostics.as_ref().and_then(|diagnostics| diagnostics.as_ref().ok()).map(|(output, exit_code)| json!({ "output": output, "exit_code": exit_code })),
+        "diagnostics_error": host.diagnostics.as_ref().and_then(|diagnostics| diagnostics.as_ref().err()),
+        "map_exit": host.follow_ups.iter().map(|follow_up| json!({
+            "code": follow_up.code,
+            "command": follow_up.command,
+            "local": follow_up.local,
+            "output": follow_up.outcome.as_ref().ok().map(|(output, _)| output),
+            "exit_code": follow_up.outcome.as_ref().ok().map(|(_, exit_code)| exit_code),
+            "error": follow_up.outcome.as_ref().err(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+pub fn build_report(
+    hosts: &[Rc<RefCell<Host>>], mode: &str, started: u128, summary_by: Option<&str>,
+) -> Value {
+    let entries: Vec<Value> = hosts
+        .iter()
+        .map(|host| host_entry(&host.borrow()))
+        .collect();
+    let failed = hosts
+        .iter()
+        .filter(|host| host.borrow().cp.exit_code != 0)
+        .count();
+
+    let mut report = json!({
+        "program": format!("{} {}", PROG_NAME, PROG_VERSION),
+        "mode": mode,
+        "started_ms": started as u64,
+        "duration_ms": (monotonic_time_ms() - started) as u64,
+        "succeeded": hosts.len() - failed,
+        "failed": failed,
+        "hosts": entries,
+    });
+
+    let (output_bytes, _) = output_totals(hosts);
+    report["output_bytes"] = json!(output_bytes);
+    report["bytes_per_sec"] = json!(throughput(output_bytes, monotonic_time_ms() - started));
+
+    let usage = total_usage(hosts);
+    report["resources"] = json!({
+        "user_ms": usage.user_ms,
+        "system_ms": usage.system_ms,
+        "peak_rss_kb": usage.max_rss_kb,
+    });
+
+    if let Some(key) = summary_by {
+        let groups: serde_json::Map<String, Value> = summarize_by(hosts, key)
+            .into_iter()
+            .map(|(value, group)| {
+                let summary = json!({
+                    "succeeded": group.succeeded,
+                    "failed": group.failed,
+                    "avg_ms": group.avg_ms() as u64,
+                    "max_ms": group.max_ms as u64,
+                });
+                (value, summary)
+            })
+            .collect();
+        report["summary_by"] = json!({ "key": key, "groups": groups });
+    }
+
+    report
+}
+
+// `data` goes to `path` as is, or compressed to `path` with the format's extension once it
+// reaches the threshold; returns where it was written
+pub fn write_artifact(
+    path: &str, data: &[u8], compress: Option<&CompressOutput>,
+) -> io::Result<PathBuf> {
+    let compress = match compress {
+        Some(compress) if data.len() as u64 >= compress.threshold => compress,
+        _ => {
+            let mut file = File::create(path)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+            return Ok(PathBuf::from(path));
+        }
+    };
+
+    let extension = compress.extension();
+    let path = if Path::new(path).extension().and_then(|ext| ext.to_str()) == Some(extension) {
+        PathBuf::from(path)
+    } else {
+        PathBuf::from(format!("{}.{}", path, extension))
+    };
+    let file = File::create(&path)?;
+    let file = match compress.compression {
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(file, 0)?;
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+    };
+    file.sync_all()?;
+    Ok(path)
+}
+
+// the average and slowest connect latency of the probed hosts, and how many of them
+// could not be reached
+pub fn latency_totals(hosts: &[Rc<RefCell<Host>>]) -> (Option<(u128, String, u128)>, usize) {
+    let (mut connected, mut total, mut failed) = (0, 0, 0);
+    let mut slowest: Option<(String, u128)> = None;
+    for host in hosts.iter() {
+        let host = host.borrow();
+        match &host.latency {
+            Some(Ok(latency)) => {
+                let ms = latency.as_millis();
+                connected += 1;
+                total += ms;
+                if slowest.as_ref().is_none_or(|(_, max)| ms > *max) {
+                    slowest = Some((host.name.to_string(), ms));
+                }
+            }
+            Some(Err(_)) => failed += 1,
+            None => {}
+        }
+    }
+    let latency = slowest.map(|(name, max)| (total / connected, name, max));
+    (latency, failed)
+}
+
+// for reading an archived report without knowing where it came from
+pub fn environment(config: Value, started: u128) -> Value {
+    json!({
+        "program": PROG_NAME,
+        "version": PROG_VERSION,
+        "control_host": hostname(),
+        "invoked_at": rfc3339_utc(UNIX_EPOCH + Duration::from_millis(started as u64)),
+        "config": config,
+    })
+}
+
+// written as a whole once the run is over, pretty printed for humans and diffs
+pub fn write_report(
+    path: &str, report: &Value, compress: Option<&CompressOutput>,
+) -> io::Result<PathBuf> {
+    let mut data = serde_json::to_vec_pretty(report)?;
+    data.push(b'\n');
+    write_artifact(path, &data, compress)
+}
+
+// a report written by --report, compressed ones are recognized by their extension
+pub fn read_report(path: &str) -> io::Result<Value> {
+    let file = io::BufReader::new(File::open(path)?);
+    let report = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => serde_json::from_reader(flate2::bufread::MultiGzDecoder::new(file))?,
+        Some("zst") => serde_json::from_reader(zstd::Decoder::with_buffer(file)?)?,
+        _ => serde_json::from_reader(file)?,
+    };
+    Ok(report)
+}
+
+// one host per line with its metadata, so the file can be fed back with -f
+pub fn write_failed_hosts(path: &str, hosts: &[Rc<RefCell<Host>>]) -> io::Result<()> {
+    let mut out = io::BufWriter::new(File::create(path)?);
+    for host in hosts.iter() {
+        let host = host.borrow();
+        if host.cp.exit_code == 0 {
+            continue;
+        }
+        write!(out, "{}", host.full_name)?;
+        for (key, value) in host.meta.iter() {
+            write!(out, " {}={}", key, value)?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+// whether the host's receipt records a success of `command` finished at or after `since`;
+// a missing or unreadable receipt means the host has to run
+pub fn succeeded_since(dir: &str, host: &Host, command: &str, since: u128) -> bool {
+    let path = Path::new(dir).join(format!("{}.json", host.full_name));
+    let receipt: Value = match std::fs::read(&path)
+        .ok()
+        .and_then(|receipt| serde_json::from_slice(&receipt).ok())
+    {
+        Some(receipt) => receipt,
+        None => return false,
+    };
+    receipt["exit_code"] == 0
+        && receipt["command"] == command
+        && receipt["finished_ms"].as_u64().unwrap_or(0) as u128 >= since
+}
+
+// how long the host's last recorded run took, for estimating the next one
+pub fn last_duration_ms(dir: &str, host: &Host) -> Option<u64> {
+    let path = Path::new(dir).join(format!("{}.json", host.full_name));
+    let receipt: Value = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    let started = receipt["started_ms"].as_u64()?;
+    receipt["finished_ms"].as_u64()?.checked_sub(started)
+}
+
+// written to a temporary name and renamed, so watchers never see a partial receipt
+pub fn write_receipt(dir: &str, host: &Host, command: &str, run_id: &str) -> io::Result<()> {
+    let receipt = json!({
+        "host": &*host.full_name,
+        "command": command,
+        "run_id": run_id,
+        "started_ms": host.cp.started_time as u64,
+        "finished_ms": host.cp.finished_time as u64,
+        "exit_code": host.cp.exit_code,
+        "attempt": host.cp.attempts,
+        "output_digest": format!("{:016x}", host.cp.output_digest.finish()),
+    });
+
+    let path = Path::new(dir).join(format!("{}.json", host.full_name));
+    let tmp_path = Path::new(dir).join(format!(".{}.json.tmp", host.full_name));
+    let mut file = File::create(&tmp_path)?;
+    serde_json::to_writer(&mut file, &receipt)?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, &path)
+}
@@ -0,0 +1,145 @@
+use crate::report::read_report;
+use crate::utils::Colorize;
+use crate::{Config, PROG_NAME};
+use std::collections::{BTreeMap, BTreeSet};
+
+// slower by less than this is jitter, whatever the percentage
+const MIN_REGRESSION_MS: u64 = 100;
+const DEFAULT_REGRESSION_PERCENT: u32 = 50;
+
+// what is compared of a host, reports written before a field existed leave it out
+struct HostResult {
+    exit_code: i64,
+    duration_ms: Option<u64>,
+    output_digest: Option<String>,
+}
+
+fn load(path: &str) -> Result<BTreeMap<String, HostResult>, String> {
+    let report = read_report(path).map_err(|e| format!("{}: {}", path, e))?;
+    let entries = report["hosts"]
+        .as_array()
+        .ok_or_else(|| format!("{}: not a report, it has no hosts", path))?;
+    let mut hosts = BTreeMap::new();
+    for entry in entries {
+        let Some(name) = entry["host"].as_str() else {
+            continue;
+        };
+        // a host run twice in a report, the later result is the one that counts
+        hosts.insert(
+            name.to_string(),
+            HostResult {
+                exit_code: entry["exit_code"].as_i64().unwrap_or(0),
+                duration_ms: entry["duration_ms"].as_u64(),
+                output_digest: entry["output_digest"].as_str().map(str::to_string),
+            },
+        );
+    }
+    Ok(hosts)
+}
+
+// how many different outputs the hosts had, the groups -j would print
+fn output_groups(hosts: &BTreeMap<String, HostResult>) -> usize {
+    hosts
+        .values()
+        .filter_map(|host| host.output_digest.as_deref())
+        .collect::<BTreeSet<_>>()
+        .len()
+}
+
+// prints what changed from the old report to the new one and returns the exit code: 0
+// when nothing got worse, 1 when hosts newly failed or got slower, 2 when a report
+// cannot be read
+pub fn diff_reports(conf: &Config) -> u8 {
+    let theme = &conf.theme;
+    // both are there, checked when the arguments were parsed
+    let (old_path, new_path) = (&conf.remote_command[0], &conf.remote_command[1]);
+    let (old, new) = match (load(old_path), load(new_path)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}: {}", PROG_NAME, e);
+            return 2;
+        }
+    };
+    let percent = conf
+        .regression_threshold
+        .unwrap_or(DEFAULT_REGRESSION_PERCENT) as u64;
+
+    let (mut failed, mut recovered, mut slower, mut changed) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (name, before) in old.iter() {
+        let Some(after) = new.get(name) else {
+            continue;
+        };
+        if before.exit_code == 0 && after.exit_code != 0 {
+            failed.push(format!("{} (exit {})", name, after.exit_code));
+        } else if before.exit_code != 0 && after.exit_code == 0 {
+            recovered.push(name.clone());
+        }
+        if let (Some(before), Some(after)) = (before.duration_ms, after.duration_ms) {
+            if after >= before + MIN_REGRESSION_MS && after * 100 > before * (100 + percent) {
+                slower.push(format!("{} ({} ms -> {} ms)", name, before, after));
+            }
+        }
+        if let (Some(before), Some(after)) = (&before.output_digest, &after.output_digest) {
+            if before != after {
+                changed.push(name.clone());
+            }
+        }
+    }
+    let removed: Vec<String> = old
+        .keys()
+        .filter(|name| !new.contains_key(*name))
+        .cloned()
+        .collect();
+    let added: Vec<String> = new
+        .keys()
+        .filter(|name| !old.contains_key(*name))
+        .cloned()
+        .collect();
+
+    let sections = [
+        ("newly failed", &failed, &theme.exit_fail),
+        ("recovered", &recovered, &theme.exit_ok),
+        ("slower", &slower, &theme.exit_fail),
+        ("output changed", &changed, &theme.meta),
+        ("only in old", &removed, &theme.meta),
+        ("only in new", &added, &theme.meta),
+    ];
+    let mut unchanged = true;
+    for (label, hosts, color) in sections {
+        if hosts.is_empty() {
+            continue;
+        }
+        unchanged = false;
+        outln!(
+            "[{}] {} ({}): {}",
+            PROG_NAME.colorize(&theme.hostname),
+            label.colorize(color),
+            hosts.len(),
+            hosts.join(", ")
+        );
+    }
+    let groups = (output_groups(&old), output_groups(&new));
+    if groups.0 != groups.1 {
+        unchanged = false;
+        outln!(
+            "[{}] output groups: {} -> {}",
+            PROG_NAME.colorize(&theme.hostname),
+            groups.0,
+            groups.1
+        );
+    }
+    if unchanged {
+        outln!(
+            "[{}] no changes across {} hosts",
+            PROG_NAME.colorize(&theme.hostname),
+            new.len()
+        );
+    }
+
+    if failed.is_empty() && slower.is_empty() {
+        0
+    } else {
+        1
+    }
+}
@@ -0,0 +1,147 @@
+use crate::report::{read_report, write_report};
+use crate::{Config, PROG_NAME, PROG_VERSION};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+// a host's result and the runs it replaced
+struct Merged {
+    entry: Value,
+    started_ms: u64,
+    earlier: Vec<Value>,
+}
+
+// combines the reports given as arguments into one: the host's result from the run that
+// started last wins, ties go to the report given later. Every host records the report
+// its result came from and the results it replaced. Written to --report, else printed;
+// returns the exit code: 0 when every host succeeded, 1 when some failed, 2 when a
+// report cannot be read or written
+pub fn merge_reports(conf: &Config) -> u8 {
+    let mut hosts: BTreeMap<String, Merged> = BTreeMap::new();
+    let mut sources = Vec::new();
+    let (mut started, mut finished) = (u64::MAX, 0);
+    let mut modes: Vec<String> = Vec::new();
+    let mut warnings = Vec::new();
+
+    for path in conf.remote_command.iter() {
+        let report = match read_report(path) {
+            Ok(report) if report["hosts"].is_array() => report,
+            Ok(_) => {
+                eprintln!("{}: {}: not a report, it has no hosts", PROG_NAME, path);
+                return 2;
+            }
+            Err(e) => {
+                eprintln!("{}: {}: {}", PROG_NAME, path, e);
+                return 2;
+            }
+        };
+        let report_started = report["started_ms"].as_u64().unwrap_or(0);
+        let duration = report["duration_ms"].as_u64().unwrap_or(0);
+        started = started.min(report_started);
+        finished = finished.max(report_started + duration);
+        if let Some(mode) = report["mode"].as_str() {
+            if !modes.iter().any(|known| known == mode) {
+                modes.push(mode.to_string());
+            }
+        }
+        if let Some(report_warnings) = report["warnings"].as_array() {
+            warnings.extend(report_warnings.iter().cloned());
+        }
+        let entries = report["hosts"].as_array().map_or(&[][..], Vec::as_slice);
+        sources.push(json!({
+            "report": path,
+            "program": report["program"],
+            "run_id": report["run_id"],
+            "started_ms": report_started,
+            "hosts": entries.len(),
+        }));
+
+        for entry in entries {
+            let Some(name) = entry["host"].as_str() else {
+                continue;
+            };
+            let mut entry = entry.clone();
+            entry["merged_from"] = json!(path);
+            let provenance = |entry: &Value| {
+                json!({
+                    "report": entry["merged_from"],
+                    "exit_code": entry["exit_code"],
+                    "duration_ms": entry["duration_ms"],
+                })
+            };
+            match hosts.get_mut(name) {
+                Some(merged) if report_started >= merged.started_ms => {
+                    let replaced = std::mem::replace(&mut merged.entry, entry);
+                    merged.earlier.push(provenance(&replaced));
+                    merged.started_ms = report_started;
+                }
+                Some(merged) => merged.earlier.push(provenance(&entry)),
+                None => {
+                    hosts.insert(
+                        name.to_string(),
+                        Merged {
+                            entry,
+                            started_ms: report_started,
+                            earlier: Vec::new(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    let mut failed = 0;
+    let mut output_bytes = 0;
+    let entries: Vec<Value> = hosts
+        .into_values()
+        .map(|mut merged| {
+            if merged.entry["exit_code"].as_i64().unwrap_or(0) != 0 {
+                failed += 1;
+            }
+            output_bytes += merged.entry["output_bytes"].as_u64().unwrap_or(0);
+            if !merged.earlier.is_empty() {
+                merged.entry["replaced"] = Value::Array(merged.earlier);
+            }
+            merged.entry
+        })
+        .collect();
+
+    // runs in different modes are still merged, their output is not compared here
+    let mode = match modes.as_slice() {
+        [mode] => mode.clone(),
+        _ => "MIXED".to_string(),
+    };
+    let report = json!({
+        "program": format!("{} {}", PROG_NAME, PROG_VERSION),
+        "mode": mode,
+        "started_ms": started,
+        "duration_ms": finished.saturating_sub(started),
+        "succeeded": entries.len() - failed,
+        "failed": failed,
+        "hosts": entries,
+        "output_bytes": output_bytes,
+        "warnings": warnings,
+        "merged_from": sources,
+    });
+
+    match &conf.report {
+        Some(path) => {
+            if let Err(e) = write_report(path, &report, conf.compress_output.as_ref()) {
+                eprintln!("{}: {}: {}", PROG_NAME, path, e);
+                return 2;
+            }
+        }
+        None => match serde_json::to_string_pretty(&report) {
+            Ok(report) => outln!("{}", report),
+            Err(e) => {
+                eprintln!("{}: {}", PROG_NAME, e);
+                return 2;
+            }
+        },
+    }
+
+    if failed == 0 {
+        0
+    } else {
+        1
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+// exit code ssh(1) uses when the connection itself failed
+const SSH_CONNECT_FAILURE: i32 = 255;
+// exit code of timeout(1) when the command ran out of time
+const TIMEOUT_EXPIRED: i32 = 124;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureClass {
+    Connect,
+    Timeout,
+    Exit(i32),
+}
+
+impl FailureClass {
+    fn parse(s: &str) -> Result<FailureClass, String> {
+        match s {
+            "connect" => Ok(FailureClass::Connect),
+            "timeout" => Ok(FailureClass::Timeout),
+            _ => match s.strip_prefix("exit=").map(str::parse) {
+                Some(Ok(code)) => Ok(FailureClass::Exit(code)),
+                _ => Err(format!(
+                    "unknown failure class `{}` (expected connect, timeout or exit=<code>)",
+                    s
+                )),
+            },
+        }
+    }
+
+    fn matches(&self, exit_code: i32) -> bool {
+        match self {
+            FailureClass::Connect => exit_code == SSH_CONNECT_FAILURE,
+            FailureClass::Timeout => exit_code == TIMEOUT_EXPIRED,
+            FailureClass::Exit(code) => exit_code == *code,
+        }
+    }
+}
+
+// without --retry-on every failure is retried
+#[derive(Debug, Default)]
+pub struct RetryPolicy {
+    classes: Option<Vec<FailureClass>>,
+}
+
+impl RetryPolicy {
+    pub fn from_spec(spec: &str) -> Result<RetryPolicy, String> {
+        let classes = spec
+            .split(',')
+            .map(|class| FailureClass::parse(class.trim()))
+            .collect::<Result<Vec<FailureClass>, String>>()?;
+        Ok(RetryPolicy {
+            classes: Some(classes),
+        })
+    }
+
+    pub fn should_retry(&self, exit_code: i32) -> bool {
+        if exit_code == 0 {
+            return false;
+        }
+        match &self.classes {
+            Some(classes) => classes.iter().any(|class| class.matches(exit_code)),
+            None => true,
+        }
+    }
+}
+
+// the backoff stops growing after this many doublings of --retry-delay
+const MAX_BACKOFF_DOUBLINGS: u32 = 10;
+
+// --retry-delay doubled for every attempt already made, `attempts` counts the first run
+pub fn backoff(delay: Duration, attempts: u32) -> Duration {
+    delay.saturating_mul(1 << attempts.saturating_sub(1).min(MAX_BACKOFF_DOUBLINGS))
+}
+
+// how many times `--reconnect rerun` starts a command again after losing the connection
+pub const MAX_RECONNECTS: u32 = 3;
+
+// what --reconnect does when ssh lost the connection; plain ssh cannot pick a session
+// back up, so the command is either run again or the loss is only reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReconnectPolicy {
+    Rerun,
+    Report,
+}
+
+impl ReconnectPolicy {
+    pub fn from_spec(spec: &str) -> Result<ReconnectPolicy, String> {
+        match spec {
+            "rerun" => Ok(ReconnectPolicy::Rerun),
+            "report" => Ok(ReconnectPolicy::Report),
+            _ => Err(format!(
+                "unknown policy `{}` (expected rerun or report)",
+                spec
+            )),
+        }
+    }
+}
+
+// what ssh(1) prints when an established connection goes away
+const CONNECTION_DROPPED: [&str; 5] = [
+    "closed by remote host",
+    "Broken pipe",
+    "Connection reset",
+    "not responding",
+    "client_loop: send disconnect",
+];
+
+// ssh exits 255 when it could not connect, when the connection dropped mid-run, and when
+// the remote command itself exits 255; only a host that sent output before ssh reported
+// the drop on stderr lost its connection. Anything else goes on to --retries and
+// --retry-on like any failure; reruns for lost connections are attempts too and use up
+// the --retries budget
+pub fn connection_lost(exit_code: i32, sent_output: bool, stderr_tail: &[u8]) -> bool {
+    let stderr_tail = String::from_utf8_lossy(stderr_tail);
+    exit_code == SSH_CONNECT_FAILURE
+        && sent_output
+        && CONNECTION_DROPPED
+            .iter()
+            .any(|message| stderr_tail.contains(message))
+}
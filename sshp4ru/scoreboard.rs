@@ -0,0 +1,134 @@
+use crate::utils::generate_seed;
+use crate::{CpState, Host};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// the success rate only looks at this many of the latest runs of a host
+const SCOREBOARD_WINDOW: usize = 20;
+// fewer runs than this say too little to quarantine a host on
+const MIN_SCORED_RUNS: usize = 3;
+
+#[derive(Debug, Default)]
+struct Score {
+    // oldest first
+    recent: Vec<bool>,
+    // seconds since the epoch, when the host last ran
+    last_run: u64,
+}
+
+// the latest results of every host seen across runs
+#[derive(Debug, Default)]
+pub struct Scoreboard {
+    hosts: BTreeMap<String, Score>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+impl Scoreboard {
+    // a missing file is an empty scoreboard, the first run creates it
+    pub fn load(path: &str) -> io::Result<Scoreboard> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Scoreboard::default()),
+            Err(e) => return Err(e),
+        };
+        let scoreboard: Value = serde_json::from_slice(&data)?;
+        let hosts = scoreboard["hosts"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(host, score)| {
+                let recent = score["recent"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_bool)
+                    .collect();
+                let last_run = score["last_run"].as_u64().unwrap_or_default();
+                (host.clone(), Score { recent, last_run })
+            })
+            .collect();
+        Ok(Scoreboard { hosts })
+    }
+
+    // percentage of the recent runs that succeeded, None until there are enough of them
+    pub fn success_rate(&self, host: &str) -> Option<(f64, usize)> {
+        let recent = &self.hosts.get(host)?.recent;
+        if recent.len() < MIN_SCORED_RUNS {
+            return None;
+        }
+        let succeeded = recent.iter().filter(|&&success| success).count();
+        Some((succeeded as f64 * 100.0 / recent.len() as f64, recent.len()))
+    }
+
+    // the success rate of a host below `threshold` that ran within `period`; a quarantined
+    // host runs again once its last run is older, and stays quarantined until enough of
+    // those runs succeed
+    pub fn quarantined(
+        &self, host: &str, threshold: f64, period: Duration,
+    ) -> Option<(f64, usize)> {
+        let last_run = self.hosts.get(host)?.last_run;
+        if now_secs().saturating_sub(last_run) >= period.as_secs() {
+            return None;
+        }
+        self.success_rate(host)
+            .filter(|&(rate, _)| rate < threshold)
+    }
+
+    // hosts that were skipped or never started leave their score alone
+    pub fn record(&mut self, hosts: &[Rc<RefCell<Host>>]) {
+        for host in hosts.iter() {
+            let host = host.borrow();
+            if host.cp.skipped || !matches!(host.cp.state, CpState::Done) {
+                continue;
+            }
+            let score = self.hosts.entry(host.full_name.to_string()).or_default();
+            score.last_run = now_secs();
+            let recent = &mut score.recent;
+            recent.push(host.cp.exit_code == 0);
+            if recent.len() > SCOREBOARD_WINDOW {
+                recent.drain(..recent.len() - SCOREBOARD_WINDOW);
+            }
+        }
+    }
+
+    // written to a temporary name and renamed, an interrupted run keeps the old scores; the
+    // name is unique so concurrent runs do not write into each other's file
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let hosts: serde_json::Map<String, Value> = self
+            .hosts
+            .iter()
+            .map(|(host, score)| {
+                let score = json!({ "recent": score.recent, "last_run": score.last_run });
+                (host.clone(), score)
+            })
+            .collect();
+        let tmp_path = format!(
+            "{}.{}.{:08x}.tmp",
+            path,
+            std::process::id(),
+            generate_seed() as u32
+        );
+        let written = (|| {
+            let mut file = File::create(&tmp_path)?;
+            serde_json::to_writer_pretty(&mut file, &json!({ "hosts": hosts }))?;
+            file.write_all(b"\n")?;
+            file.sync_all()?;
+            std::fs::rename(&tmp_path, Path::new(path))
+        })();
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        written
+    }
+}
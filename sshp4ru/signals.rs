@@ -1,30 +1,31 @@
 use crate::utils::{Color, Colorize};
-use crate::CpState;
-use crate::Host;
 use libc::sigprocmask;
-use libc::{sigaction, sigemptyset, SA_RESTART, SIGINT, SIGTERM, SIGUSR1, SIG_BLOCK};
-use std::cell::RefCell;
+use libc::{sigaction, sigemptyset, SA_RESTART, SIGINT, SIGTERM, SIGUSR1, SIGWINCH, SIG_BLOCK};
+use serde_json::json;
+use std::fmt::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 use std::ptr;
-use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 
-static mut PROGRAM_CONTEXT: *const Vec<Rc<RefCell<Host>>> = ptr::null_mut();
-static mut HOSTS_LEN: usize = 0;
 static mut COLORIZE: bool = false;
+static mut STATUS_JSON: bool = false;
+static mut SORTED: bool = false;
 
 pub struct SignalHandler {
     sigint: libc::sigaction,
     sigusr1: libc::sigaction,
     sigkill: libc::sigaction,
+    sigwinch: libc::sigaction,
 
-    hosts_context: *const Vec<Rc<RefCell<Host>>>,
     hosts_len: usize,
     colorize: bool,
+    status_json: bool,
+    sorted: bool,
 }
 
 impl SignalHandler {
-    pub fn new(
-        program_ctx: *const Vec<Rc<RefCell<Host>>>, hosts_len: usize, colorize: bool,
-    ) -> SignalHandler {
+    pub fn new(hosts_len: usize, colorize: bool, status_json: bool, sorted: bool) -> SignalHandler {
         SignalHandler {
             sigint: sigaction {
                 sa_sigaction: handle_sigint_term as usize,
@@ -44,9 +45,16 @@ impl SignalHandler {
                 sa_restorer: None,
                 ..unsafe { std::mem::zeroed() }
             },
-            hosts_context: program_ctx,
+            sigwinch: sigaction {
+                sa_sigaction: handle_sigwinch as *const () as usize,
+                sa_flags: SA_RESTART,
+                sa_restorer: None,
+                ..unsafe { std::mem::zeroed() }
+            },
             hosts_len,
             colorize,
+            status_json,
+            sorted,
         }
     }
 
@@ -60,13 +68,24 @@ impl SignalHandler {
         unsafe {
             let mut set: libc::sigset_t = std::mem::zeroed();
             sigemptyset(&mut set);
-            for &signal in [SIGINT, SIGUSR1, SIGTERM].iter() {
+            for &signal in [SIGINT, SIGUSR1, SIGTERM, SIGWINCH].iter() {
                 libc::sigaddset(&mut set, signal);
             }
             sigprocmask(SIG_BLOCK, &set, ptr::null_mut());
         }
     }
 
+    // --tty only, the run loop passes resizes on to the ptys of the hosts
+    pub fn register_sigwinch(&mut self) {
+        unsafe {
+            sigemptyset(&mut self.sigwinch.sa_mask);
+            if sigaction(SIGWINCH, &self.sigwinch, ptr::null_mut()) != 0 {
+                eprintln!("register SIGWINCH");
+                std::process::exit(3);
+            }
+        }
+    }
+
     fn set_sigint(&mut self) {
         unsafe {
             sigemptyset(&mut self.sigint.sa_mask);
@@ -80,9 +99,10 @@ impl SignalHandler {
     fn set_sigusr1(&mut self) {
         unsafe {
             sigemptyset(&mut self.sigusr1.sa_mask);
-            PROGRAM_CONTEXT = self.hosts_context;
-            HOSTS_LEN = self.hosts_len;
             COLORIZE = self.colorize;
+            STATUS_JSON = self.status_json;
+            SORTED = self.sorted;
+            track_hosts(self.hosts_len);
             if sigaction(SIGUSR1, &self.sigusr1, ptr::null_mut()) != 0 {
                 eprintln!("register SIGUSR1");
                 std::process::exit(3);
@@ -102,64 +122,360 @@ impl SignalHandler {
 }
 
 extern "C" fn handle_sigint_term(_signum: i32) {
-    std::process::exit(4);
+    remove_control_dir();
+    unsafe { libc::_exit(4) };
+}
+
+// the loop wakes up from its wait and takes it with take_resized
+extern "C" fn handle_sigwinch(_signum: i32) {
+    RESIZED.store(true, Ordering::Release);
+}
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+// whether the terminal was resized since the last call
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::AcqRel)
 }
 
 extern "C" fn handle_sigusr1(_signum: i32) {
-    unsafe {
-        if !PROGRAM_CONTEXT.is_null() {
-            print_status();
-        }
+    if TRACKING.load(Ordering::Acquire) {
+        print_status();
     }
 }
 
-extern "C" fn print_status() {
-    let mut cp_ready = 0;
-    let mut cp_running = 0;
-    let mut cp_done = 0;
-
-    unsafe { assert_eq!(HOSTS_LEN, (*PROGRAM_CONTEXT).len()) };
-    let magenta = unsafe {
-        if COLORIZE {
-            Color::Magenta
+// the handler may run between any two instructions of the event loop, with a host
+// borrowed mutably, so it only reads what the loop published here and writes with write(2)
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static READY: AtomicUsize = AtomicUsize::new(0);
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+static DONE: AtomicUsize = AtomicUsize::new(0);
+static TOTAL: AtomicUsize = AtomicUsize::new(0);
+static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+// -m is at most 255, so every running host gets a slot
+const STATUS_SLOTS: usize = 256;
+const SLOT_LINE_LEN: usize = 256;
+const SLOT_KEY_LEN: usize = 64;
+
+// the status line of one running host, rendered when it was spawned (text lines end in a
+// newline, JSON objects do not); `len` is 0 while
+// the slot is free or being written
+struct StatusSlot {
+    len: AtomicUsize,
+    // spawn order, or the position of the name when sorted
+    order: AtomicUsize,
+    key: [AtomicU8; SLOT_KEY_LEN],
+    line: [AtomicU8; SLOT_LINE_LEN],
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_SLOT: StatusSlot = StatusSlot {
+    len: AtomicUsize::new(0),
+    order: AtomicUsize::new(0),
+    key: [const { AtomicU8::new(0) }; SLOT_KEY_LEN],
+    line: [const { AtomicU8::new(0) }; SLOT_LINE_LEN],
+};
+
+static SLOTS: [StatusSlot; STATUS_SLOTS] = [FREE_SLOT; STATUS_SLOTS];
+
+fn store_bytes(dest: &[AtomicU8], bytes: &[u8]) -> usize {
+    let len = bytes.len().min(dest.len());
+    for (cell, &byte) in dest.iter().zip(&bytes[..len]) {
+        cell.store(byte, Ordering::Relaxed);
+    }
+    len
+}
+
+fn color(colorize: bool) -> Color {
+    if colorize {
+        Color::Magenta
+    } else {
+        Color::White
+    }
+}
+
+// counts every host as ready, called before the first one is spawned
+fn track_hosts(total: usize) {
+    READY.store(total, Ordering::Relaxed);
+    RUNNING.store(0, Ordering::Relaxed);
+    DONE.store(0, Ordering::Relaxed);
+    TOTAL.store(total, Ordering::Relaxed);
+    SPAWNED.store(0, Ordering::Relaxed);
+    TRACKING.store(true, Ordering::Release);
+}
+
+// publishes the status line of a spawned host, returns its slot for `track_finished`
+pub(crate) fn track_running(pid: i32, name: &str) -> Option<usize> {
+    if !TRACKING.load(Ordering::Acquire) {
+        return None;
+    }
+    READY.fetch_sub(1, Ordering::Relaxed);
+    RUNNING.fetch_add(1, Ordering::Relaxed);
+
+    let (slot_index, slot) = SLOTS
+        .iter()
+        .enumerate()
+        .find(|(_, slot)| slot.len.load(Ordering::Relaxed) == 0)?;
+    let line = unsafe {
+        if STATUS_JSON {
+            json_status_line(pid, name)
         } else {
-            Color::White
+            let magenta = color(COLORIZE);
+            format!(
+                "--> pid {} {}\n",
+                pid.to_string().as_str().colorize(&magenta),
+                name.colorize(&magenta)
+            )
         }
     };
+    let order = SPAWNED.fetch_add(1, Ordering::Relaxed);
+    store_bytes(&slot.key, name.as_bytes());
+    slot.order.store(order, Ordering::Relaxed);
+    let len = store_bytes(&slot.line, line.as_bytes());
+    slot.len.store(len, Ordering::Release);
+    Some(slot_index)
+}
+
+// the name is cut short rather than the JSON, so the line stays a whole object in its slot
+fn json_status_line(pid: i32, name: &str) -> String {
+    let mut name = name;
+    loop {
+        let line = json!({ "pid": pid, "host": name }).to_string();
+        if line.len() <= SLOT_LINE_LEN {
+            return line;
+        }
+        let cut = name
+            .char_indices()
+            .next_back()
+            .map_or(0, |(index, _)| index);
+        name = &name[..cut];
+    }
+}
+
+pub(crate) fn track_finished(slot: Option<usize>) {
+    if !TRACKING.load(Ordering::Acquire) {
+        return;
+    }
+    RUNNING.fetch_sub(1, Ordering::Relaxed);
+    DONE.fetch_add(1, Ordering::Relaxed);
+    if let Some(slot) = slot {
+        SLOTS[slot].len.store(0, Ordering::Release);
+    }
+}
+
+// a host that was never run, e.g. one with a recent receipt
+pub(crate) fn track_skipped() {
+    if TRACKING.load(Ordering::Acquire) {
+        READY.fetch_sub(1, Ordering::Relaxed);
+        DONE.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-    let hosts = unsafe { &*PROGRAM_CONTEXT };
+// a finished host queued again for a retry or a reconnect
+pub(crate) fn track_requeued() {
+    if TRACKING.load(Ordering::Acquire) {
+        DONE.fetch_sub(1, Ordering::Relaxed);
+        READY.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-    for host in hosts.iter() {
-        match host.borrow().cp_status() {
-            CpState::Ready => cp_ready += 1,
-            CpState::Running => cp_running += 1,
-            CpState::Done => cp_done += 1,
+// the --control-master directory of the running run, NUL terminated; empty while there
+// is none or while it is being written
+const CONTROL_DIR_LEN: usize = 256;
+static CONTROL_DIR_SET: AtomicBool = AtomicBool::new(false);
+static CONTROL_DIR: [AtomicU8; CONTROL_DIR_LEN] = [const { AtomicU8::new(0) }; CONTROL_DIR_LEN];
+
+// paths too long for the buffer are not tracked, the directory is then left behind
+// when the run is interrupted
+pub(crate) fn track_control_dir(path: Option<&Path>) {
+    CONTROL_DIR_SET.store(false, Ordering::Release);
+    if let Some(path) = path {
+        let bytes = path.as_os_str().as_bytes();
+        if bytes.len() < CONTROL_DIR_LEN {
+            store_bytes(&CONTROL_DIR, bytes);
+            CONTROL_DIR[bytes.len()].store(0, Ordering::Relaxed);
+            CONTROL_DIR_SET.store(true, Ordering::Release);
+        }
+    }
+}
+
+// unlinks the sockets and the directory; the masters behind them exit on their own
+// once ControlPersist runs out. Only system calls: opendir(3) allocates, so the entries
+// are read with getdents64(2) into a buffer on the stack
+fn remove_control_dir() {
+    if !CONTROL_DIR_SET.load(Ordering::Acquire) {
+        return;
+    }
+    let mut path = [0u8; CONTROL_DIR_LEN];
+    for (byte, stored) in path.iter_mut().zip(CONTROL_DIR.iter()) {
+        *byte = stored.load(Ordering::Relaxed);
+    }
+    let path = path.as_ptr() as *const libc::c_char;
+    let reclen_at = std::mem::offset_of!(libc::dirent64, d_reclen);
+    let name_at = std::mem::offset_of!(libc::dirent64, d_name);
+    unsafe {
+        let fd = libc::open(path, libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC);
+        if fd >= 0 {
+            let mut entries = [0u8; 4096];
+            loop {
+                let len = libc::syscall(
+                    libc::SYS_getdents64,
+                    fd,
+                    entries.as_mut_ptr(),
+                    entries.len(),
+                );
+                if len <= 0 {
+                    break;
+                }
+                let mut offset = 0;
+                while offset < len as usize {
+                    let entry = entries.as_ptr().add(offset);
+                    let name = entry.add(name_at) as *const libc::c_char;
+                    if libc::strcmp(name, c".".as_ptr()) != 0
+                        && libc::strcmp(name, c"..".as_ptr()) != 0
+                    {
+                        libc::unlinkat(fd, name, 0);
+                    }
+                    offset += ptr::read_unaligned(entry.add(reclen_at) as *const u16) as usize;
+                }
+            }
+            libc::close(fd);
+        }
+        libc::rmdir(path);
+    }
+}
+
+// formats into a fixed buffer, nothing in the handler allocates
+struct StackWriter {
+    buf: [u8; SLOT_LINE_LEN],
+    len: usize,
+}
+
+impl StackWriter {
+    fn new() -> StackWriter {
+        StackWriter {
+            buf: [0; SLOT_LINE_LEN],
+            len: 0,
         }
     }
 
-    println!(
-        "status: {} running {}, finished {}, remaining ({} total)",
-        cp_ready.to_string().as_str().colorize(&magenta),
-        cp_running.to_string().as_str().colorize(&magenta),
-        cp_done.to_string().as_str().colorize(&magenta),
-        hosts.len().to_string().as_str().colorize(&magenta)
+    fn flush(&mut self) {
+        write_stdout(&self.buf[..self.len]);
+        self.len = 0;
+    }
+}
+
+impl Write for StackWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+fn write_stdout(mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        let n = unsafe { libc::write(1, bytes.as_ptr().cast(), bytes.len()) };
+        if n <= 0 {
+            return;
+        }
+        bytes = &bytes[n as usize..];
+    }
+}
+
+fn slot_line(slot: &StatusSlot) -> ([u8; SLOT_LINE_LEN], usize) {
+    let len = slot.len.load(Ordering::Acquire);
+    let mut line = [0; SLOT_LINE_LEN];
+    for (byte, cell) in line.iter_mut().zip(&slot.line[..len]) {
+        *byte = cell.load(Ordering::Relaxed);
+    }
+    (line, len)
+}
+
+// orders running slots by spawn order, or by name with --sorted
+fn slot_before(a: &StatusSlot, b: &StatusSlot, sorted: bool) -> bool {
+    if sorted {
+        for (x, y) in a.key.iter().zip(b.key.iter()) {
+            let (x, y) = (x.load(Ordering::Relaxed), y.load(Ordering::Relaxed));
+            if x != y {
+                return x < y;
+            }
+        }
+    }
+    a.order.load(Ordering::Relaxed) < b.order.load(Ordering::Relaxed)
+}
+
+extern "C" fn print_status() {
+    let (ready, running, done, total) = (
+        READY.load(Ordering::Relaxed),
+        RUNNING.load(Ordering::Relaxed),
+        DONE.load(Ordering::Relaxed),
+        TOTAL.load(Ordering::Relaxed),
     );
+    let (status_json, sorted, colorize) = unsafe { (STATUS_JSON, SORTED, COLORIZE) };
 
-    if cp_running > 0 {
-        println!("running processes:");
-
-        for host in hosts.iter() {
-            if let CpState::Running = host.borrow().cp_status() {
-                println!(
-                    "--> pid {} {}",
-                    host.borrow()
-                        .cp_pid()
-                        .to_string()
-                        .as_str()
-                        .colorize(&magenta),
-                    host.borrow().hostname().as_str().colorize(&magenta)
-                );
+    // running slots in the order they are printed, insertion sorted on the stack
+    let mut order = [0u8; STATUS_SLOTS];
+    let mut count = 0;
+    for (index, slot) in SLOTS.iter().enumerate() {
+        if slot.len.load(Ordering::Acquire) == 0 {
+            continue;
+        }
+        let mut pos = count;
+        while pos > 0 && slot_before(slot, &SLOTS[order[pos - 1] as usize], sorted) {
+            order[pos] = order[pos - 1];
+            pos -= 1;
+        }
+        order[pos] = index as u8;
+        count += 1;
+    }
+
+    let mut out = StackWriter::new();
+    let print_slot = |index: u8| {
+        let slot = &SLOTS[index as usize];
+        let (line, len) = slot_line(slot);
+        write_stdout(&line[..len]);
+    };
+
+    if status_json {
+        let _ = write!(
+            out,
+            "{{\"done\":{},\"ready\":{},\"running\":{},\"running_hosts\":[",
+            done, ready, running
+        );
+        out.flush();
+        // JSON slots hold one object each, joined into the running_hosts array
+        for (i, &index) in order[..count].iter().enumerate() {
+            if i > 0 {
+                write_stdout(b",");
             }
+            print_slot(index);
+        }
+        let _ = writeln!(out, "],\"total\":{}}}", total);
+        out.flush();
+        return;
+    }
+
+    // both colors are borrowed strings, as_str does not allocate for them
+    let (start, reset) = (color(colorize).as_str(), Color::Reset.as_str());
+    let _ = writeln!(
+        out,
+        "status: {s}{}{r} running {s}{}{r}, finished {s}{}{r}, remaining ({s}{}{r} total)",
+        ready,
+        running,
+        done,
+        total,
+        s = start,
+        r = reset
+    );
+    out.flush();
+
+    if count > 0 {
+        write_stdout(b"running processes:\n");
+        for &index in order[..count].iter() {
+            print_slot(index);
         }
     }
 }
@@ -0,0 +1,207 @@
+use crate::utils::{make_pipe, make_pty, make_stdin_pipe, wait_with_usage, PipeFd, ResourceUsage};
+use crate::{RuntimeError, CHILD_STACK_SIZE};
+use libc::pid_t;
+use nix::errno::Errno;
+use nix::sched;
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::{close, dup2, execvpe, setsid, Pid};
+use std::ffi::CString;
+use std::os::fd::RawFd;
+
+// the read ends of a started child's output, stdio in join mode and stdout/stderr
+// otherwise; the unused ones are -1
+#[derive(Debug)]
+pub struct SpawnedChild {
+    pub pid: pid_t,
+    pub stdout_fd: RawFd,
+    pub stderr_fd: RawFd,
+    pub stdio_fd: RawFd,
+}
+
+// how jobs are started, signalled and reaped; the run loop and the hosts only go
+// through this, so another way of running them is a new implementation
+pub trait Spawner {
+    // `argv[0]` is looked up in PATH, `stdin` is fed to the child on a pipe, without it
+    // the child keeps our stdin; `joined` gives stdout and stderr one pipe, as the output
+    // mode asks
+    fn spawn(
+        &self, argv: &[CString], joined: bool, env: &[CString], stdin: Option<&[u8]>,
+    ) -> Result<SpawnedChild, RuntimeError>;
+
+    fn signal(&self, pid: pid_t, signal: Signal) -> nix::Result<()>;
+
+    // blocks until `pid` exits, with what it used
+    fn wait(&self, pid: pid_t) -> nix::Result<(WaitStatus, ResourceUsage)>;
+}
+
+// clone(2) with a stack of its own, dup2(2) onto the pipes and execvpe(3); with `tty` the
+// joined output goes through a pty instead, in a session of its own with the pty as its
+// controlling terminal and stdin
+#[derive(Debug, Default)]
+pub struct CloneSpawner {
+    pub tty: bool,
+}
+
+impl Spawner for CloneSpawner {
+    fn spawn(
+        &self, argv: &[CString], joined: bool, env: &[CString], stdin: Option<&[u8]>,
+    ) -> Result<SpawnedChild, RuntimeError> {
+        let mut stdio_fd_pair = PipeFd::default();
+        let mut stdout_fd_pair = PipeFd::default();
+        let mut stderr_fd_pair = PipeFd::default();
+
+        // pipe creation
+        match (joined, self.tty) {
+            (true, true) => {
+                stdio_fd_pair = match make_pty() {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return Err(RuntimeError::PipeCreationError("pty".to_string()));
+                    }
+                };
+            }
+            (true, false) => {
+                stdio_fd_pair = match make_pipe() {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return Err(RuntimeError::PipeCreationError("stdio".to_string()));
+                    }
+                };
+            }
+            _ => {
+                stdout_fd_pair = match make_pipe() {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return Err(RuntimeError::PipeCreationError("stdout".to_string()));
+                    }
+                };
+                stderr_fd_pair = match make_pipe() {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return Err(RuntimeError::PipeCreationError("stderr".to_string()));
+                    }
+                };
+            }
+        }
+
+        // without one the child keeps our stdin, /dev/null
+        let stdin_read_end = match stdin {
+            Some(data) => Some(
+                make_stdin_pipe(data)
+                    .map_err(|_| RuntimeError::PipeCreationError("stdin".to_string()))?,
+            ),
+            None => None,
+        };
+
+        if joined {
+            assert_ne!(stdio_fd_pair, stdout_fd_pair);
+        } else {
+            assert_ne!(stderr_fd_pair, stdio_fd_pair);
+            assert_ne!(stdout_fd_pair, stdio_fd_pair);
+        }
+
+        let mut child_stack = vec![0u8; CHILD_STACK_SIZE];
+        match unsafe {
+            sched::clone(
+            Box::new( || {
+                if self.tty && joined {
+                    let pty = stdio_fd_pair.pipe_write_end.unwrap();
+                    let tty_error = setsid().err().or_else(|| {
+                        Errno::result(libc::ioctl(pty, libc::TIOCSCTTY as _, 0)).err()
+                    });
+                    if let Some(e) = tty_error {
+                        eprintln!("controlling tty error: {}", e);
+                        std::process::exit(3);
+                    }
+                    if stdin_read_end.is_none() {
+                        if let Err(e) = dup2(pty, 0) {
+                            eprintln!("dup2 stdin error: {}", e);
+                            std::process::exit(3);
+                        }
+                    }
+                }
+                if let Some(fd) = stdin_read_end {
+                    if let Err(e) = dup2(fd, 0) {
+                        eprintln!("dup2 stdin error: {}", e);
+                        std::process::exit(3);
+                    }
+                }
+                match joined {
+                    true => {
+                        // unwrap is safe here in both cases
+                        if let Err(e) = dup2(stdio_fd_pair.pipe_write_end.unwrap(), 1) {
+                            eprintln!("dup2 stdout error: {}", e);
+                            std::process::exit(3);
+                        }
+                        if let Err(e) = dup2(stdio_fd_pair.pipe_write_end.unwrap(), 2) {
+                            eprintln!("dup2 stderr error: {}", e);
+                            std::process::exit(3);
+                        }
+                    },
+                    false => {
+                        // newprocess 1> stdout-captured pipe's write end
+                        if let Err(e) = dup2(stdout_fd_pair.pipe_write_end.unwrap(), 1) {
+                            eprintln!("dup2 stdout error: {}", e);
+                            std::process::exit(3);
+                        }
+                        // newprocess 2> stderr-captured pipe's write end
+                        if let Err(e) = dup2(stderr_fd_pair.pipe_write_end.unwrap(), 2) {
+                            eprintln!("dup2 stderr error: {}", e);
+                            std::process::exit(3);
+                        }
+                    }
+                }
+                // replace binary with ssh command
+                let _ = execvpe(&argv[0], argv, env);
+                eprintln!("exec");
+                std::process::exit(3);
+            }),
+            child_stack.as_mut_slice(),
+            sched::CloneFlags::CLONE_FS | sched::CloneFlags::CLONE_IO,
+            None
+            )
+        } // unsafe block end
+        {
+            Ok(pid) => {
+                if let Some(fd) = stdin_read_end {
+                    if close(fd).is_err() {
+                        return Err(RuntimeError::ClosePipeError("stdin".to_string()));
+                    }
+                }
+                let mut child = SpawnedChild {
+                    pid: pid.as_raw(),
+                    stdout_fd: -1,
+                    stderr_fd: -1,
+                    stdio_fd: -1,
+                };
+                if joined {
+                    if close(stdio_fd_pair.pipe_write_end.unwrap()).is_err() {
+                        return Err(RuntimeError::ClosePipeError("stdio".to_string()));
+                    }
+                    child.stdio_fd = stdio_fd_pair.pipe_read_end.unwrap();
+                }
+                else {
+                    if close(stdout_fd_pair.pipe_write_end.unwrap()).is_err() {
+                        return Err(RuntimeError::ClosePipeError("stdout".to_string()));
+                    }
+                    if close(stderr_fd_pair.pipe_write_end.unwrap()).is_err() {
+                        return Err(RuntimeError::ClosePipeError("stderr".to_string()));
+                    }
+                    child.stdout_fd = stdout_fd_pair.pipe_read_end.unwrap();
+                    child.stderr_fd = stderr_fd_pair.pipe_read_end.unwrap();
+                }
+                Ok(child)
+            },
+            Err(_) => Err(RuntimeError::CloneProcessError),
+        }
+    }
+
+    fn signal(&self, pid: pid_t, signal: Signal) -> nix::Result<()> {
+        signal::kill(Pid::from_raw(pid), signal)
+    }
+
+    fn wait(&self, pid: pid_t) -> nix::Result<(WaitStatus, ResourceUsage)> {
+        wait_with_usage(pid)
+    }
+}
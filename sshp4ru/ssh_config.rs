@@ -0,0 +1,272 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// ssh(1) gives up on Include chains deeper than this
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// where ssh(1) reads the user's configuration from when no -F is given
+pub fn default_path() -> Option<PathBuf> {
+    home().map(|home| home.join(".ssh").join("config"))
+}
+
+fn home() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+// what the configuration sets for one host, as far as sshp4ru looks at it
+#[derive(Debug, Clone, Default)]
+pub struct HostDefaults {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_files: Vec<String>,
+    pub proxy_jump: Option<String>,
+}
+
+impl HostDefaults {
+    pub fn is_empty(&self) -> bool {
+        self.hostname.is_none()
+            && self.user.is_none()
+            && self.port.is_none()
+            && self.identity_files.is_empty()
+            && self.proxy_jump.is_none()
+    }
+}
+
+impl std::fmt::Display for HostDefaults {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(hostname) = &self.hostname {
+            settings.push(format!("HostName {}", hostname));
+        }
+        if let Some(user) = &self.user {
+            settings.push(format!("User {}", user));
+        }
+        if let Some(port) = self.port {
+            settings.push(format!("Port {}", port));
+        }
+        for file in self.identity_files.iter() {
+            settings.push(format!("IdentityFile {}", file));
+        }
+        if let Some(jump) = &self.proxy_jump {
+            settings.push(format!("ProxyJump {}", jump));
+        }
+        write!(f, "{}", settings.join(", "))
+    }
+}
+
+// the settings under a `Host` line, `Match` blocks are never taken: their criteria
+// need what only ssh knows at connect time
+#[derive(Debug)]
+struct Block {
+    // None for the lines before the first Host or Match, they apply to every host
+    patterns: Option<Vec<String>>,
+    settings: Vec<(String, String)>,
+}
+
+impl Block {
+    // any pattern matches and no negated one does
+    fn matches(&self, alias: &str) -> bool {
+        let Some(patterns) = &self.patterns else {
+            return true;
+        };
+        // ssh compares host names without regard to case
+        let alias = alias.to_ascii_lowercase();
+        let mut matched = false;
+        for pattern in patterns.iter().map(|pattern| pattern.to_ascii_lowercase()) {
+            match pattern.strip_prefix('!') {
+                Some(negated) if wildcard_match(negated, &alias) => return false,
+                Some(_) => {}
+                None => matched |= wildcard_match(&pattern, &alias),
+            }
+        }
+        matched
+    }
+}
+
+#[derive(Debug)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+impl SshConfig {
+    // `path` is --ssh-config, which has to be readable; the default file may be missing
+    pub fn load(path: Option<&str>) -> Result<Option<SshConfig>, String> {
+        let (path, given) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(None),
+            },
+        };
+        let mut config = SshConfig { blocks: Vec::new() };
+        match config.read(&path, 0) {
+            Ok(()) => Ok(Some(config)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !given => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    fn read(&mut self, path: &Path, depth: usize) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        for line in text.lines() {
+            let Some((keyword, args)) = split_line(line) else {
+                continue;
+            };
+            match keyword.as_str() {
+                "host" => self.blocks.push(Block {
+                    patterns: Some(args),
+                    settings: Vec::new(),
+                }),
+                "match" => {
+                    let all = args.len() == 1 && args[0].eq_ignore_ascii_case("all");
+                    self.blocks.push(Block {
+                        patterns: Some(if all {
+                            vec!["*".to_string()]
+                        } else {
+                            Vec::new()
+                        }),
+                        settings: Vec::new(),
+                    });
+                }
+                // unreadable includes are skipped like ssh does, the rest still applies
+                "include" if depth < MAX_INCLUDE_DEPTH => {
+                    for file in args.iter().flat_map(|arg| include_paths(arg)) {
+                        let _ = self.read(&file, depth + 1);
+                    }
+                }
+                "include" => {}
+                _ => {
+                    let value = args.join(" ");
+                    match self.blocks.last_mut() {
+                        Some(block) => block.settings.push((keyword, value)),
+                        None => self.blocks.push(Block {
+                            patterns: None,
+                            settings: vec![(keyword, value)],
+                        }),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the first value obtained for a setting is the one used, IdentityFile adds up
+    pub fn lookup(&self, alias: &str) -> HostDefaults {
+        let mut defaults = HostDefaults::default();
+        let settings = self
+            .blocks
+            .iter()
+            .filter(|block| block.matches(alias))
+            .flat_map(|block| block.settings.iter());
+        for (keyword, value) in settings {
+            match keyword.as_str() {
+                "hostname" if defaults.hostname.is_none() => {
+                    defaults.hostname = Some(value.replace("%h", alias));
+                }
+                "user" if defaults.user.is_none() => defaults.user = Some(value.clone()),
+                "port" if defaults.port.is_none() => defaults.port = value.parse().ok(),
+                "identityfile" => defaults.identity_files.push(expand_tilde(value)),
+                "proxyjump" if defaults.proxy_jump.is_none() && value != "none" => {
+                    defaults.proxy_jump = Some(value.clone());
+                }
+                _ => {}
+            }
+        }
+        defaults
+    }
+}
+
+// keyword and arguments of a line, `Keyword arg`, `Keyword=arg` and quoted arguments
+fn split_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..end].to_ascii_lowercase();
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut args = Vec::new();
+    let mut chars = rest.trim_start().chars().peekable();
+    while chars.peek().is_some() {
+        let mut arg = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            arg.extend(chars.by_ref().take_while(|&c| c != '"'));
+        } else {
+            arg.extend(chars.by_ref().take_while(|c| !c.is_whitespace()));
+        }
+        args.push(arg);
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+    Some((keyword, args))
+}
+
+fn expand_tilde(path: &str) -> String {
+    match (path.strip_prefix("~/"), home()) {
+        (Some(rest), Some(home)) => home.join(rest).display().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+// relative includes are under ~/.ssh; a wildcard in the file name picks every file of
+// the directory it matches, in order
+fn include_paths(arg: &str) -> Vec<PathBuf> {
+    let path = PathBuf::from(expand_tilde(arg));
+    let path = match (path.is_relative(), home()) {
+        (true, Some(home)) => home.join(".ssh").join(path),
+        _ => path,
+    };
+    let pattern = path.file_name().and_then(|name| name.to_str());
+    match (path.parent(), pattern) {
+        (Some(dir), Some(pattern)) if pattern.contains(['*', '?']) => {
+            let mut files: Vec<PathBuf> = fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .is_some_and(|name| wildcard_match(pattern, name))
+                })
+                .map(|entry| entry.path())
+                .collect();
+            files.sort();
+            files
+        }
+        _ => vec![path],
+    }
+}
+
+// `*` is any run of characters and `?` any one, the patterns of ssh_config(5)
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was and how much of the text it took so far
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,217 @@
+use std::io;
+use std::process::{Command, Stdio};
+
+// keywords from ssh_config(5), matched case-insensitively like ssh(1) does
+const KNOWN_OPTIONS: &[&str] = &[
+    "AddKeysToAgent",
+    "AddressFamily",
+    "BatchMode",
+    "BindAddress",
+    "BindInterface",
+    "CanonicalDomains",
+    "CanonicalizeFallbackLocal",
+    "CanonicalizeHostname",
+    "CanonicalizeMaxDots",
+    "CanonicalizePermittedCNAMEs",
+    "CASignatureAlgorithms",
+    "CertificateFile",
+    "ChannelTimeout",
+    "CheckHostIP",
+    "Ciphers",
+    "ClearAllForwardings",
+    "Compression",
+    "ConnectionAttempts",
+    "ConnectTimeout",
+    "ControlMaster",
+    "ControlPath",
+    "ControlPersist",
+    "DynamicForward",
+    "EnableEscapeCommandline",
+    "EnableSSHKeysign",
+    "EscapeChar",
+    "ExitOnForwardFailure",
+    "FingerprintHash",
+    "ForkAfterAuthentication",
+    "ForwardAgent",
+    "ForwardX11",
+    "ForwardX11Timeout",
+    "ForwardX11Trusted",
+    "GatewayPorts",
+    "GlobalKnownHostsFile",
+    "GSSAPIAuthentication",
+    "GSSAPIDelegateCredentials",
+    "HashKnownHosts",
+    "HostbasedAcceptedAlgorithms",
+    "HostbasedAuthentication",
+    "HostKeyAlgorithms",
+    "HostKeyAlias",
+    "Hostname",
+    "IdentitiesOnly",
+    "IdentityAgent",
+    "IdentityFile",
+    "IgnoreUnknown",
+    "Include",
+    "IPQoS",
+    "KbdInteractiveAuthentication",
+    "KbdInteractiveDevices",
+    "KexAlgorithms",
+    "KnownHostsCommand",
+    "LocalCommand",
+    "LocalForward",
+    "LogLevel",
+    "LogVerbose",
+    "MACs",
+    "NoHostAuthenticationForLocalhost",
+    "NumberOfPasswordPrompts",
+    "ObscureKeystrokeTiming",
+    "PasswordAuthentication",
+    "PermitLocalCommand",
+    "PermitRemoteOpen",
+    "PKCS11Provider",
+    "Port",
+    "PreferredAuthentications",
+    "ProxyCommand",
+    "ProxyJump",
+    "ProxyUseFdpass",
+    "PubkeyAcceptedAlgorithms",
+    "PubkeyAuthentication",
+    "RekeyLimit",
+    "RemoteCommand",
+    "RemoteForward",
+    "RequestTTY",
+    "RequiredRSASize",
+    "RevokedHostKeys",
+    "SecurityKeyProvider",
+    "SendEnv",
+    "ServerAliveCountMax",
+    "ServerAliveInterval",
+    "SessionType",
+    "SetEnv",
+    "StdinNull",
+    "StreamLocalBindMask",
+    "StreamLocalBindUnlink",
+    "StrictHostKeyChecking",
+    "SyslogFacility",
+    "TCPKeepAlive",
+    "Tag",
+    "Tunnel",
+    "TunnelDevice",
+    "UpdateHostKeys",
+    "User",
+    "UserKnownHostsFile",
+    "VerifyHostKeyDNS",
+    "VisualHostKey",
+    "XAuthLocation",
+];
+
+// suggestions further away than this (or a third of the key) are more confusing than helpful
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn suggest(key: &str) -> Option<&'static str> {
+    let key = key.to_lowercase();
+    let max_distance = (key.chars().count() / 3).clamp(1, MAX_SUGGESTION_DISTANCE);
+    KNOWN_OPTIONS
+        .iter()
+        .map(|known| (edit_distance(&key, &known.to_lowercase()), *known))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+// accepts `Key=Value` and `Key Value`, returns the `Key=Value` form passed on to ssh
+pub fn normalize(option: &str) -> Result<String, String> {
+    let (key, value) = match option
+        .trim()
+        .split_once(|c: char| c == '=' || c.is_whitespace())
+    {
+        Some((key, value)) if !key.is_empty() && !value.trim().is_empty() => (key, value.trim()),
+        _ => return Err(format!("expected <key>=<value>, got `{}`", option)),
+    };
+
+    if !KNOWN_OPTIONS
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(key))
+    {
+        return Err(match suggest(key) {
+            Some(known) => format!("unknown ssh option `{}`, did you mean `{}`?", key, known),
+            None => format!("unknown ssh option `{}`", key),
+        });
+    }
+    Ok(format!("{}={}", key, value))
+}
+
+// the value of `key` among options in the `Key=Value` form, keys compare like ssh
+// compares them
+pub fn find<'a>(options: &'a [String], key: &str) -> Option<&'a str> {
+    options.iter().find_map(|opt| {
+        let (name, value) = opt.split_once('=')?;
+        name.eq_ignore_ascii_case(key).then_some(value)
+    })
+}
+
+// --insecure and --strict-host-keys, shorthands for host key checking options
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HostKeyPolicy {
+    // any key is accepted and none is remembered
+    Insecure,
+    // only hosts already in known_hosts, ssh never asks
+    Strict,
+}
+
+impl HostKeyPolicy {
+    pub fn flag(self) -> &'static str {
+        match self {
+            HostKeyPolicy::Insecure => "--insecure",
+            HostKeyPolicy::Strict => "--strict-host-keys",
+        }
+    }
+
+    pub fn options(self) -> &'static [&'static str] {
+        match self {
+            HostKeyPolicy::Insecure => {
+                &["StrictHostKeyChecking=no", "UserKnownHostsFile=/dev/null"]
+            }
+            HostKeyPolicy::Strict => &["StrictHostKeyChecking=yes"],
+        }
+    }
+}
+
+// OpenSSH releases that added what a run may ask ssh for
+pub const PROXY_JUMP_SINCE: (u32, u32) = (7, 3);
+pub const CONTROL_PERSIST_SINCE: (u32, u32) = (5, 6);
+
+// `OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024` -> (9, 6)
+pub fn parse_openssh_version(banner: &str) -> Option<(u32, u32)> {
+    let version = banner.trim().strip_prefix("OpenSSH_")?;
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    let (major, minor) = version[..end].split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+// the version `<program> -V` reports, None for clients other than OpenSSH
+pub fn openssh_version(program: &str) -> io::Result<Option<(u32, u32)>> {
+    // ssh -V prints its version on stderr
+    let output = Command::new(program)
+        .arg("-V")
+        .stdin(Stdio::null())
+        .output()?;
+    Ok(parse_openssh_version(&String::from_utf8_lossy(
+        &output.stderr,
+    )))
+}
@@ -0,0 +1,113 @@
+use crate::utils::Color;
+
+// roles that make up a theme, in the form accepted by `--theme`
+const THEME_ROLES: [&str; 6] = [
+    "hostname",
+    "stdout",
+    "stderr",
+    "exit-ok",
+    "exit-fail",
+    "meta",
+];
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub(crate) hostname: Color,
+    pub(crate) stdout: Color,
+    pub(crate) stderr: Color,
+    pub(crate) exit_ok: Color,
+    pub(crate) exit_fail: Color,
+    pub(crate) meta: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            hostname: Color::Cyan,
+            stdout: Color::Green,
+            stderr: Color::Red,
+            exit_ok: Color::Green,
+            exit_fail: Color::Red,
+            meta: Color::Magenta,
+        }
+    }
+}
+
+impl Theme {
+    pub fn plain() -> Theme {
+        Theme {
+            hostname: Color::Empty,
+            stdout: Color::Empty,
+            stderr: Color::Empty,
+            exit_ok: Color::Empty,
+            exit_fail: Color::Empty,
+            meta: Color::Empty,
+        }
+    }
+
+    fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "plain" => Some(Theme::plain()),
+            // 256-color palette
+            "soft" => Some(Theme {
+                hostname: Color::Fixed(75),
+                stdout: Color::Fixed(114),
+                stderr: Color::Fixed(203),
+                exit_ok: Color::Fixed(114),
+                exit_fail: Color::Fixed(203),
+                meta: Color::Fixed(176),
+            }),
+            // truecolor palette
+            "solarized" => Some(Theme {
+                hostname: Color::Rgb(0x26, 0x8b, 0xd2),
+                stdout: Color::Rgb(0x85, 0x99, 0x00),
+                stderr: Color::Rgb(0xdc, 0x32, 0x2f),
+                exit_ok: Color::Rgb(0x85, 0x99, 0x00),
+                exit_fail: Color::Rgb(0xdc, 0x32, 0x2f),
+                meta: Color::Rgb(0xd3, 0x36, 0x82),
+            }),
+            _ => None,
+        }
+    }
+
+    // <name>[,<role>=<color>...] or just <role>=<color>[,...] on top of the default theme
+    pub fn from_spec(spec: &str) -> Result<Theme, String> {
+        let mut theme = Theme::default();
+
+        for (i, entry) in spec.split(',').map(str::trim).enumerate() {
+            match entry.split_once('=') {
+                Some((role, color)) => {
+                    let color = Color::parse(color)
+                        .ok_or_else(|| format!("unknown color `{}` for `{}`", color, role))?;
+                    match role {
+                        "hostname" => theme.hostname = color,
+                        "stdout" => theme.stdout = color,
+                        "stderr" => theme.stderr = color,
+                        "exit-ok" => theme.exit_ok = color,
+                        "exit-fail" => theme.exit_fail = color,
+                        "meta" => theme.meta = color,
+                        _ => {
+                            return Err(format!(
+                                "unknown role `{}` (expected one of: {})",
+                                role,
+                                THEME_ROLES.join(", ")
+                            ))
+                        }
+                    }
+                }
+                None if i == 0 => {
+                    theme = Theme::builtin(entry).ok_or_else(|| {
+                        format!(
+                            "unknown theme `{}` (expected default, plain, soft or solarized)",
+                            entry
+                        )
+                    })?
+                }
+                None => return Err(format!("expected <role>=<color>, got `{}`", entry)),
+            }
+        }
+
+        Ok(theme)
+    }
+}
@@ -1,21 +1,37 @@
-use crate::Host;
-use crate::{PROG_FULL_NAME, PROG_LICENSE, PROG_NAME, PROG_SOURCE, PROG_VERSION};
+#[cfg(feature = "cli")]
+use crate::fdwatcher::Fdwatcher;
+use crate::theme::Theme;
+use crate::PROG_NAME;
+use crate::{Host, RuntimeError};
+#[cfg(feature = "cli")]
+use crate::{PROG_FULL_NAME, PROG_LICENSE, PROG_SOURCE, PROG_VERSION};
+#[cfg(feature = "cli")]
 use chrono::prelude::*;
-use nix::fcntl::OFlag;
-use nix::unistd::pipe2;
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, Flock, FlockArg, OFlag};
+use nix::pty::{openpty, Winsize};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::{pipe2, Pid};
 use rand::rngs::OsRng;
 use rand::Rng;
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, IntoRawFd, RawFd};
 use std::rc::Rc;
-use std::time::SystemTime;
-use std::{
-    io::{self, Write},
-    os::fd::{IntoRawFd, RawFd},
-};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+// set once a write to stdout fails with EPIPE, nothing more is written to it after
+static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
 
 #[allow(unused)]
+#[derive(Debug, Clone)]
 pub enum Color {
     Black,
+    Bold,
     Blue,
     Cyan,
     Green,
@@ -24,22 +40,53 @@ pub enum Color {
     Reset,
     White,
     Yellow,
+    Fixed(u8),
+    Rgb(u8, u8, u8),
     Empty,
 }
 
 impl Color {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> Cow<'static, str> {
         match self {
-            Color::Black => "\x1b[030m",
-            Color::Blue => "\x1b[034m",
-            Color::Cyan => "\x1b[036m",
-            Color::Green => "\x1b[032m",
-            Color::Magenta => "\x1b[035m",
-            Color::Red => "\x1b[031m",
-            Color::Reset => "\x1b[0m",
-            Color::White => "\x1b[037m",
-            Color::Yellow => "\x1b[033m",
-            Color::Empty => "",
+            Color::Black => Cow::Borrowed("\x1b[030m"),
+            Color::Bold => Cow::Borrowed("\x1b[1m"),
+            Color::Blue => Cow::Borrowed("\x1b[034m"),
+            Color::Cyan => Cow::Borrowed("\x1b[036m"),
+            Color::Green => Cow::Borrowed("\x1b[032m"),
+            Color::Magenta => Cow::Borrowed("\x1b[035m"),
+            Color::Red => Cow::Borrowed("\x1b[031m"),
+            Color::Reset => Cow::Borrowed("\x1b[0m"),
+            Color::White => Cow::Borrowed("\x1b[037m"),
+            Color::Yellow => Cow::Borrowed("\x1b[033m"),
+            Color::Fixed(n) => Cow::Owned(format!("\x1b[38;5;{}m", n)),
+            Color::Rgb(r, g, b) => Cow::Owned(format!("\x1b[38;2;{};{};{}m", r, g, b)),
+            Color::Empty => Cow::Borrowed(""),
+        }
+    }
+
+    // named colors, 256-color palette indices (0-255) and truecolor #rrggbb values
+    pub fn parse(s: &str) -> Option<Color> {
+        match s {
+            "black" => Some(Color::Black),
+            "blue" => Some(Color::Blue),
+            "cyan" => Some(Color::Cyan),
+            "green" => Some(Color::Green),
+            "magenta" => Some(Color::Magenta),
+            "red" => Some(Color::Red),
+            "white" => Some(Color::White),
+            "yellow" => Some(Color::Yellow),
+            "none" => Some(Color::Empty),
+            _ => {
+                if let Some(hex) = s.strip_prefix('#') {
+                    if hex.len() != 6 {
+                        return None;
+                    }
+                    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+                    Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+                } else {
+                    s.parse().ok().map(Color::Fixed)
+                }
+            }
         }
     }
 }
@@ -80,16 +127,201 @@ pub fn make_pipe() -> Result<PipeFd, nix::Error> {
     })
 }
 
+// --tty: a pty in place of the joined pipe, the master is the non-blocking read end and
+// the slave the write end, the child's controlling terminal; it starts with the size of
+// our terminal when there is one
+pub fn make_pty() -> Result<PipeFd, nix::Error> {
+    let pty = openpty(window_size().as_ref(), None)?;
+    let (master, slave) = (pty.master.into_raw_fd(), pty.slave.into_raw_fd());
+    fcntl(master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    for fd in [master, slave] {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    }
+    Ok(PipeFd {
+        pipe_read_end: Some(master),
+        pipe_write_end: Some(slave),
+    })
+}
+
+// the size of the terminal on stdout or stderr, stdin is /dev/null while running
+pub fn window_size() -> Option<Winsize> {
+    [libc::STDOUT_FILENO, libc::STDERR_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut size: Winsize = unsafe { std::mem::zeroed() };
+            (unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0).then_some(size)
+        })
+}
+
+pub fn set_window_size(fd: RawFd, size: &Winsize) -> nix::Result<()> {
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, size) }).map(drop)
+}
+
+// the children only read what fits a pipe, so writing it up front cannot block
+pub const MAX_CHILD_STDIN: usize = 64 * 1024;
+
+// a pipe already holding `data` with its write end closed, the read end becomes a
+// child's stdin; Errno::EAGAIN when the pipe is smaller than `data`
+pub fn make_stdin_pipe(data: &[u8]) -> Result<RawFd, nix::Error> {
+    let (pipe_read_end, pipe_write_end) = pipe2(OFlag::O_CLOEXEC)?;
+    // only the write end, the child reads its stdin blocking
+    fcntl(
+        pipe_write_end.as_raw_fd(),
+        FcntlArg::F_SETFL(OFlag::O_NONBLOCK),
+    )?;
+    let mut written = 0;
+    while written < data.len() {
+        written += nix::unistd::write(&pipe_write_end, &data[written..])?;
+    }
+    Ok(pipe_read_end.into_raw_fd())
+}
+
+pub fn acquire_lock(path: &str, wait: bool) -> Result<Flock<File>, RuntimeError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| RuntimeError::LockError(path.to_string(), e))?;
+
+    // the lock is released by the kernel when the returned guard is dropped or the process exits
+    let arg = if wait {
+        FlockArg::LockExclusive
+    } else {
+        FlockArg::LockExclusiveNonblock
+    };
+    match Flock::lock(file, arg) {
+        Ok(lock) => Ok(lock),
+        Err((_, Errno::EWOULDBLOCK)) => Err(RuntimeError::LockHeld(path.to_string())),
+        Err((_, e)) => Err(RuntimeError::LockError(
+            path.to_string(),
+            io::Error::from(e),
+        )),
+    }
+}
+
+// soft RLIMIT_NOFILE, the number of fds this process may have open
+pub fn open_files_limit() -> io::Result<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(limit.rlim_cur)
+}
+
+// name of the machine running sshp4ru, None if it cannot be read
+pub fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    Some(String::from_utf8_lossy(&buffer[..len]).into_owned())
+}
+
+// MemAvailable in bytes, None where there is no /proc/meminfo
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+// CPU time and memory of reaped children, as wait4(2) reports them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResourceUsage {
+    pub user_ms: u64,
+    pub system_ms: u64,
+    pub max_rss_kb: u64,
+}
+
+impl ResourceUsage {
+    // CPU time adds up, memory is the peak of any single child
+    pub fn add(&mut self, other: &ResourceUsage) {
+        self.user_ms += other.user_ms;
+        self.system_ms += other.system_ms;
+        self.max_rss_kb = self.max_rss_kb.max(other.max_rss_kb);
+    }
+
+    pub fn cpu_ms(&self) -> u64 {
+        self.user_ms + self.system_ms
+    }
+}
+
+fn timeval_ms(time: &libc::timeval) -> u64 {
+    time.tv_sec as u64 * 1000 + time.tv_usec as u64 / 1000
+}
+
+// waitpid(2) that also returns what the child used, nix has no wait4 wrapper
+pub fn wait_with_usage(pid: libc::pid_t) -> nix::Result<(WaitStatus, ResourceUsage)> {
+    let mut status: libc::c_int = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let reaped = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+    let reaped = Errno::result(reaped)?;
+    // ru_maxrss is in bytes on macOS, in kilobytes everywhere else
+    let max_rss_kb = if cfg!(target_os = "macos") {
+        usage.ru_maxrss as u64 / 1024
+    } else {
+        usage.ru_maxrss as u64
+    };
+    Ok((
+        WaitStatus::from_raw(Pid::from_raw(reaped), status)?,
+        ResourceUsage {
+            user_ms: timeval_ms(&usage.ru_utime),
+            system_ms: timeval_ms(&usage.ru_stime),
+            max_rss_kb,
+        },
+    ))
+}
+
+#[cfg(feature = "cli")]
+// probed rather than taken from the build, the watcher falls back to poll(2) where the
+// native interface cannot be set up
+pub(crate) fn fdwatcher_interface() -> &'static str {
+    Fdwatcher::new().map_or("none", |watcher| watcher.interface())
+}
+
+#[cfg(feature = "cli")]
+fn enabled_features() -> Vec<&'static str> {
+    let mut features = vec!["cli"];
+    if cfg!(feature = "cloud") {
+        features.push("cloud");
+    }
+    if cfg!(feature = "otlp") {
+        features.push("otlp");
+    }
+    features
+}
+
+#[cfg(feature = "cli")]
+pub fn print_version_json<T: Write>(out: T) -> io::Result<()> {
+    let mut handle = io::BufWriter::new(out);
+    let info = serde_json::json!({
+        "name": PROG_NAME,
+        "version": PROG_VERSION,
+        "git_commit": env!("SSHP4RU_GIT_COMMIT"),
+        "build_date": env!("SSHP4RU_BUILD_DATE"),
+        "features": enabled_features(),
+        "fdwatcher": fdwatcher_interface(),
+        "target_os": std::env::consts::OS,
+        "target_arch": std::env::consts::ARCH,
+    });
+    writeln!(handle, "{}", info)?;
+    handle.flush()
+}
+
+#[cfg(feature = "cli")]
 pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
     let mut handle = io::BufWriter::new(out);
     let datetime = Local::now();
     let date = datetime.format("%Y-%m-%d").to_string();
     let time = datetime.format("%H:%M:%S").to_string();
-    let fdwatcher_interface = if cfg!(feature = "USE_KQUEUE") {
-        "kqueue"
-    } else {
-        "epoll"
-    };
+    let fdwatcher_interface = fdwatcher_interface();
 
     let colorize = |s: &str, col: &Color| -> String {
         if c == "auto" || c == "on" {
@@ -167,7 +399,40 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
     writeln!(
         handle,
         "    {1} {0}",
-        colorize("[-m maxjobs] [-f file] command ...", &green),
+        colorize("[-m maxjobs] [-f file] [--] command ...", &green),
+        colorize(PROG_NAME, &green)
+    )?;
+    writeln!(
+        handle,
+        "    {1} {0}",
+        colorize("doctor [-m maxjobs] [-f file]", &green),
+        colorize(PROG_NAME, &green)
+    )?;
+    writeln!(
+        handle,
+        "    {1} {0}",
+        colorize(
+            "diff-reports [--regression-threshold <percent>] <old.json> <new.json>",
+            &green
+        ),
+        colorize(PROG_NAME, &green)
+    )?;
+    writeln!(
+        handle,
+        "    {1} {0}",
+        colorize(
+            "merge-reports [--report <path>] <a.json> <b.json> ...",
+            &green
+        ),
+        colorize(PROG_NAME, &green)
+    )?;
+    writeln!(
+        handle,
+        "    {1} {0}",
+        colorize(
+            "serve [--socket <path>] [--listen <addr:port> [--listen-token-file <file>]] [--watch-hosts <file>] [options]",
+            &green
+        ),
         colorize(PROG_NAME, &green)
     )?;
     writeln!(handle)?; // Empty line
@@ -214,6 +479,20 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
     )?;
     writeln!(handle)?; // Empty line
 
+    writeln!(
+        handle,
+        "    Print a string with two spaces on every host. The command words are joined
+    with spaces for the remote shell to split again, like ssh does, so quote
+    what it has to keep together.\n"
+    )?;
+    writeln!(
+        handle,
+        "      {1} {0}",
+        colorize("-f hosts.txt \"echo 'a  b'\"", &green),
+        colorize(PROG_NAME, &green)
+    )?;
+    writeln!(handle)?; // Empty line
+
     // Options
     writeln!(handle, "{}", colorize("OPTIONS:", &yellow))?;
     write!(
@@ -238,17 +517,14 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
         "  Set color output, defaults to {}.",
         colorize("auto", &green)
     )?;
-    write!(
-        handle,
-        "  {}, {}",
-        colorize("-d", &green),
-        colorize("--debug", &green)
-    )?;
+    write!(handle, "  {}", colorize("-d", &green))?;
     writeln!(
         handle,
-        "\t             Enable debug info, defaults to {}.",
+        "\t\t             Enable all debug info, defaults to {}.",
         colorize("false", &green)
     )?;
+    write!(handle, "      {}", colorize("--debug <categories>", &green))?;
+    writeln!(handle, "   Debug info for scheduler,io,ssh,signals or all.")?;
     write!(
         handle,
         "  {}, {}",
@@ -268,9 +544,70 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
     )?;
     writeln!(
         handle,
-        "\t     A file of hosts separated by newlines, defaults to {}.",
+        "\t     A file (or directory) of hosts separated by newlines, repeatable, defaults to {}.",
         colorize("stdin", &green)
     )?;
+    write!(handle, "      {}", colorize("--lenient", &green))?;
+    writeln!(
+        handle,
+        "              Skip malformed host lines with a warning."
+    )?;
+    write!(handle, "  {}", colorize("--from-etc-hosts <glob>", &green))?;
+    writeln!(
+        handle,
+        "    Add the names in /etc/hosts ({}) matching {} ({} for all).",
+        colorize("$SSHP4RU_ETC_HOSTS", &green),
+        colorize("<glob>", &green),
+        colorize("'*'", &green)
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--from-known-hosts <glob>", &green)
+    )?;
+    writeln!(
+        handle,
+        "  Add the hosts in ~/.ssh/known_hosts matching {}, hashed ones are skipped.",
+        colorize("<glob>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--dns-srv <name>", &green))?;
+    writeln!(
+        handle,
+        "           Add the targets of the SRV records of {}, repeatable.",
+        colorize("<name>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--consul-service <name>", &green))?;
+    writeln!(
+        handle,
+        "    Add the healthy instances of a Consul service (at {}).",
+        colorize("$CONSUL_HTTP_ADDR", &green)
+    )?;
+    if cfg!(feature = "cloud") {
+        write!(handle, "  {}", colorize("--cloud <provider>", &green))?;
+        writeln!(
+            handle,
+            "         Add the running instances of a provider, {} or a {} program.",
+            colorize("aws", &green),
+            colorize("sshp4ru-cloud-<provider>", &green)
+        )?;
+        write!(handle, "  {}", colorize("--cloud-filter <filter>", &green))?;
+        writeln!(handle, "    Only instances matching it, repeatable.")?;
+        write!(handle, "  {}", colorize("--cloud-address <kind>", &green))?;
+        writeln!(
+            handle,
+            "     Connect to the {} or {} address, defaults to {}.",
+            colorize("public", &green),
+            colorize("private", &green),
+            colorize("private", &green)
+        )?;
+        write!(handle, "  {}", colorize("--aws-filter <filter>", &green))?;
+        writeln!(
+            handle,
+            "      Same as {} with a filter like {}.",
+            colorize("--cloud aws", &green),
+            colorize("tag:role=web", &green)
+        )?;
+    }
     write!(
         handle,
         "  {}, {}",
@@ -308,7 +645,8 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
     )?;
     writeln!(
         handle,
-        "\t     Max processes to run concurrently, defaults to {}.",
+        "\t     Max processes to run concurrently or {}, defaults to {}.",
+        colorize("auto", &green),
         colorize("50", &green)
     )?;
     write!(
@@ -349,7 +687,11 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
         colorize("-v", &green),
         colorize("--version", &green)
     )?;
-    writeln!(handle, "\t             Print the version number and exit.")?;
+    writeln!(
+        handle,
+        "\t             Print the version number and exit ({} for build info as JSON).",
+        colorize("--json", &green)
+    )?;
     write!(
         handle,
         "  {}, {}",
@@ -358,8 +700,96 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
     )?;
     writeln!(
         handle,
-        "          Program to execute, defaults to {}.",
-        colorize("ssh", &green)
+        "          Program to execute, defaults to {}; split like sh, fills in {} and gets the command as arguments.",
+        colorize("ssh", &green),
+        colorize("{host}", &green)
+    )?;
+    write!(handle, "  {}", colorize("--control-master", &green))?;
+    writeln!(
+        handle,
+        "           Share connections per host through sockets in a private directory."
+    )?;
+    write!(handle, "  {}", colorize("--mux", &green))?;
+    writeln!(
+        handle,
+        "                      Like {}, opening the connections before the run.",
+        colorize("--control-master", &green)
+    )?;
+    write!(handle, "  {}", colorize("--ssh-path <path>", &green))?;
+    writeln!(
+        handle,
+        "          The ssh binary, checked up front when ProxyJump or ControlPersist is used."
+    )?;
+    write!(handle, "  {}", colorize("--ssh-config <file>", &green))?;
+    writeln!(
+        handle,
+        "        Read {} instead of ~/.ssh/config, also passed to ssh as {}.",
+        colorize("<file>", &green),
+        colorize("-F", &green)
+    )?;
+    write!(handle, "  {}", colorize("--parse-json", &green))?;
+    writeln!(
+        handle,
+        "               Group hosts by their output parsed as JSON (with {}).",
+        colorize("-j", &green)
+    )?;
+    write!(handle, "  {}", colorize("--field <path>", &green))?;
+    writeln!(
+        handle,
+        "             Group by this field instead, e.g. {} or {}.",
+        colorize(".kernel.version", &green),
+        colorize(".disks[0]", &green)
+    )?;
+    write!(handle, "  {}", colorize("--aggregate <fn>", &green))?;
+    writeln!(
+        handle,
+        "           Print the {}, {}, {} or {} of the numbers hosts print (with {}).",
+        colorize("sum", &green),
+        colorize("min", &green),
+        colorize("max", &green),
+        colorize("avg", &green),
+        colorize("-j", &green)
+    )?;
+    write!(handle, "  {}", colorize("--fail-on-stderr", &green))?;
+    writeln!(
+        handle,
+        "           Fail a host that wrote to stderr, even when it exited 0."
+    )?;
+    write!(handle, "  {}", colorize("--kill-on-output <regex>", &green))?;
+    writeln!(
+        handle,
+        "   Terminate a host and fail it once a line of its output matches."
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--abort-on-output <regex>", &green)
+    )?;
+    writeln!(
+        handle,
+        "  Stop the whole run, killing every host, once any host prints a match."
+    )?;
+    write!(handle, "  {}", colorize("--on-epipe <policy>", &green))?;
+    writeln!(
+        handle,
+        "        When stdout is closed, {} the running jobs or {} without output.",
+        colorize("stop", &green),
+        colorize("continue", &green)
+    )?;
+    write!(handle, "  {}", colorize("--max-lines <num>", &green))?;
+    writeln!(
+        handle,
+        "          Stop printing a host after <num> lines (in line mode), still reading the rest."
+    )?;
+    write!(handle, "  {}", colorize("--atomic-lines <num>", &green))?;
+    writeln!(
+        handle,
+        "       Print a host's lines in blocks of <num>, not split by other hosts (in line mode)."
+    )?;
+    write!(handle, "  {}", colorize("--atomic-until <regex>", &green))?;
+    writeln!(
+        handle,
+        "     Hold back a host's lines until one matches, then print them together."
     )?;
     write!(handle, "  {} ", colorize("--max-line-length <num>", &green))?;
     writeln!(
@@ -377,6 +807,476 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
         " Maximum output length (in join mode), defaults to {}.",
         colorize("8192", &green)
     )?;
+    write!(handle, "  {}", colorize("--lock <path>", &green))?;
+    writeln!(
+        handle,
+        "              Take an exclusive lock on {} before executing.",
+        colorize("<path>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--lock-wait", &green))?;
+    writeln!(
+        handle,
+        "                Wait for the lock instead of failing if it is held."
+    )?;
+    write!(handle, "  {}", colorize("--serialize-per-host", &green))?;
+    writeln!(
+        handle,
+        "       Never run jobs for the same host concurrently."
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--total-output-limit <soft>[,<hard>]", &green)
+    )?;
+    writeln!(
+        handle,
+        "\n                             Stop buffering output past {} bytes, kill all jobs past {}.",
+        colorize("<soft>", &green),
+        colorize("<hard>", &green)
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--per-host-buffer-limit <bytes>[,<keep>]", &green)
+    )?;
+    writeln!(
+        handle,
+        "\n                             Buffer at most {} per host (join, {}), keep {}, {} or {}.",
+        colorize("<bytes>", &green),
+        colorize("--buffered", &green),
+        colorize("head", &green),
+        colorize("tail", &green),
+        colorize("head+tail", &green)
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--on-remote-failure <cmd>", &green)
+    )?;
+    writeln!(
+        handle,
+        "  Run {} on hosts that failed and add its output to the {}.",
+        colorize("<cmd>", &green),
+        colorize("--report", &green)
+    )?;
+    write!(handle, "  {}", colorize("--map-exit <code>:<cmd>", &green))?;
+    writeln!(
+        handle,
+        "    Run {} on hosts that exited with {}, or here with {}.",
+        colorize("<cmd>", &green),
+        colorize("<code>", &green),
+        colorize("local:<cmd>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--collect <glob>", &green))?;
+    writeln!(
+        handle,
+        "           Fetch the remote files matching {} from hosts that succeeded.",
+        colorize("<glob>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--collect-dir <dir>", &green))?;
+    writeln!(
+        handle,
+        "        Put them in {}, one directory per host.",
+        colorize("<dir>/<host>/", &green)
+    )?;
+    write!(handle, "  {}", colorize("--output-dir <dir>", &green))?;
+    writeln!(
+        handle,
+        "         Also write each host's output to {} and {}.",
+        colorize("<dir>/<host>.out", &green),
+        colorize(".err", &green)
+    )?;
+    write!(handle, "  {}", colorize("--retries <n>", &green))?;
+    writeln!(
+        handle,
+        "              Re-run failed hosts up to {} more times.",
+        colorize("<n>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--retry-delay <dur>", &green))?;
+    writeln!(
+        handle,
+        "        Wait {} before the first retry, doubling it for each one after.",
+        colorize("<dur>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--retry-on <classes>", &green))?;
+    writeln!(
+        handle,
+        "       Only retry these failures ({}).",
+        colorize("connect,timeout,exit=<code>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--command-file <path>", &green))?;
+    writeln!(
+        handle,
+        "      Run the script in {} instead of a command, passed to {}.",
+        colorize("<path>", &green),
+        colorize("sh -c", &green)
+    )?;
+    write!(handle, "  {}", colorize("--command-via-stdin", &green))?;
+    writeln!(
+        handle,
+        "        Send the {} script on stdin to its #! interpreter (or {}).",
+        colorize("--command-file", &green),
+        colorize("sh -s", &green)
+    )?;
+    write!(handle, "  {}", colorize("--stdin-string <text>", &green))?;
+    writeln!(
+        handle,
+        "      Write {} and a newline to the stdin of every command.",
+        colorize("<text>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--remote-timeout <secs>", &green))?;
+    writeln!(
+        handle,
+        "    Kill the command on the remote host after {} (needs {}).",
+        colorize("<secs>", &green),
+        colorize("timeout", &green)
+    )?;
+    write!(handle, "  {}", colorize("--reconnect <policy>", &green))?;
+    writeln!(
+        handle,
+        "       When ssh loses the connection, {} the command or only {} it.",
+        colorize("rerun", &green),
+        colorize("report", &green)
+    )?;
+    writeln!(
+        handle,
+        "                             A host lost it when it sent output before ssh exited 255"
+    )?;
+    writeln!(
+        handle,
+        "                             reporting the drop; reruns count against {}.",
+        colorize("--retries", &green)
+    )?;
+    write!(handle, "  {}", colorize("--per-jump-limit <n>", &green))?;
+    writeln!(
+        handle,
+        "       Max concurrent jobs per ProxyJump host ({} metadata or {}).",
+        colorize("jump=", &green),
+        colorize("-o ProxyJump", &green)
+    )?;
+    write!(handle, "  {}", colorize("--align", &green))?;
+    writeln!(
+        handle,
+        "                    Pad hostname prefixes to the same width (in line mode)."
+    )?;
+    write!(handle, "  {}", colorize("--mark-streams", &green))?;
+    writeln!(
+        handle,
+        "             Prefix lines with {} or {} (in line mode).",
+        colorize("O|", &green),
+        colorize("E|", &green)
+    )?;
+    write!(handle, "  {}", colorize("--json-lines", &green))?;
+    writeln!(
+        handle,
+        "               Print every line as a JSON object with its host and stream."
+    )?;
+    write!(handle, "  {}", colorize("--buffered", &green))?;
+    writeln!(
+        handle,
+        "                 Print each host's output at once under its exit code (with -g)."
+    )?;
+    write!(handle, "  {}", colorize("--color-by-exit", &green))?;
+    writeln!(
+        handle,
+        "            End each host with its exit line, the [host] prefix colored by exit code."
+    )?;
+    write!(handle, "  {}", colorize("--sorted", &green))?;
+    writeln!(
+        handle,
+        "                   Sort hosts by name in {} and status listings.",
+        colorize("-d", &green)
+    )?;
+    write!(handle, "  {}", colorize("--status-format <fmt>", &green))?;
+    writeln!(
+        handle,
+        "      Print the {} status as {} (default) or {}.",
+        colorize("SIGUSR1", &green),
+        colorize("text", &green),
+        colorize("json", &green)
+    )?;
+    write!(handle, "  {}", colorize("--theme <spec>", &green))?;
+    writeln!(
+        handle,
+        "             Color theme ({}) and/or {} overrides.",
+        colorize("default|plain|soft|solarized", &green),
+        colorize("role=color", &green)
+    )?;
+    write!(handle, "  {}", colorize("--check", &green))?;
+    writeln!(
+        handle,
+        "                    Print a single Nagios-compatible status line."
+    )?;
+    write!(handle, "  {}", colorize("--check-warning <num>", &green))?;
+    writeln!(
+        handle,
+        "      Failed hosts for a WARNING status, defaults to {}.",
+        colorize("1", &green)
+    )?;
+    write!(handle, "  {}", colorize("--check-critical <num>", &green))?;
+    writeln!(
+        handle,
+        "     Failed hosts for a CRITICAL status, defaults to {}.",
+        colorize("2", &green)
+    )?;
+    write!(handle, "  {}", colorize("--audit-log <path>", &green))?;
+    writeln!(
+        handle,
+        "         Append a record of every run to {}.",
+        colorize("<path>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--confirm", &green))?;
+    writeln!(
+        handle,
+        "                  Ask on the terminal ({}) before running anything.",
+        colorize("/dev/tty", &green)
+    )?;
+    write!(handle, "  {}", colorize("--max-hosts <n>", &green))?;
+    writeln!(
+        handle,
+        "            Ask before running on more than {} hosts, defaults to {}.",
+        colorize("<n>", &green),
+        colorize("1000", &green)
+    )?;
+    write!(handle, "  {}", colorize("--yes", &green))?;
+    writeln!(
+        handle,
+        "                      Do not ask because of the host count."
+    )?;
+    write!(handle, "  {}", colorize("--events-socket <path>", &green))?;
+    writeln!(
+        handle,
+        "     Stream run events as NDJSON to the Unix socket at {}.",
+        colorize("<path>", &green)
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--limit meta.<key>=<value>", &green)
+    )?;
+    writeln!(
+        handle,
+        " Only run on hosts whose metadata matches, repeatable."
+    )?;
+    write!(handle, "  {}", colorize("--summary-by <key>", &green))?;
+    writeln!(
+        handle,
+        "         Summarize results per value of metadata {} when done.",
+        colorize("<key>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--receipts-dir <dir>", &green))?;
+    writeln!(
+        handle,
+        "       Write a JSON receipt per host to {} as each one finishes.",
+        colorize("<dir>", &green)
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--skip-if-succeeded-within <duration>", &green)
+    )?;
+    writeln!(handle)?;
+    writeln!(
+        handle,
+        "                             Skip hosts that ran the same command successfully within {}.",
+        colorize("<duration>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--deadline <duration>", &green))?;
+    writeln!(
+        handle,
+        "      Kill the remaining jobs once the run takes longer (estimated up front from {}).",
+        colorize("--receipts-dir", &green)
+    )?;
+    write!(handle, "  {}", colorize("--deadline-raise-jobs", &green))?;
+    writeln!(
+        handle,
+        "      Raise {} as far as {} allows when the estimate misses the deadline.",
+        colorize("-m", &green),
+        colorize("-m auto", &green)
+    )?;
+    write!(handle, "  {}", colorize("--notes <file>", &green))?;
+    writeln!(
+        handle,
+        "             Show the note for a failed host from {} ({}).",
+        colorize("<file>", &green),
+        colorize("<host> <note>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--priority-map <file>", &green))?;
+    writeln!(
+        handle,
+        "      Start hosts with a higher priority first ({}, or {} in hosts files).",
+        colorize("<host> <priority>", &green),
+        colorize("priority=<n>", &green)
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--failed-hosts-out <path>", &green)
+    )?;
+    writeln!(
+        handle,
+        "  Write the hosts that failed to {}, usable with {}.",
+        colorize("<path>", &green),
+        colorize("-f", &green)
+    )?;
+    write!(handle, "  {}", colorize("--report <path>", &green))?;
+    writeln!(
+        handle,
+        "            Write a JSON report of the run to {}.",
+        colorize("<path>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--interleave-groups", &green))?;
+    writeln!(
+        handle,
+        "        Start hosts round-robin across hosts files instead of file by file."
+    )?;
+    write!(handle, "  {}", colorize("--probe-latency", &green))?;
+    writeln!(
+        handle,
+        "            Time a TCP connect to every ssh port first, shown with exit codes."
+    )?;
+    write!(handle, "  {}", colorize("--record-server-info", &green))?;
+    writeln!(
+        handle,
+        "       Record every host's address and ssh banner in the {}.",
+        colorize("--report", &green)
+    )?;
+    write!(handle, "  {}", colorize("--scoreboard <file>", &green))?;
+    writeln!(
+        handle,
+        "        Keep the success rate of every host over its last 20 runs in <file>."
+    )?;
+    write!(handle, "  {}", colorize("--quarantine-below <pct>", &green))?;
+    writeln!(
+        handle,
+        "   Skip hosts below <pct>% success on the scoreboard (after 3 runs)."
+    )?;
+    write!(handle, "  {}", colorize("--quarantine-for <dur>", &green))?;
+    writeln!(
+        handle,
+        "     Run a quarantined host again once its last run is {} old, 1h by default.",
+        colorize("<dur>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--clean-env", &green))?;
+    writeln!(
+        handle,
+        "                Run ssh with only PATH, HOME, USER, LOGNAME, TERM and SSH_AUTH_SOCK."
+    )?;
+    write!(handle, "  {}", colorize("--child-env <k=v>", &green))?;
+    writeln!(
+        handle,
+        "          Set k=v in the environment of ssh, not the remote (repeatable)."
+    )?;
+    write!(handle, "  {}", colorize("--report-format <fmt>", &green))?;
+    writeln!(
+        handle,
+        "      Print the {} plan as {} (default) or {}.",
+        colorize("-n", &green),
+        colorize("text", &green),
+        colorize("json", &green)
+    )?;
+    write!(handle, "  {}", colorize("--print-config [fmt]", &green))?;
+    writeln!(
+        handle,
+        "       Print the resolved options as {} (default) or {} and exit.",
+        colorize("json", &green),
+        colorize("toml", &green)
+    )?;
+    write!(handle, "  {}", colorize("--config-from <file>", &green))?;
+    writeln!(
+        handle,
+        "       Start from the options and command in a {} file.",
+        colorize("--print-config", &green)
+    )?;
+    write!(handle, "  {}", colorize("--report-env", &green))?;
+    writeln!(
+        handle,
+        "               Record the version, options, control host and start time in the report."
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--regression-threshold <pct>", &green)
+    )?;
+    writeln!(
+        handle,
+        "\n                             {}: slower by this many percent is a regression, defaults to {}.",
+        colorize("diff-reports", &green),
+        colorize("50", &green)
+    )?;
+    write!(handle, "  {}", colorize("--compress-output <fmt>", &green))?;
+    writeln!(
+        handle,
+        "    Compress reports of 64 KiB or more with {} or {} ({} to set the size).",
+        colorize("gzip", &green),
+        colorize("zstd", &green),
+        colorize("<fmt>,<bytes>", &green)
+    )?;
+    write!(
+        handle,
+        "  {}",
+        colorize("--require-reviewed <hash>", &green)
+    )?;
+    writeln!(
+        handle,
+        "  Refuse to run unless the plan hash (see {}) matches.",
+        colorize("-n", &green)
+    )?;
+    if cfg!(feature = "otlp") {
+        write!(handle, "  {}", colorize("--otlp-endpoint <url>", &green))?;
+        writeln!(
+            handle,
+            "      Export one trace span per host to an OTLP/HTTP collector."
+        )?;
+    }
+    write!(handle, "  {}", colorize("--notify", &green))?;
+    writeln!(
+        handle,
+        "                   Ring the bell and print a summary with resource use when done."
+    )?;
+    write!(handle, "  {}", colorize("--become", &green))?;
+    writeln!(
+        handle,
+        "                   Run the command through sudo as {}.",
+        colorize("root", &green)
+    )?;
+    write!(handle, "  {}", colorize("--become-user <user>", &green))?;
+    writeln!(
+        handle,
+        "       Run the command through sudo as {}, implies --become.",
+        colorize("<user>", &green)
+    )?;
+    write!(handle, "  {}", colorize("--ask-become-pass", &green))?;
+    writeln!(
+        handle,
+        "          Ask for the sudo password once and send it to every host."
+    )?;
+    write!(handle, "  {}", colorize("--export-run-id", &green))?;
+    writeln!(
+        handle,
+        "            Export the run id as {} to the command, {} also expands to it.",
+        colorize("$SSHP4RU_RUN_ID", &green),
+        colorize("{run_id}", &green)
+    )?;
+    write!(handle, "  {}", colorize("--notify-command <cmd>", &green))?;
+    writeln!(
+        handle,
+        "     Run {} when done, with {} env vars set.",
+        colorize("<cmd>", &green),
+        colorize("SSHP4RU_*", &green)
+    )?;
+    write!(handle, "  {}", colorize("--redact <regex>", &green))?;
+    writeln!(
+        handle,
+        "           Replace matches with {} in commands and output.",
+        colorize("****", &green)
+    )?;
+    write!(handle, "  {}", colorize("--tty", &green))?;
+    writeln!(
+        handle,
+        "                      Give every host a terminal, resized with ours (needs {}).",
+        colorize("-j", &green)
+    )?;
     writeln!(handle)?; // Empty line
 
     // SSH options
@@ -391,7 +1291,30 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
         colorize("-i", &green),
         colorize("--identity <ident>", &green)
     )?;
-    writeln!(handle, "     ssh identity file to use.")?;
+    writeln!(handle, "     ssh identity file to use, repeatable.")?;
+    write!(handle, "      {}", colorize("--agent-only", &green))?;
+    writeln!(handle, "           Only use keys held by ssh-agent.")?;
+    write!(handle, "      {}", colorize("--known-hosts <file>", &green))?;
+    writeln!(handle, "   known_hosts file used by every ssh.")?;
+    write!(handle, "      {}", colorize("--learn-hostkeys", &green))?;
+    writeln!(handle, "       Add keys of unknown hosts first (asks).")?;
+    write!(handle, "      {}", colorize("--check-known-hosts", &green))?;
+    writeln!(
+        handle,
+        "    Fail up front on hosts missing from known_hosts."
+    )?;
+    write!(handle, "      {}", colorize("--accept-new", &green))?;
+    writeln!(
+        handle,
+        "           With it, let ssh add their keys instead."
+    )?;
+    write!(handle, "      {}", colorize("--insecure", &green))?;
+    writeln!(
+        handle,
+        "             Accept any host key and remember none."
+    )?;
+    write!(handle, "      {}", colorize("--strict-host-keys", &green))?;
+    writeln!(handle, "     Only connect to hosts already in known_hosts.")?;
     write!(
         handle,
         "  {}, {}",
@@ -399,20 +1322,42 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
         colorize("--login <name>", &green)
     )?;
     writeln!(handle, "         The username to login as.")?;
+    write!(
+        handle,
+        "  {}, {}",
+        colorize("-A", &green),
+        colorize("--forward-agent", &green)
+    )?;
+    writeln!(handle, "        Forward the local ssh agent to the hosts.")?;
+    write!(
+        handle,
+        "  {}, {}",
+        colorize("-J", &green),
+        colorize("--jump <host>", &green)
+    )?;
+    writeln!(
+        handle,
+        "          Reach hosts through this bastion, {} metadata overrides it.",
+        colorize("jump=", &green)
+    )?;
     write!(
         handle,
         "  {}, {}",
         colorize("-o", &green),
         colorize("--option <key=val>", &green)
     )?;
-    writeln!(handle, "     ssh option passed in key=value form.")?;
+    writeln!(handle, "     ssh option in key=value form, repeatable.")?;
     write!(
         handle,
         "  {}, {}",
         colorize("-p", &green),
         colorize("--port <port>", &green)
     )?;
-    writeln!(handle, "          The ssh port.")?;
+    writeln!(
+        handle,
+        "          The ssh port, a host's {} metadata overrides it.",
+        colorize("port=", &green)
+    )?;
     write!(
         handle,
         "  {}, {}",
@@ -432,31 +1377,198 @@ pub fn print_usage<T: Write>(out: T, c: &str) -> io::Result<()> {
     Ok(())
 }
 
-pub fn debug_hosts(hosts: &Vec<Rc<RefCell<Host>>>, colorize: bool) -> () {
+pub fn debug_hosts(hosts: &[Rc<RefCell<Host>>], theme: &Theme, sorted: bool) {
     let host_count: &str = &hosts.len().to_string();
+    let mut hosts: Vec<&Rc<RefCell<Host>>> = hosts.iter().collect();
+    if sorted {
+        hosts.sort_by(|a, b| a.borrow().name.cmp(&b.borrow().name));
+    }
 
-    let (cyan, magenta, green) = if colorize {
-        (Color::Cyan, Color::Magenta, Color::Green)
-    } else {
-        (Color::Empty, Color::Empty, Color::Empty)
-    };
-    print!(
+    let (cyan, magenta, green) = (&theme.hostname, &theme.meta, &theme.stdout);
+    out!(
         "[{}] hosts ({}): [ ",
-        PROG_NAME.colorize(&cyan),
-        host_count.colorize(&magenta)
+        PROG_NAME.colorize(cyan),
+        host_count.colorize(magenta)
     );
-    for host in hosts {
-        print!(
+    for host in hosts.iter() {
+        out!(
             "{} ",
             format!(
                 "{}{}{}",
-                "'".colorize(&green),
-                host.borrow().as_str().colorize(&green),
-                "'".colorize(&green)
+                "'".colorize(green),
+                host.borrow().as_str().colorize(green),
+                "'".colorize(green)
             )
         );
     }
-    println!("]");
+    outln!("]");
+
+    for host in hosts.iter().map(|host| host.borrow()) {
+        if !host.ssh_defaults.is_empty() {
+            outln!(
+                "[{}] ssh config for {}: {}",
+                PROG_NAME.colorize(cyan),
+                host.as_str().colorize(green),
+                host.ssh_defaults
+            );
+        }
+    }
+}
+
+pub fn stdout_closed() -> bool {
+    STDOUT_CLOSED.load(Ordering::Relaxed)
+}
+
+// a reader that went away is not an error, the output is dropped
+fn stdout_result(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            STDOUT_CLOSED.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+pub fn write_stdout(bytes: &[u8]) -> io::Result<()> {
+    if stdout_closed() {
+        return Ok(());
+    }
+    stdout_result(io::stdout().lock().write_all(bytes))
+}
+
+// what out!/outln! expand to, any other error still panics like print!/println! do
+pub fn print_stdout(args: fmt::Arguments) {
+    if stdout_closed() {
+        return;
+    }
+    if let Err(e) = stdout_result(io::stdout().lock().write_fmt(args)) {
+        panic!("failed printing to stdout: {}", e);
+    }
+}
+
+// `arg` as a shell reads it back, quoted only when it has to be
+pub fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
+    }
+}
+
+// the words of a command line the way sh(1) splits them, without any expansion: quotes
+// and backslashes keep whitespace in a word
+pub fn shell_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // only these lose the backslash between double quotes
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => word.extend(['\\', c]),
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash".to_string()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+// an argv as one line for plans, receipts and the audit log, the way it would be typed
+pub fn command_line(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// `*` matches any run of characters and `?` any one, like host patterns in ssh_config
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was and the text it is matching up to, to retry it one longer
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// <number>[ms|s|m|h|d], a bare number is seconds
+pub fn parse_duration(spec: &str) -> Result<Duration, String> {
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let (number, unit) = spec.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("`{}` is not a duration, e.g. 90s, 15m or 2h", spec))?;
+    let millis = match unit {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => {
+            return Err(format!(
+                "unknown unit `{}` (expected ms, s, m, h or d)",
+                unit
+            ))
+        }
+    };
+    Ok(Duration::from_millis(number.saturating_mul(millis)))
 }
 
 pub fn monotonic_time_ms() -> u128 {
@@ -467,6 +1579,52 @@ pub fn monotonic_time_ms() -> u128 {
     now.as_millis()
 }
 
+// UTC timestamp in RFC 3339 form, e.g. 2024-05-21T13:37:00Z
+pub fn rfc3339_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // civil date from days since the epoch (Howard Hinnant's days_from_civil inverse)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
 pub fn generate_seed() -> u64 {
     OsRng.gen()
 }
+
+// a random (version 4) UUID
+pub fn new_uuid() -> String {
+    let mut bytes: [u8; 16] = OsRng.gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
@@ -0,0 +1,39 @@
+use crate::PROG_NAME;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+
+#[derive(Debug, Clone)]
+pub struct Warning {
+    // stable name for scripts reading reports, e.g. `truncated-output`
+    pub kind: &'static str,
+    pub message: String,
+}
+
+// non-fatal problems noticed while parsing or running, printed once the run is over
+#[derive(Debug, Default)]
+pub struct Warnings {
+    list: RefCell<Vec<Warning>>,
+}
+
+impl Warnings {
+    pub fn push(&self, kind: &'static str, message: String) {
+        self.list.borrow_mut().push(Warning { kind, message });
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.list
+            .borrow()
+            .iter()
+            .map(|warning| json!({ "kind": warning.kind, "message": warning.message }))
+            .collect()
+    }
+
+    pub fn print(&self) {
+        for warning in self.list.borrow().iter() {
+            eprintln!(
+                "[{}] warning ({}): {}",
+                PROG_NAME, warning.kind, warning.message
+            );
+        }
+    }
+}
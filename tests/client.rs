@@ -0,0 +1,126 @@
+use sshp4ru::client::{Client, Event};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+// a daemon on a socket of its own, its jobs run through the ssh stand-in of the shell tests
+struct Daemon {
+    dir: PathBuf,
+    child: Child,
+}
+
+impl Daemon {
+    fn start(name: &str) -> Daemon {
+        let dir = std::env::temp_dir().join(format!("sshp4ru-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ssh = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/assets/ssh-run");
+        let path = format!(
+            "{}:{}",
+            ssh.display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+        let child = Command::new(env!("CARGO_BIN_EXE_sshp4ru"))
+            .args(["serve", "--socket"])
+            .arg(dir.join("serve.sock"))
+            .env("PATH", path)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let daemon = Daemon { dir, child };
+        for _ in 0..50 {
+            if daemon.socket().exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        daemon
+    }
+
+    fn socket(&self) -> PathBuf {
+        self.dir.join("serve.sock")
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn submit_streams_the_job_events() {
+    let daemon = Daemon::start("client-submit");
+    let client = Client::new(daemon.socket());
+
+    let events = client
+        .submit(&["host-1", "host-2"], &["echo", "hi"])
+        .unwrap()
+        .collect::<io::Result<Vec<Event>>>()
+        .unwrap();
+    assert_eq!(events.first(), Some(&Event::Accepted { job: 1, hosts: 2 }));
+    let output = events
+        .iter()
+        .filter(|event| matches!(event, Event::Output { line, .. } if line.ends_with("hi")))
+        .count();
+    assert_eq!(output, 2);
+    assert_eq!(events.last(), Some(&Event::Exit { job: 1, code: 0 }));
+
+    let code = client.submit(&["host-1"], &["exit", "3"]).unwrap().wait();
+    assert_eq!(code.unwrap(), 1);
+}
+
+#[test]
+fn subscribers_see_every_job() {
+    let daemon = Daemon::start("client-subscribe");
+    let client = Client::new(daemon.socket());
+
+    let subscription = client.subscribe().unwrap();
+    // the daemon takes the subscription on a thread of its own, give it a moment
+    thread::sleep(Duration::from_millis(200));
+    client
+        .submit(&["host-1"], &["true"])
+        .unwrap()
+        .wait()
+        .unwrap();
+    let events = subscription
+        .take(2)
+        .collect::<io::Result<Vec<Event>>>()
+        .unwrap();
+    assert_eq!(
+        events,
+        vec![
+            Event::Accepted { job: 1, hosts: 1 },
+            Event::Exit { job: 1, code: 0 }
+        ]
+    );
+}
+
+#[test]
+fn rejected_requests_start_no_job() {
+    let daemon = Daemon::start("client-rejected");
+    let client = Client::new(daemon.socket());
+
+    let error = client.submit(&[], &["true"]).unwrap().wait().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "`hosts` is empty");
+}
+
+#[test]
+fn command_words_are_never_job_options() {
+    let daemon = Daemon::start("client-options");
+    let client = Client::new(daemon.socket());
+
+    // as an option -x would run the touch here instead of ssh
+    let touched = daemon.dir.join("touched");
+    let touch = format!("touch {}", touched.display());
+    client
+        .submit(&["host-1"], &["-x", &touch, "x"])
+        .unwrap()
+        .wait()
+        .unwrap();
+    assert!(!touched.exists());
+}